@@ -0,0 +1,26 @@
+# ADMIN_KEY is an API key with the "admin" scope owned by an admin account
+curl -H "Authorization: Bearer ${ADMIN_KEY}" \
+  -X GET \
+  -v \
+  "http://127.0.0.1:8080/admin/users?search=jan&page=1&per_page=20"
+
+curl -H "Authorization: Bearer ${ADMIN_KEY}" \
+  -H "Content-Type: application/json" \
+  -X POST \
+  -d '{"users": [{"login": "customer1", "name": "Customer", "password": "Password1!", "email": "customer1@example.com"}]}' \
+  -v \
+  http://127.0.0.1:8080/admin/users
+
+# USER_ID is an "id" returned above
+curl -H "Authorization: Bearer ${ADMIN_KEY}" \
+  -X POST \
+  -v \
+  http://127.0.0.1:8080/admin/users/${USER_ID}/disable
+
+# Existing sessions and keys of the user stop working at once, "until" is unix time
+curl -H "Authorization: Bearer ${ADMIN_KEY}" \
+  -H "Content-Type: application/json" \
+  -X POST \
+  -d '{"until": 1893456000, "reason": "Spam in comments"}' \
+  -v \
+  http://127.0.0.1:8080/admin/users/${USER_ID}/ban
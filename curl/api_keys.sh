@@ -0,0 +1,13 @@
+# TOKEN is the "access" token returned by login.sh, the key is shown only once
+curl -H "Authorization: Bearer ${TOKEN}" \
+  -H "Content-Type: application/json" \
+  -X POST \
+  -d '{"name": "integration", "scopes": ["read"]}' \
+  -v \
+  http://127.0.0.1:8080/user/me/api-keys
+
+# API_KEY is the "key" returned above, accepted in place of an access token
+curl -H "Authorization: Bearer ${API_KEY}" \
+  -X GET \
+  -v \
+  http://127.0.0.1:8080/user/me
@@ -0,0 +1,11 @@
+# ADMIN_KEY is an API key with the "admin" scope owned by an admin account
+curl -H "Authorization: Bearer ${ADMIN_KEY}" \
+  -H "Content-Type: application/json" \
+  -X POST \
+  -d '{"name": "Shoes", "slug": "shoes", "position": 1}' \
+  -v \
+  http://127.0.0.1:8080/admin/categories
+
+# Whole tree, then a category with the products of it and its descendants
+curl -X GET -v http://127.0.0.1:8080/category
+curl -X GET -v "http://127.0.0.1:8080/category/shoes?page=1&per_page=20"
@@ -0,0 +1,13 @@
+# VARIANT_ID is one of the "variants" of GET /product/{id}
+curl -H "Authorization: Bearer ${TOKEN}" \
+  -H "Content-Type: application/json" \
+  -X POST \
+  -d "{\"variant_id\": \"${VARIANT_ID}\", \"quantity\": 2}" \
+  -v \
+  http://127.0.0.1:8080/basket
+
+# Needs a verified email when REQUIRE_VERIFIED_EMAIL is set
+curl -H "Authorization: Bearer ${TOKEN}" \
+  -X POST \
+  -v \
+  http://127.0.0.1:8080/order/checkout
@@ -0,0 +1,7 @@
+# REFRESH is the "refresh" token returned by signin.sh, it can be used once
+curl -d "{ \"refresh_token\": \"${REFRESH}\" }" \
+  -H 'Content-Type: application/json' \
+  -H "Accept-content: application/json" \
+  -X POST \
+  -v \
+  http://127.0.0.1:8080/user/token/refresh
@@ -0,0 +1,2 @@
+# Words, "quoted phrases", OR and -excluded words are understood
+curl -X GET -v "http://127.0.0.1:8080/product/search?q=wool%20-socks&category=shoes&min_price_cents=1000&max_price_cents=5000&min_rating=3&in_stock=true&sort=price_asc&page=1&per_page=20"
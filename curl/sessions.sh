@@ -0,0 +1,6 @@
+# TOKEN is the "access" token returned by signin.sh
+curl -H "Authorization: Bearer ${TOKEN}" \
+  -H "Accept-content: application/json" \
+  -X GET \
+  -v \
+  http://127.0.0.1:8080/user/me/sessions
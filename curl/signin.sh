@@ -1,4 +1,4 @@
-curl -d '{ "name": "aboba", "login": "boba", "password": "!@1Lovpery" }' \
+curl -d '{ "name": "aboba", "login": "boba", "password": "!@1Lovpery", "email": "boba@example.com" }' \
   -H 'Content-Type: application/json' \
   -H "Accept-content: application/json" \
   -X POST \
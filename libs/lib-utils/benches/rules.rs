@@ -0,0 +1,95 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use lib_utils::validation::{validate_rules, PasswordStrength, Rules};
+use regex::Regex;
+
+// Previous implementation, building a regex on every call, kept as a baseline.
+// Email rule had no previous implementation, so it is left out of the comparison
+fn validate_uncached(rule: &Rules, value: &str) -> Option<bool> {
+    let contains = |re: &str, must_contain: bool| Regex::new(re).unwrap().is_match(value) == must_contain;
+
+    let valid = match rule {
+        Rules::MaxLength(length) => Regex::new(&format!("^.{{0,{}}}$", length)).unwrap().is_match(value),
+        Rules::MinLength(length) => Regex::new(&format!("^.{{{},}}$", length)).unwrap().is_match(value),
+        Rules::ContainsDidgits(must_contain) => contains(r"\d", *must_contain),
+        Rules::ContainsSpecialCharacters(must_contain) => contains(r".*[@$!%*?&]", *must_contain),
+        Rules::ContainsLowecaseCharacter(must_contain) => contains(r".*[a-z]", *must_contain),
+        Rules::ContainsUppercaseCharacter(must_contain) => contains(r".*[A-Z]", *must_contain),
+        Rules::Email => return None,
+    };
+
+    Some(valid)
+}
+
+// Rules run by sign up: name, login and password
+const SIGN_UP_RULES: [(&str, &[&Rules]); 3] = [
+    ("aboba", &[&Rules::MinLength(3), &Rules::ContainsDidgits(false)]),
+    ("boba", &[&Rules::MinLength(3), &Rules::MaxLength(20)]),
+    ("!@1Lovpery", &[
+        &Rules::MinLength(8),
+        &Rules::ContainsLowecaseCharacter(true),
+        &Rules::ContainsUppercaseCharacter(true),
+        &Rules::ContainsDidgits(true),
+        &Rules::ContainsSpecialCharacters(true),
+    ]),
+];
+
+fn single_rules(c: &mut Criterion) {
+    let value = "Apanki@123".to_string();
+    let rules = [
+        Rules::MaxLength(20),
+        Rules::MinLength(3),
+        Rules::ContainsDidgits(true),
+        Rules::ContainsSpecialCharacters(true),
+    ];
+
+    let mut group = c.benchmark_group("rule");
+    for rule in &rules {
+        group.bench_with_input(BenchmarkId::new("cached", format!("{:?}", rule)), rule, |b, rule| {
+            b.iter(|| validate_rules(black_box(&value), &[rule]))
+        });
+        group.bench_with_input(BenchmarkId::new("uncached", format!("{:?}", rule)), rule, |b, rule| {
+            b.iter(|| validate_uncached(rule, black_box(&value)))
+        });
+    }
+    group.finish();
+}
+
+fn sign_up(c: &mut Criterion) {
+    let values: Vec<(String, &[&Rules])> = SIGN_UP_RULES
+        .iter()
+        .map(|(value, rules)| (value.to_string(), *rules))
+        .collect();
+
+    let mut group = c.benchmark_group("sign_up");
+    group.bench_function("cached", |b| {
+        b.iter(|| {
+            for (value, rules) in &values {
+                black_box(validate_rules(black_box(value), rules));
+            }
+        })
+    });
+    group.bench_function("uncached", |b| {
+        b.iter(|| {
+            for (value, rules) in &values {
+                for rule in rules.iter() {
+                    black_box(validate_uncached(rule, black_box(value)));
+                }
+            }
+        })
+    });
+    group.finish();
+}
+
+fn password_strength(c: &mut Criterion) {
+    let value = "c0rrect-Horse-b@ttery".to_string();
+    let rule = PasswordStrength::new(3);
+
+    c.bench_function("password_strength", |b| {
+        b.iter(|| validate_rules(black_box(&value), &[&rule]))
+    });
+}
+
+criterion_group!(benches, single_rules, sign_up, password_strength);
+criterion_main!(benches);
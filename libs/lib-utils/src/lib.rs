@@ -5,8 +5,9 @@ pub mod tests {
     extern crate proc_macro;
 
     use std::error::Error;
+    use std::collections::HashMap;
 
-    use crate::{validate, validation::{self, validate_rules, Rule, Rules, Validate}};
+    use crate::{validate, validation::{password, string_schema, validate_rules, FieldPath, JsonSchema, PasswordBlocklist, PasswordStrength, Rules, Validate, ValidateNested, ValidationErrors}};
 
     #[test]
     fn rules_validation_many_test() {
@@ -24,6 +25,16 @@ pub mod tests {
     }
 
     #[test]
+    fn length_rules_reject_newlines() {
+        let value = "Abo\nba".to_string();
+
+        assert!(Rules::MaxLength(10).validate(&value).is_err());
+        assert!(Rules::MinLength(3).validate(&value).is_err());
+        assert!(Rules::MinLength(3).validate(&"Ab\rba".to_string()).is_ok());
+    }
+
+    #[test]
+    #[allow(unused_must_use)]
     fn borrowing() {
         let str = "Zhopa".to_string();
         Rules::MaxLength(100).validate(&str);
@@ -93,4 +104,234 @@ pub mod tests {
 
         Ok(())
     }
+
+    struct Item {
+        title: String,
+        tags: Vec<String>,
+    }
+
+    impl ValidateNested<'static> for Item {
+        fn validate_nested(&self, path: &FieldPath, errors: &mut ValidationErrors<'static>) {
+            errors.check(&path.field("title"), &self.title, &[&Rules::MinLength(3)]);
+
+            for (i, tag) in self.tags.iter().enumerate() {
+                errors.check(&path.field("tags").index(i), tag, &[&Rules::ContainsDidgits(false)]);
+            }
+        }
+    }
+
+    struct Address {
+        city: String,
+    }
+
+    impl ValidateNested<'static> for Address {
+        fn validate_nested(&self, path: &FieldPath, errors: &mut ValidationErrors<'static>) {
+            errors.check(&path.field("city"), &self.city, &[&Rules::MinLength(2), &Rules::MaxLength(10)]);
+        }
+    }
+
+    struct Order {
+        items: Vec<Item>,
+        address: Option<Address>,
+        notes: HashMap<String, Item>,
+    }
+
+    impl ValidateNested<'static> for Order {
+        fn validate_nested(&self, path: &FieldPath, errors: &mut ValidationErrors<'static>) {
+            errors.nested(&path.field("items"), &self.items);
+            errors.nested(&path.field("address"), &self.address);
+            errors.nested(&path.field("notes"), &self.notes);
+        }
+    }
+
+    #[test]
+    fn nested_validation_paths() {
+        let order = Order {
+            items: vec![
+                Item { title: "Boots".to_string(), tags: vec!["winter".to_string()] },
+                Item { title: "Ok".to_string(), tags: vec!["new".to_string(), "2024".to_string()] },
+            ],
+            address: Some(Address { city: "X".to_string() }),
+            notes: HashMap::from([
+                ("gift".to_string(), Item { title: "A".to_string(), tags: vec![] }),
+            ]),
+        };
+
+        let errors = order.validate_all().expect_err("Order must not be validated");
+
+        let paths: Vec<&String> = errors.iter().map(|(path, _)| path).collect();
+        assert_eq!(paths, ["address.city", "items[1].tags[1]", "items[1].title", "notes[gift].title"]);
+        assert_eq!(errors.get("address.city").unwrap().len(), 1);
+
+        let json = serde_json::to_value(&errors).unwrap();
+        assert_eq!(json["items[1].title"][0], "Minimum length must be: 3");
+    }
+
+    #[test]
+    fn nested_validation_passes() {
+        let order = Order {
+            items: vec![Item { title: "Boots".to_string(), tags: vec![] }],
+            address: None,
+            notes: HashMap::new(),
+        };
+
+        assert!(order.validate_all().is_ok());
+        assert_eq!(FieldPath::root().field("items").index(0).field("title").to_string(), "items[0].title");
+    }
+
+    #[test]
+    fn rules_json_schema() {
+        let schema = string_schema(&[
+            &Rules::MinLength(3),
+            &Rules::MaxLength(20),
+            &Rules::ContainsDidgits(true),
+            &Rules::ContainsSpecialCharacters(false),
+        ]);
+
+        assert_eq!(schema["type"], "string");
+        assert_eq!(schema["minLength"], 3);
+        assert_eq!(schema["maxLength"], 20);
+        // Both length rules share the single line pattern, it's kept once
+        assert_eq!(schema["pattern"], r"^[^\n]*$");
+        assert_eq!(schema["allOf"][0]["pattern"], "[0-9]");
+        assert_eq!(schema["allOf"][1]["pattern"], "^[^@$!%*?&]*$");
+        assert_eq!(schema["allOf"].as_array().unwrap().len(), 2);
+
+        let schema = string_schema(&[&PasswordStrength::new(3), &PasswordBlocklist::bundled()]);
+
+        assert_eq!(schema["x-passwordStrength"]["minScore"], 3);
+        assert_eq!(schema["x-passwordBlocklist"], "Must not be a common or breached password");
+    }
+
+    #[test]
+    fn rules_json_schema_matches_validation() {
+        let rules = [
+            Rules::ContainsDidgits(true),
+            Rules::ContainsDidgits(false),
+            Rules::ContainsSpecialCharacters(true),
+            Rules::ContainsSpecialCharacters(false),
+            Rules::ContainsLowecaseCharacter(true),
+            Rules::ContainsLowecaseCharacter(false),
+            Rules::ContainsUppercaseCharacter(true),
+            Rules::ContainsUppercaseCharacter(false),
+        ];
+        let samples = ["Apanki", "apanki1", "APANKI@", "123", "a!B2"];
+
+        for rule in [Rules::MinLength(0), Rules::MaxLength(20)] {
+            let schema = rule.json_schema();
+            let pattern = regex::Regex::new(schema["pattern"].as_str().unwrap()).unwrap();
+
+            for sample in ["Apanki", "a\nb", "trailing\n"] {
+                assert_eq!(
+                    pattern.is_match(sample),
+                    rule.validate(&sample.to_string()).is_ok(),
+                    "{rule:?} disagrees with its schema on {sample:?}"
+                );
+            }
+        }
+
+        for rule in &rules {
+            let schema = rule.json_schema();
+            let pattern = regex::Regex::new(schema["pattern"].as_str().unwrap()).unwrap();
+
+            for sample in samples {
+                assert_eq!(
+                    pattern.is_match(sample),
+                    rule.validate(&sample.to_string()).is_ok(),
+                    "{rule:?} disagrees with its schema on {sample}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn password_strength_estimation() {
+        let weak = password::estimate("Aa1!");
+        let patterned = password::estimate("Poiuytrew9");
+        let strong = password::estimate("!@1Lovpery");
+
+        assert!(weak.score < 3, "{weak:?}");
+        assert!(patterned.score < 3, "{patterned:?}");
+        assert!(patterned.weaknesses.contains(&password::Weakness::KeyboardPattern));
+        assert_eq!(strong.score, password::MAX_SCORE, "{strong:?}");
+
+        assert!(password::estimate("aaaaaaaaaaaa").weaknesses.contains(&password::Weakness::Repeated));
+        assert!(password::estimate("Summer1987").weaknesses.contains(&password::Weakness::Year));
+    }
+
+    #[test]
+    fn password_strength_rule() {
+        let rule = PasswordStrength::new(3);
+
+        assert!(rule.validate(&"c0rrect-Horse-b@ttery".to_string()).is_ok());
+
+        let error = rule.validate(&"Password1!".to_string()).expect_err("Must not be validated");
+        assert!(error.to_string().contains("contains a common password"), "{error}");
+    }
+
+    #[test]
+    fn password_blocklist_rule() {
+        let bundled = PasswordBlocklist::bundled();
+        assert!(!bundled.is_empty());
+        assert!(bundled.validate(&"P@SSW0RD".to_string()).is_err());
+        assert!(bundled.validate(&"!@1Lovpery".to_string()).is_ok());
+
+        let custom = PasswordBlocklist::from_list("# leaked\nhunter2\n\n");
+        assert_eq!(custom.len(), 1);
+        assert_eq!(
+            custom.validate(&"Hunter2".to_string()).unwrap_err().to_string(),
+            "Must not be a common or breached password: found in the password blocklist"
+        );
+    }
+
+    #[test]
+    fn validate_macro() {
+        let login = "ab$".to_string();
+        let blocklist = PasswordBlocklist::from_list("ab$");
+
+        let errors = validate!(login =>
+            min_length(3),
+            max_length(20),
+            contains_special_characters(false),
+            custom(blocklist),
+        );
+        let expected = validate_rules(&login, &[&Rules::ContainsSpecialCharacters(false)]);
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].to_string(), expected[0].to_string());
+        assert!(validate!(login, Rules::MinLength(2), Rules::MaxLength(3)).is_empty());
+    }
+
+    #[test]
+    fn validate_macro_with_nested() {
+        struct Credentials {
+            login: String,
+        }
+
+        impl ValidateNested<'static> for Credentials {
+            fn validate_nested(&self, path: &FieldPath, errors: &mut ValidationErrors<'static>) {
+                errors.add(&path.field("login"), validate!(self.login => min_length(3), contains_digits(false)));
+            }
+        }
+
+        let errors = vec![Credentials { login: "a1".to_string() }]
+            .validate_all()
+            .expect_err("Must not be validated");
+
+        assert_eq!(errors.get("[0].login").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn email_rule() {
+        for valid in ["boba@shop.test", "first.last+tag@mail.example.com", "a_b@x-y.io"] {
+            assert!(Rules::Email.validate(&valid.to_string()).is_ok(), "{valid}");
+        }
+
+        let too_long = format!("{}@shop.test", "a".repeat(250));
+        for invalid in ["boba", "boba@", "@shop.test", "boba@shop", "bo ba@shop.test", "boba@-shop.test", too_long.as_str()] {
+            assert!(Rules::Email.validate(&invalid.to_string()).is_err(), "{invalid}");
+        }
+
+        assert_eq!(Rules::Email.json_schema()["format"], "email");
+    }
 }
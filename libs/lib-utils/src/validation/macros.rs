@@ -1,15 +1,42 @@
-extern crate proc_macro;
-
+// Declarative validation, borrows the value and returns the same `Box<[Error]>` as `validate_rules`:
+//
+// validate!(login => min_length(3), max_length(20), custom(CustomRules::LoginCanContain))
+//
+// Standart rule arguments must be constants. `custom` takes any rule
+// implementing `Validate` for the value type, living as long as the errors
+// (unit variants and `*STATIC` values do)
 
 #[macro_export]
 macro_rules! validate {
-    ( $val:expr, $($rule:expr),+ ) => {
+    (@rule min_length ( $n:expr )) => { &const { $crate::validation::Rules::MinLength($n) } };
+    (@rule max_length ( $n:expr )) => { &const { $crate::validation::Rules::MaxLength($n) } };
+    (@rule contains_digits ( $b:expr )) => { &const { $crate::validation::Rules::ContainsDidgits($b) } };
+    (@rule contains_special_characters ( $b:expr )) => { &const { $crate::validation::Rules::ContainsSpecialCharacters($b) } };
+    (@rule contains_lowercase ( $b:expr )) => { &const { $crate::validation::Rules::ContainsLowecaseCharacter($b) } };
+    (@rule contains_uppercase ( $b:expr )) => { &const { $crate::validation::Rules::ContainsUppercaseCharacter($b) } };
+    (@rule custom ( $rule:expr )) => { &$rule };
+    (@rule $other:ident ( $($arg:expr),* )) => {
+        compile_error!(concat!("Unknown validation rule: ", stringify!($other)))
+    };
+
+    ( $val:expr => $( $rule:ident ( $($arg:expr),* ) ),+ $(,)? ) => {
         {
-            let mut not_validated: Vec<Box<dyn std::error::Error>> = Vec::new();
+            let value = &$val;
+            let mut not_validated: Vec<$crate::validation::Error> = Vec::new();
             $(
-                $rule.validate($val).map_err( |e| { not_validated.push(Box::new(e)); } );
-            )*
-            not_validated
+                if let Err(e) = $crate::validation::Validate::validate(
+                    $crate::validate!(@rule $rule ( $($arg),* )),
+                    value
+                ) {
+                    not_validated.push(e);
+                }
+            )+
+            not_validated.into_boxed_slice()
         }
     };
+
+    // Shorthand for rule values: validate!(value, Rules::MinLength(3), CustomRules::LoginCanContain)
+    ( $val:expr, $($rule:expr),+ $(,)? ) => {
+        $crate::validate!( $val => $( custom($rule) ),+ )
+    };
 }
@@ -1,6 +1,14 @@
 pub mod macros;
+pub mod nested;
+pub mod password;
+pub mod schema;
+
+pub use nested::{FieldPath, PathSegment, ValidateNested, ValidationErrors};
+pub use password::{PasswordBlocklist, PasswordStrength};
+pub use schema::{object_schema, string_schema, JsonSchema};
 
 use std::fmt::Debug;
+use std::sync::LazyLock;
 use regex::Regex;
 
 // traits
@@ -8,7 +16,7 @@ use regex::Regex;
 pub trait Rule: std::fmt::Display + std::fmt::Debug { }
 
 pub trait Validate<V>: Rule {
-    fn validate(&self, value: &V) -> Result<&Self>;
+    fn validate(&self, value: &V) -> Result<'_, &Self>;
 }
 
 pub fn validate_rules<'a,T,U>(value: &T, rules: &[&'a U]) -> Box<[Error<'a>]>
@@ -29,6 +37,17 @@ where
 
 // Standart rules
 
+// Character classes shared by rule validation and schema export
+// ASCII only, `\d` means different things in ECMA-262 and Rust regex
+pub(crate) const DIGITS: &str = "0-9";
+pub(crate) const SPECIAL_CHARACTERS: &str = "@$!%*?&";
+pub(crate) const LOWERCASE_CHARACTERS: &str = "a-z";
+pub(crate) const UPPERCASE_CHARACTERS: &str = "A-Z";
+
+// Practical subset of RFC 5322 addresses: dotted domain, no quoted local parts
+pub(crate) const EMAIL: &str = r"^[A-Za-z0-9.!#$%&'*+/=?^_`{|}~-]+@[A-Za-z0-9](?:[A-Za-z0-9-]{0,61}[A-Za-z0-9])?(?:\.[A-Za-z0-9](?:[A-Za-z0-9-]{0,61}[A-Za-z0-9])?)+$";
+pub(crate) const EMAIL_MAX_LENGTH: usize = 254;
+
 #[derive(Debug, Clone)]
 pub enum Rules {
     MaxLength(i16),
@@ -37,6 +56,7 @@ pub enum Rules {
     ContainsSpecialCharacters(bool),
     ContainsLowecaseCharacter(bool),
     ContainsUppercaseCharacter(bool),
+    Email,
 }
 
 impl Rule for Rules { }
@@ -74,100 +94,67 @@ impl std::fmt::Display for Rules {
                     write!(f, "Must not contain uppercase characters")
                 }
             },
+            Rules::Email => { write!(f, "Must be a valid email address") },
+        }
+    }
+}
+
+// Compiled once on first use, shared by every validation afterwards
+static DIGITS_REGEX: LazyLock<Regex> = LazyLock::new(|| class_regex(DIGITS));
+static SPECIAL_CHARACTERS_REGEX: LazyLock<Regex> = LazyLock::new(|| class_regex(SPECIAL_CHARACTERS));
+static LOWERCASE_CHARACTERS_REGEX: LazyLock<Regex> = LazyLock::new(|| class_regex(LOWERCASE_CHARACTERS));
+static UPPERCASE_CHARACTERS_REGEX: LazyLock<Regex> = LazyLock::new(|| class_regex(UPPERCASE_CHARACTERS));
+static EMAIL_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(EMAIL).unwrap());
+
+fn class_regex(class: &str) -> Regex {
+    Regex::new(&format!("[{}]", class)).unwrap()
+}
+
+impl Rules {
+    fn check_contains(&self, must_contain: bool, do_contain: bool) -> Result<'_, &Self> {
+        if must_contain == do_contain {
+            Ok(self)
+        } else {
+            Err(Error::RuleNotValidated(self))
         }
     }
 }
 
 impl Validate<String> for Rules where {
-    fn validate(&self, value: &String) -> Result<&Self> {
+    fn validate(&self, value: &String) -> Result<'_, &Self> {
         match self {
+            // Newlines are rejected, same as `.` of the regexes these rules replaced
             Rules::MaxLength(length) => {
-                let re = format!("^.{{0,{}}}$",length);
-                let regex = Regex::new(&re).unwrap();
-
-                if !regex.is_match(value) {
+                if value.contains('\n') || value.chars().count() > (*length).max(0) as usize {
                     Err(Error::RuleNotValidated(self))
                 } else {
                     Ok(self)
                 }
             },
             Rules::MinLength(length) => {
-                let re = format!("^.{{{},}}$",length);
-                let regex = Regex::new(&re).unwrap();
-
-                if !regex.is_match(value) {
+                if value.contains('\n') || value.chars().count() < (*length).max(0) as usize {
                     Err(Error::RuleNotValidated(self))
                 } else {
                     Ok(self)
                 }
             },
             Rules::ContainsDidgits(must_contain) => {
-                let re = r"\d";
-                let regex = Regex::new(re).unwrap();
-                let do_contain = regex.is_match(value);
-
-                if *must_contain {
-                    if do_contain {
-                        Ok(self)
-                    } else {
-                        Err(Error::RuleNotValidated(self))
-                    }
-                } else if do_contain {
-                    Err(Error::RuleNotValidated(self))
-                } else {
-                    Ok(self)
-                }
-
+                self.check_contains(*must_contain, DIGITS_REGEX.is_match(value))
             },
             Rules::ContainsSpecialCharacters(must_contain) => {
-                let re = r".*[@$!%*?&]";
-                let regex = Regex::new(re).unwrap();
-                let do_contain = regex.is_match(value);
-
-                if *must_contain {
-                    if do_contain {
-                        Ok(self)
-                    } else {
-                        Err(Error::RuleNotValidated(self))
-                    }
-                } else if do_contain {
-                    Err(Error::RuleNotValidated(self))
-                } else {
-                    Ok(self)
-                }
+                self.check_contains(*must_contain, SPECIAL_CHARACTERS_REGEX.is_match(value))
             },
             Rules::ContainsLowecaseCharacter(must_contain) => {
-                let re = r".*[a-z]";
-                let regex = Regex::new(re).unwrap();
-                let do_contain = regex.is_match(value);
-
-                if *must_contain {
-                    if do_contain {
-                        Ok(self)
-                    } else {
-                        Err(Error::RuleNotValidated(self))
-                    }
-                } else if do_contain {
-                    Err(Error::RuleNotValidated(self))
-                } else {
-                    Ok(self)
-                }
+                self.check_contains(*must_contain, LOWERCASE_CHARACTERS_REGEX.is_match(value))
             },
             Rules::ContainsUppercaseCharacter(must_contain) => {
-                let re = r".*[A-Z]";
-                let regex = Regex::new(re).unwrap();
-                let do_contain = regex.is_match(value);
-
-                if *must_contain {
-                    if do_contain {
-                        Ok(self)
-                    } else {
-                        Err(Error::RuleNotValidated(self))
-                    }
-                } else if do_contain {
-                    Err(Error::RuleNotValidated(self))
-                } else {
+                self.check_contains(*must_contain, UPPERCASE_CHARACTERS_REGEX.is_match(value))
+            },
+            Rules::Email => {
+                if value.len() <= EMAIL_MAX_LENGTH && EMAIL_REGEX.is_match(value) {
                     Ok(self)
+                } else {
+                    Err(Error::RuleNotValidated(self))
                 }
             },
         }
@@ -180,7 +167,8 @@ pub type Result<'a,T> = std::result::Result<T,Error<'a>>;
 
 #[derive(Debug, Clone)]
 pub enum Error<'a> {
-    RuleNotValidated(&'a dyn Rule)
+    RuleNotValidated(&'a dyn Rule),
+    RuleNotValidatedBecause(&'a dyn Rule, String),
 }
 
 impl<'a> std::fmt::Display for Error<'a> {
@@ -189,7 +177,8 @@ impl<'a> std::fmt::Display for Error<'a> {
         f: &mut std::fmt::Formatter
     ) -> core::result::Result<(),core::fmt::Error> {
         match self {
-            Error::RuleNotValidated(rule) => write!(f,"{}",rule)
+            Error::RuleNotValidated(rule) => write!(f,"{}",rule),
+            Error::RuleNotValidatedBecause(rule, reason) => write!(f,"{}: {}",rule,reason),
         }
     }
 }
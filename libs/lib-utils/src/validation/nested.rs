@@ -0,0 +1,239 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+
+use super::{validate_rules, Error, Validate};
+
+// Path
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PathSegment {
+    Field(String),
+    Index(usize),
+    Key(String),
+}
+
+// Location of a value inside validated structure, displayed as `items[2].quantity`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct FieldPath(Vec<PathSegment>);
+
+impl FieldPath {
+    pub fn root() -> Self {
+        FieldPath(Vec::new())
+    }
+
+    pub fn field(&self, name: &str) -> Self {
+        self.push(PathSegment::Field(name.to_string()))
+    }
+
+    pub fn index(&self, index: usize) -> Self {
+        self.push(PathSegment::Index(index))
+    }
+
+    pub fn key<K: Display>(&self, key: K) -> Self {
+        self.push(PathSegment::Key(key.to_string()))
+    }
+
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn push(&self, segment: PathSegment) -> Self {
+        let mut segments = self.0.clone();
+        segments.push(segment);
+        FieldPath(segments)
+    }
+}
+
+impl Display for FieldPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                PathSegment::Field(name) => {
+                    if i > 0 { write!(f, ".")?; }
+                    write!(f, "{}", name)?;
+                },
+                PathSegment::Index(index) => { write!(f, "[{}]", index)?; },
+                PathSegment::Key(key) => { write!(f, "[{}]", key)?; },
+            }
+        }
+        Ok(())
+    }
+}
+
+// Errors
+
+// Flat path -> errors map, serialized as a plain JSON object
+#[derive(Debug, Clone, Default)]
+pub struct ValidationErrors<'a>(BTreeMap<String, Vec<Error<'a>>>);
+
+impl<'a> ValidationErrors<'a> {
+    pub fn new() -> Self {
+        ValidationErrors(BTreeMap::new())
+    }
+
+    pub fn add<I>(&mut self, path: &FieldPath, errors: I)
+    where
+        I: IntoIterator<Item = Error<'a>>
+    {
+        let mut errors = errors.into_iter().peekable();
+        if errors.peek().is_none() {
+            return;
+        }
+
+        self.0
+            .entry(path.to_string())
+            .or_default()
+            .extend(errors);
+    }
+
+    // Validates a leaf value with plain rules and records failures under `path`
+    pub fn check<T, U>(&mut self, path: &FieldPath, value: &T, rules: &[&'a U])
+    where
+        U: Validate<T>
+    {
+        self.add(path, validate_rules(value, rules).into_vec());
+    }
+
+    // Validates a nested value, recording its failures relative to `path`
+    pub fn nested<V>(&mut self, path: &FieldPath, value: &V)
+    where
+        V: ValidateNested<'a> + ?Sized
+    {
+        value.validate_nested(path, self);
+    }
+
+    pub fn merge(&mut self, other: ValidationErrors<'a>) {
+        for (path, errors) in other.0 {
+            self.0.entry(path).or_default().extend(errors);
+        }
+    }
+
+    pub fn get(&self, path: &str) -> Option<&Vec<Error<'a>>> {
+        self.0.get(path)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Vec<Error<'a>>)> {
+        self.0.iter()
+    }
+
+    pub fn into_result(self) -> std::result::Result<(), Self> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl<'a> From<ValidationErrors<'a>> for BTreeMap<String, Vec<Error<'a>>> {
+    fn from(value: ValidationErrors<'a>) -> Self {
+        value.0
+    }
+}
+
+impl<'a> IntoIterator for ValidationErrors<'a> {
+    type Item = (String, Vec<Error<'a>>);
+    type IntoIter = std::collections::btree_map::IntoIter<String, Vec<Error<'a>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> std::fmt::Display for ValidationErrors<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (path, errors)) in self.0.iter().enumerate() {
+            if i > 0 { write!(f, "; ")?; }
+            write!(f, "{}: ", path)?;
+            for (j, error) in errors.iter().enumerate() {
+                if j > 0 { write!(f, ", ")?; }
+                write!(f, "{}", error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> serde::Serialize for ValidationErrors<'a> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'a> std::error::Error for ValidationErrors<'a> {}
+
+// Traits
+
+pub trait ValidateNested<'a> {
+    fn validate_nested(&self, path: &FieldPath, errors: &mut ValidationErrors<'a>);
+
+    fn validate_all(&self) -> std::result::Result<(), ValidationErrors<'a>> {
+        let mut errors = ValidationErrors::new();
+        self.validate_nested(&FieldPath::root(), &mut errors);
+        errors.into_result()
+    }
+}
+
+impl<'a, T: ValidateNested<'a> + ?Sized> ValidateNested<'a> for &T {
+    fn validate_nested(&self, path: &FieldPath, errors: &mut ValidationErrors<'a>) {
+        (**self).validate_nested(path, errors);
+    }
+}
+
+impl<'a, T: ValidateNested<'a> + ?Sized> ValidateNested<'a> for Box<T> {
+    fn validate_nested(&self, path: &FieldPath, errors: &mut ValidationErrors<'a>) {
+        (**self).validate_nested(path, errors);
+    }
+}
+
+impl<'a, T: ValidateNested<'a>> ValidateNested<'a> for Option<T> {
+    fn validate_nested(&self, path: &FieldPath, errors: &mut ValidationErrors<'a>) {
+        if let Some(value) = self {
+            value.validate_nested(path, errors);
+        }
+    }
+}
+
+impl<'a, T: ValidateNested<'a>> ValidateNested<'a> for [T] {
+    fn validate_nested(&self, path: &FieldPath, errors: &mut ValidationErrors<'a>) {
+        for (i, value) in self.iter().enumerate() {
+            value.validate_nested(&path.index(i), errors);
+        }
+    }
+}
+
+impl<'a, T: ValidateNested<'a>> ValidateNested<'a> for Vec<T> {
+    fn validate_nested(&self, path: &FieldPath, errors: &mut ValidationErrors<'a>) {
+        self.as_slice().validate_nested(path, errors);
+    }
+}
+
+impl<'a, K: Display, V: ValidateNested<'a>, S> ValidateNested<'a> for HashMap<K, V, S> {
+    fn validate_nested(&self, path: &FieldPath, errors: &mut ValidationErrors<'a>) {
+        for (key, value) in self {
+            value.validate_nested(&path.key(key), errors);
+        }
+    }
+}
+
+impl<'a, K: Display, V: ValidateNested<'a>> ValidateNested<'a> for BTreeMap<K, V> {
+    fn validate_nested(&self, path: &FieldPath, errors: &mut ValidationErrors<'a>) {
+        for (key, value) in self {
+            value.validate_nested(&path.key(key), errors);
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+use super::{Error, Result, Rule, Validate};
+
+const COMMON_PASSWORDS: &str = include_str!("common-passwords.txt");
+
+const KEYBOARD_ROWS: [&str; 4] = ["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+// Shortest run counted as a pattern
+const MIN_PATTERN_LENGTH: usize = 3;
+
+// Entropy in bits needed to reach score 1, 2, 3 and 4
+const SCORE_THRESHOLDS: [f64; 4] = [10.0, 20.0, 28.0, 36.0];
+
+pub const MAX_SCORE: u8 = 4;
+
+static BUNDLED_BLOCKLIST: LazyLock<PasswordBlocklist> = LazyLock::new(PasswordBlocklist::bundled);
+
+// Keyboard rows in both directions
+static KEYBOARD_PATTERNS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    KEYBOARD_ROWS
+        .iter()
+        .flat_map(|row| [row.to_string(), row.chars().rev().collect()])
+        .collect()
+});
+
+// Estimation
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Weakness {
+    Repeated,
+    Sequence,
+    KeyboardPattern,
+    Year,
+    CommonPassword,
+}
+
+impl std::fmt::Display for Weakness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Weakness::Repeated => write!(f, "contains repeated characters"),
+            Weakness::Sequence => write!(f, "contains an alphabetical or numeric sequence"),
+            Weakness::KeyboardPattern => write!(f, "contains a keyboard pattern"),
+            Weakness::Year => write!(f, "contains a year"),
+            Weakness::CommonPassword => write!(f, "contains a common password"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Estimate {
+    pub score: u8,
+    pub entropy: f64,
+    pub weaknesses: Vec<Weakness>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Match {
+    start: usize,
+    end: usize,
+    entropy: f64,
+    weakness: Weakness,
+}
+
+// Rough zxcvbn-style estimation: known patterns are charged by how cheap
+// they are to guess, remaining characters by the size of the character pool
+pub fn estimate(password: &str) -> Estimate {
+    let original: Vec<char> = password.chars().collect();
+    let chars: Vec<char> = password.to_lowercase().chars().collect();
+
+    // Lowercasing may change length for some scripts, patterns are skipped then
+    let mut matches = if chars.len() == original.len() {
+        [
+            repeat_matches(&chars),
+            sequence_matches(&chars),
+            keyboard_matches(&chars),
+            year_matches(&chars),
+            dictionary_matches(&chars, &original),
+        ]
+        .concat()
+    } else {
+        Vec::new()
+    };
+
+    // Longest patterns win, overlapping shorter ones are dropped
+    matches.sort_by_key(|m| std::cmp::Reverse(m.end - m.start));
+
+    let mut covered = vec![false; original.len()];
+    let mut entropy = 0.0;
+    let mut weaknesses: Vec<Weakness> = Vec::new();
+
+    for m in matches {
+        if covered[m.start..m.end].iter().any(|&c| c) {
+            continue;
+        }
+        covered[m.start..m.end].iter_mut().for_each(|c| *c = true);
+        entropy += m.entropy;
+        if !weaknesses.contains(&m.weakness) {
+            weaknesses.push(m.weakness);
+        }
+    }
+
+    let char_entropy = (pool_size(&original) as f64).log2();
+    entropy += covered.iter().filter(|&&c| !c).count() as f64 * char_entropy;
+
+    weaknesses.sort();
+
+    let score = SCORE_THRESHOLDS
+        .iter()
+        .filter(|&&threshold| entropy >= threshold)
+        .count() as u8;
+
+    Estimate { score, entropy, weaknesses }
+}
+
+fn pool_size(chars: &[char]) -> usize {
+    let mut pool = 0;
+    if chars.iter().any(|c| c.is_ascii_lowercase()) { pool += 26; }
+    if chars.iter().any(|c| c.is_ascii_uppercase()) { pool += 26; }
+    if chars.iter().any(|c| c.is_ascii_digit()) { pool += 10; }
+    if chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' ') { pool += 33; }
+    if chars.iter().any(|c| !c.is_ascii()) { pool += 100; }
+    pool.max(1)
+}
+
+fn repeat_matches(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let mut start = 0;
+
+    while start < chars.len() {
+        let mut end = start + 1;
+        while end < chars.len() && chars[end] == chars[start] {
+            end += 1;
+        }
+        if end - start >= MIN_PATTERN_LENGTH {
+            matches.push(Match {
+                start,
+                end,
+                entropy: (pool_size(&chars[start..start + 1]) as f64).log2() + ((end - start) as f64).log2(),
+                weakness: Weakness::Repeated,
+            });
+        }
+        start = end;
+    }
+
+    matches
+}
+
+fn sequence_matches(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let mut start = 0;
+
+    while start + 1 < chars.len() {
+        let step = chars[start + 1] as i32 - chars[start] as i32;
+        let mut end = start + 1;
+
+        if (step == 1 || step == -1) && chars[start].is_ascii_alphanumeric() {
+            while end < chars.len()
+                && chars[end].is_ascii_alphanumeric()
+                && chars[end] as i32 - chars[end - 1] as i32 == step
+            {
+                end += 1;
+            }
+        }
+
+        if end - start >= MIN_PATTERN_LENGTH {
+            let alphabet: f64 = if chars[start].is_ascii_digit() { 10.0 } else { 26.0 };
+            matches.push(Match {
+                start,
+                end,
+                entropy: alphabet.log2() + ((end - start) as f64).log2() + 1.0,
+                weakness: Weakness::Sequence,
+            });
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+
+    matches
+}
+
+fn keyboard_matches(chars: &[char]) -> Vec<Match> {
+    let rows = &*KEYBOARD_PATTERNS;
+
+    let mut matches = Vec::new();
+    let mut start = 0;
+
+    while start < chars.len() {
+        let mut end = start;
+        while end < chars.len() {
+            let candidate: String = chars[start..=end].iter().collect();
+            if !rows.iter().any(|row| row.contains(&candidate)) {
+                break;
+            }
+            end += 1;
+        }
+
+        if end - start >= MIN_PATTERN_LENGTH {
+            matches.push(Match {
+                start,
+                end,
+                entropy: (rows.len() as f64 * 10.0).log2() + ((end - start) as f64).log2(),
+                weakness: Weakness::KeyboardPattern,
+            });
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+
+    matches
+}
+
+fn year_matches(chars: &[char]) -> Vec<Match> {
+    chars
+        .windows(4)
+        .enumerate()
+        .filter(|(_, window)| {
+            window.iter().all(|c| c.is_ascii_digit())
+                && matches!((window[0], window[1]), ('1', '9') | ('2', '0'))
+        })
+        .map(|(start, _)| Match {
+            start,
+            end: start + 4,
+            entropy: 200f64.log2(),
+            weakness: Weakness::Year,
+        })
+        .collect()
+}
+
+fn dictionary_matches(chars: &[char], original: &[char]) -> Vec<Match> {
+    let lowercase: String = chars.iter().collect();
+    let mut matches = Vec::new();
+
+    for (rank, word) in BUNDLED_BLOCKLIST.ranked.iter().enumerate() {
+        if word.chars().count() < 4 {
+            continue;
+        }
+
+        for (byte_start, _) in lowercase.match_indices(word.as_str()) {
+            let start = lowercase[..byte_start].chars().count();
+            let end = start + word.chars().count();
+            let capitalized = original[start..end].iter().any(|c| c.is_uppercase());
+
+            matches.push(Match {
+                start,
+                end,
+                entropy: ((rank + 1) as f64).log2() + if capitalized { 1.0 } else { 0.0 },
+                weakness: Weakness::CommonPassword,
+            });
+        }
+    }
+
+    matches
+}
+
+// Rules
+
+#[derive(Debug, Clone)]
+pub struct PasswordStrength {
+    min_score: u8,
+}
+
+impl PasswordStrength {
+    pub fn new(min_score: u8) -> Self {
+        PasswordStrength { min_score: min_score.min(MAX_SCORE) }
+    }
+
+    pub fn min_score(&self) -> u8 {
+        self.min_score
+    }
+}
+
+impl std::fmt::Display for PasswordStrength {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Password strength must be at least {} of {}", self.min_score, MAX_SCORE)
+    }
+}
+
+impl Rule for PasswordStrength {}
+
+impl Validate<String> for PasswordStrength {
+    fn validate(&self, value: &String) -> Result<'_, &Self> {
+        let estimate = estimate(value);
+
+        if estimate.score >= self.min_score {
+            return Ok(self);
+        }
+
+        let mut reason = format!("score is {}", estimate.score);
+        for weakness in &estimate.weaknesses {
+            reason.push_str(&format!(", {}", weakness));
+        }
+        if estimate.weaknesses.is_empty() {
+            reason.push_str(", too short or too few kinds of characters");
+        }
+
+        Err(Error::RuleNotValidatedBecause(self, reason))
+    }
+}
+
+// Case insensitive list of known common or breached passwords
+#[derive(Debug, Clone, Default)]
+pub struct PasswordBlocklist {
+    passwords: HashSet<String>,
+    // Keeps file order, which for bundled list is popularity order
+    ranked: Vec<String>,
+}
+
+impl PasswordBlocklist {
+    pub fn bundled() -> Self {
+        Self::from_list(COMMON_PASSWORDS)
+    }
+
+    pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+        Ok(Self::from_list(&std::fs::read_to_string(path)?))
+    }
+
+    // One password per line, empty lines and lines starting with `#` are skipped
+    pub fn from_list(list: &str) -> Self {
+        let mut blocklist = PasswordBlocklist::default();
+        blocklist.extend(list.lines());
+        blocklist
+    }
+
+    pub fn extend<'a, I: IntoIterator<Item = &'a str>>(&mut self, passwords: I) {
+        for password in passwords {
+            let password = password.trim();
+            if password.is_empty() || password.starts_with('#') {
+                continue;
+            }
+
+            let password = password.to_lowercase();
+            if self.passwords.insert(password.clone()) {
+                self.ranked.push(password);
+            }
+        }
+    }
+
+    pub fn contains(&self, password: &str) -> bool {
+        self.passwords.contains(&password.to_lowercase())
+    }
+
+    pub fn len(&self) -> usize {
+        self.passwords.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passwords.is_empty()
+    }
+}
+
+impl std::fmt::Display for PasswordBlocklist {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Must not be a common or breached password")
+    }
+}
+
+impl Rule for PasswordBlocklist {}
+
+impl Validate<String> for PasswordBlocklist {
+    fn validate(&self, value: &String) -> Result<'_, &Self> {
+        if self.contains(value) {
+            Err(Error::RuleNotValidatedBecause(self, "found in the password blocklist".to_string()))
+        } else {
+            Ok(self)
+        }
+    }
+}
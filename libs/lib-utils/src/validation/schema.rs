@@ -0,0 +1,132 @@
+use serde_json::{Map, Value};
+
+use super::password::{PasswordBlocklist, PasswordStrength, MAX_SCORE};
+use super::{Rule, Rules, DIGITS, EMAIL_MAX_LENGTH, LOWERCASE_CHARACTERS, SPECIAL_CHARACTERS, UPPERCASE_CHARACTERS};
+
+// Length rules reject line breaks as well
+const SINGLE_LINE_PATTERN: &str = r"^[^\n]*$";
+
+// Traits
+
+// Maps a rule to the JSON Schema keywords enforcing the same constraint
+pub trait JsonSchema: Rule {
+    fn json_schema(&self) -> Map<String, Value>;
+}
+
+impl JsonSchema for Rules {
+    fn json_schema(&self) -> Map<String, Value> {
+        let mut schema = Map::new();
+
+        match self {
+            Rules::MaxLength(length) => {
+                schema.insert("maxLength".to_string(), Value::from(*length));
+                schema.insert("pattern".to_string(), Value::from(SINGLE_LINE_PATTERN));
+            },
+            Rules::MinLength(length) => {
+                schema.insert("minLength".to_string(), Value::from(*length));
+                schema.insert("pattern".to_string(), Value::from(SINGLE_LINE_PATTERN));
+            },
+            Rules::ContainsDidgits(must_contain) => {
+                schema.insert("pattern".to_string(), class_pattern(DIGITS, *must_contain));
+            },
+            Rules::ContainsSpecialCharacters(must_contain) => {
+                schema.insert("pattern".to_string(), class_pattern(SPECIAL_CHARACTERS, *must_contain));
+            },
+            Rules::ContainsLowecaseCharacter(must_contain) => {
+                schema.insert("pattern".to_string(), class_pattern(LOWERCASE_CHARACTERS, *must_contain));
+            },
+            Rules::ContainsUppercaseCharacter(must_contain) => {
+                schema.insert("pattern".to_string(), class_pattern(UPPERCASE_CHARACTERS, *must_contain));
+            },
+            Rules::Email => {
+                schema.insert("format".to_string(), Value::from("email"));
+                schema.insert("maxLength".to_string(), Value::from(EMAIL_MAX_LENGTH));
+            },
+        }
+
+        schema
+    }
+}
+
+// No standard keyword can express these, so they are exported as annotations
+// for clients to show; server remains the only place they are enforced
+impl JsonSchema for PasswordStrength {
+    fn json_schema(&self) -> Map<String, Value> {
+        let mut strength = Map::new();
+        strength.insert("minScore".to_string(), Value::from(self.min_score()));
+        strength.insert("maxScore".to_string(), Value::from(MAX_SCORE));
+
+        let mut schema = Map::new();
+        schema.insert("x-passwordStrength".to_string(), Value::Object(strength));
+        schema
+    }
+}
+
+impl JsonSchema for PasswordBlocklist {
+    fn json_schema(&self) -> Map<String, Value> {
+        let mut schema = Map::new();
+        schema.insert("x-passwordBlocklist".to_string(), Value::from(self.to_string()));
+        schema
+    }
+}
+
+fn class_pattern(class: &str, must_contain: bool) -> Value {
+    if must_contain {
+        Value::String(format!("[{}]", class))
+    } else {
+        Value::String(format!("^[^{}]*$", class))
+    }
+}
+
+// Builders
+
+// Schema object can hold each keyword once, so repeated keywords
+// (several `pattern`s for example) are moved into `allOf`. Keywords
+// repeated with the same value are kept once
+pub fn string_schema(rules: &[&dyn JsonSchema]) -> Value {
+    let mut schema = Map::new();
+    schema.insert("type".to_string(), Value::from("string"));
+
+    let mut all_of: Vec<Value> = Vec::new();
+
+    for rule in rules {
+        for (keyword, value) in rule.json_schema() {
+            let mut subschema = Map::new();
+            subschema.insert(keyword.clone(), value.clone());
+            let subschema = Value::Object(subschema);
+
+            if schema.get(&keyword) == Some(&value) || all_of.contains(&subschema) {
+                continue
+            }
+
+            if schema.contains_key(&keyword) {
+                all_of.push(subschema);
+            } else {
+                schema.insert(keyword, value);
+            }
+        }
+    }
+
+    if !all_of.is_empty() {
+        schema.insert("allOf".to_string(), Value::Array(all_of));
+    }
+
+    Value::Object(schema)
+}
+
+pub fn object_schema(properties: &[(&str, Value)], required: &[&str]) -> Value {
+    let properties: Map<String, Value> = properties
+        .iter()
+        .map(|(name, schema)| (name.to_string(), schema.clone()))
+        .collect();
+
+    let mut schema = Map::new();
+    schema.insert("type".to_string(), Value::from("object"));
+    schema.insert("properties".to_string(), Value::Object(properties));
+    schema.insert(
+        "required".to_string(),
+        Value::Array(required.iter().map(|name| Value::from(*name)).collect())
+    );
+
+    Value::Object(schema)
+}
@@ -0,0 +1,8 @@
+CREATE TABLE password_history (
+
+  id BYTEA PRIMARY KEY,
+  userId BYTEA NOT NULL,
+  password VARCHAR(255) NOT NULL,
+  createdAt TIMESTAMP NOT NULL DEFAULT NOW()
+
+);
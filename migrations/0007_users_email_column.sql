@@ -0,0 +1,3 @@
+ALTER TABLE users
+
+ADD COLUMN email VARCHAR(255) UNIQUE;
@@ -0,0 +1,3 @@
+ALTER TABLE users
+
+ADD COLUMN emailVerified BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,9 @@
+CREATE TABLE audit_log (
+
+  id BYTEA PRIMARY KEY,
+  event VARCHAR(64) NOT NULL,
+  userId BYTEA,
+  details JSONB NOT NULL DEFAULT '{}',
+  createdAt TIMESTAMP NOT NULL DEFAULT NOW()
+
+);
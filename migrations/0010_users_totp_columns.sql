@@ -0,0 +1,4 @@
+ALTER TABLE users
+
+ADD COLUMN totpSecret VARCHAR(64),
+ADD COLUMN totpEnabled BOOLEAN NOT NULL DEFAULT FALSE;
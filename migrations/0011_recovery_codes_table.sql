@@ -0,0 +1,8 @@
+CREATE TABLE recovery_codes (
+
+  id BYTEA PRIMARY KEY,
+  userId BYTEA NOT NULL,
+  code VARCHAR(64) NOT NULL,
+  usedAt TIMESTAMP
+
+);
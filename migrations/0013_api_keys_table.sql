@@ -0,0 +1,13 @@
+CREATE TABLE api_keys (
+
+  id BYTEA PRIMARY KEY,
+  userId BYTEA NOT NULL,
+  name VARCHAR(64) NOT NULL,
+  prefix VARCHAR(16) NOT NULL,
+  keyHash VARCHAR(64) NOT NULL UNIQUE,
+  scopes TEXT[] NOT NULL,
+  createdAt TIMESTAMP NOT NULL DEFAULT NOW(),
+  lastUsedAt TIMESTAMP,
+  revokedAt TIMESTAMP
+
+);
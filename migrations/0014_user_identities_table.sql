@@ -0,0 +1,12 @@
+CREATE TABLE user_identities (
+
+  id BYTEA PRIMARY KEY,
+  userId BYTEA NOT NULL,
+  provider VARCHAR(32) NOT NULL,
+  subject VARCHAR(255) NOT NULL,
+  email VARCHAR(255),
+  createdAt TIMESTAMP NOT NULL DEFAULT NOW(),
+  UNIQUE (provider, subject),
+  UNIQUE (userId, provider)
+
+);
@@ -0,0 +1,5 @@
+ALTER TABLE users
+
+ADD COLUMN status VARCHAR(16) NOT NULL DEFAULT 'active',
+ADD COLUMN statusReason TEXT,
+ADD COLUMN bannedUntil TIMESTAMP;
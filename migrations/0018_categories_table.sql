@@ -0,0 +1,9 @@
+CREATE TABLE categories (
+
+  id BYTEA PRIMARY KEY,
+  parentId BYTEA,
+  slug VARCHAR(64) UNIQUE NOT NULL,
+  name VARCHAR(255) NOT NULL,
+  position INTEGER NOT NULL DEFAULT 0
+
+);
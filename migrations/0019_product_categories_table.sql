@@ -0,0 +1,7 @@
+CREATE TABLE product_categories (
+
+  productId BYTEA NOT NULL,
+  categoryId BYTEA NOT NULL,
+  PRIMARY KEY (productId, categoryId)
+
+);
@@ -0,0 +1,15 @@
+ALTER TABLE product
+
+ADD COLUMN title VARCHAR(255),
+ADD COLUMN description TEXT NOT NULL DEFAULT '',
+ADD COLUMN sku VARCHAR(64),
+ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}';
+
+UPDATE product SET title = LEFT(info, 255), description = info, sku = 'P-' || encode(id, 'hex');
+
+ALTER TABLE product
+
+ALTER COLUMN title SET NOT NULL,
+ALTER COLUMN sku SET NOT NULL,
+ADD CONSTRAINT product_sku_key UNIQUE (sku),
+DROP COLUMN info;
@@ -0,0 +1,15 @@
+CREATE TABLE product_variants (
+
+  id BYTEA PRIMARY KEY,
+  productId BYTEA NOT NULL,
+  sku VARCHAR(64) UNIQUE NOT NULL,
+  options JSONB NOT NULL DEFAULT '{}',
+  priceOverride NUMERIC(10,2),
+  stock INTEGER NOT NULL DEFAULT 0 CHECK (stock >= 0),
+  position INTEGER NOT NULL DEFAULT 0
+
+);
+
+-- Every existing product becomes its own single variant
+INSERT INTO product_variants (id, productId, sku)
+SELECT uuid_send(gen_random_uuid()), id, sku FROM product;
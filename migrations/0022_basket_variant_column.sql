@@ -0,0 +1,31 @@
+ALTER TABLE basket
+
+ADD COLUMN variantId BYTEA,
+ADD COLUMN quantity INTEGER NOT NULL DEFAULT 1 CHECK (quantity > 0);
+
+UPDATE basket SET variantId = (
+  SELECT id FROM product_variants
+  WHERE productId = basket.itemId
+  ORDER BY position, id
+  LIMIT 1
+);
+
+-- Items without a variant no longer exist in the shop
+DELETE FROM basket WHERE variantId IS NULL;
+
+-- Baskets held a row per unit, every row takes the count and only the first stays
+UPDATE basket SET quantity = (
+  SELECT COUNT(*) FROM basket AS same
+  WHERE same.userId = basket.userId AND same.variantId = basket.variantId
+);
+
+DELETE FROM basket
+USING basket AS first
+WHERE basket.userId = first.userId
+  AND basket.variantId = first.variantId
+  AND basket.id > first.id;
+
+ALTER TABLE basket
+
+ALTER COLUMN variantId SET NOT NULL,
+ADD CONSTRAINT basket_user_variant_key UNIQUE (userId, variantId);
@@ -0,0 +1,20 @@
+CREATE TABLE orders (
+
+  id BYTEA PRIMARY KEY,
+  userId BYTEA,
+  total NUMERIC(12,2) NOT NULL,
+  createdAt TIMESTAMP NOT NULL DEFAULT NOW()
+
+);
+
+CREATE TABLE order_items (
+
+  id BYTEA PRIMARY KEY,
+  orderId BYTEA NOT NULL,
+  variantId BYTEA,
+  sku VARCHAR(64) NOT NULL,
+  title VARCHAR(255) NOT NULL,
+  unitPrice NUMERIC(10,2) NOT NULL,
+  quantity INTEGER NOT NULL
+
+);
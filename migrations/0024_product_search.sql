@@ -0,0 +1,43 @@
+ALTER TABLE product
+
+ADD COLUMN searchVector TSVECTOR GENERATED ALWAYS AS (
+  setweight(to_tsvector('english', title), 'A') ||
+  setweight(to_tsvector('english', description), 'B') ||
+  setweight(jsonb_to_tsvector('english', attributes, '["string", "numeric", "boolean"]'), 'C')
+) STORED;
+
+CREATE INDEX product_search_idx ON product USING GIN (searchVector);
+
+-- Products matching the search text and filters, with what results are sorted
+-- and counted by. Price of a product is the lowest price of its variants
+CREATE FUNCTION search_products(
+  searchText TEXT,
+  searchCategories BYTEA[],
+  minPrice BIGINT,
+  maxPrice BIGINT,
+  minRating FLOAT8,
+  inStockOnly BOOLEAN
+)
+RETURNS TABLE (id BYTEA, rank REAL, fromPrice BIGINT, rating FLOAT8, inStock BOOLEAN)
+LANGUAGE SQL STABLE AS $$
+  SELECT product.id,
+    COALESCE(ts_rank(product.searchVector, terms.query), 0),
+    offers.fromPrice,
+    product.rating::FLOAT8,
+    offers.inStock
+  FROM product
+  CROSS JOIN LATERAL (SELECT websearch_to_tsquery('english', searchText) AS query) terms
+  CROSS JOIN LATERAL (
+    SELECT (COALESCE(MIN(COALESCE(variant.priceOverride, product.price)), product.price) * 100)::BIGINT AS fromPrice,
+      COALESCE(BOOL_OR(variant.stock > 0), FALSE) AS inStock
+    FROM product_variants variant WHERE variant.productId = product.id
+  ) offers
+  WHERE (searchText IS NULL OR product.searchVector @@ terms.query)
+    AND (searchCategories IS NULL OR product.id IN (
+      SELECT productId FROM product_categories WHERE categoryId = ANY(searchCategories)
+    ))
+    AND (minPrice IS NULL OR offers.fromPrice >= minPrice)
+    AND (maxPrice IS NULL OR offers.fromPrice <= maxPrice)
+    AND (minRating IS NULL OR product.rating >= minRating)
+    AND (NOT inStockOnly OR offers.inStock)
+$$;
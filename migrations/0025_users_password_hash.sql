@@ -0,0 +1,5 @@
+-- Passwords are kept hashed with a random salt, equal passwords don't
+-- collide. Rows from before hashing are hashed on their next sign in
+ALTER TABLE users
+
+DROP CONSTRAINT users_password_key;
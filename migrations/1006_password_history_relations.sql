@@ -0,0 +1,6 @@
+ALTER TABLE Password_history
+
+ADD CONSTRAINT fk_password_history_user
+  FOREIGN KEY (userId)
+  REFERENCES Users(id)
+  ON DELETE CASCADE;
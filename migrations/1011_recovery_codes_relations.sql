@@ -0,0 +1,6 @@
+ALTER TABLE Recovery_codes
+
+ADD CONSTRAINT fk_recovery_codes_user
+  FOREIGN KEY (userId)
+  REFERENCES Users(id)
+  ON DELETE CASCADE;
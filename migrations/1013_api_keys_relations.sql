@@ -0,0 +1,6 @@
+ALTER TABLE Api_keys
+
+ADD CONSTRAINT fk_api_keys_user
+  FOREIGN KEY (userId)
+  REFERENCES Users(id)
+  ON DELETE CASCADE;
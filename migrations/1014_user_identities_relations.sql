@@ -0,0 +1,6 @@
+ALTER TABLE User_identities
+
+ADD CONSTRAINT fk_user_identities_user
+  FOREIGN KEY (userId)
+  REFERENCES Users(id)
+  ON DELETE CASCADE;
@@ -0,0 +1,5 @@
+ALTER TABLE Categories
+
+ADD CONSTRAINT fk_categories_parent
+  FOREIGN KEY (parentId)
+  REFERENCES Categories(id);
@@ -0,0 +1,14 @@
+ALTER TABLE Product_categories
+
+ADD CONSTRAINT fk_product_categories_product
+  FOREIGN KEY (productId)
+  REFERENCES Product(id)
+  ON DELETE CASCADE;
+
+
+ALTER TABLE Product_categories
+
+ADD CONSTRAINT fk_product_categories_category
+  FOREIGN KEY (categoryId)
+  REFERENCES Categories(id)
+  ON DELETE CASCADE;
@@ -0,0 +1,6 @@
+ALTER TABLE Product_variants
+
+ADD CONSTRAINT fk_product_variants_product
+  FOREIGN KEY (productId)
+  REFERENCES Product(id)
+  ON DELETE CASCADE;
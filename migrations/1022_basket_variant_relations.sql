@@ -0,0 +1,6 @@
+ALTER TABLE Basket
+
+ADD CONSTRAINT fk_basket_variant
+  FOREIGN KEY (variantId)
+  REFERENCES Product_variants(id)
+  ON DELETE CASCADE;
@@ -0,0 +1,22 @@
+ALTER TABLE Orders
+
+ADD CONSTRAINT fk_orders_user
+  FOREIGN KEY (userId)
+  REFERENCES Users(id)
+  ON DELETE SET NULL;
+
+
+ALTER TABLE Order_items
+
+ADD CONSTRAINT fk_order_items_order
+  FOREIGN KEY (orderId)
+  REFERENCES Orders(id)
+  ON DELETE CASCADE;
+
+
+ALTER TABLE Order_items
+
+ADD CONSTRAINT fk_order_items_variant
+  FOREIGN KEY (variantId)
+  REFERENCES Product_variants(id)
+  ON DELETE SET NULL;
@@ -0,0 +1,8 @@
+ALTER TABLE Basket
+
+DROP CONSTRAINT fk_basket_item;
+
+
+ALTER TABLE Basket
+
+DROP COLUMN itemId;
@@ -0,0 +1,472 @@
+use actix_web::{delete, get, http::StatusCode, patch, post, put, web::{Data, Json, Path, Query}, HttpResponse, Responder};
+use lib_utils::validation::{FieldPath, ValidationErrors};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::{
+        extractors::AdminUser,
+        models::{
+            audit::{AdminChange, AuditRepository, Event},
+            user::{
+                self,
+                account::{AccountRepository, AccountState, AccountStatus},
+                auth,
+                one_time::{OneTimeToken, Purpose},
+                session::unix_time,
+                Email, Login, Name, Password, PasswordHistoryRepository, Role, RoleRepository, User, UserListQuery, UserRepository, Uuid
+            }
+        }
+    },
+    AppState
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+// Bulk requests are one transaction each
+const MAX_BATCH_SIZE: usize = 100;
+
+async fn record(db: &AppState, admin: &AdminUser, user_id: &Uuid, change: AdminChange) {
+    let event = Event::AdminChange { actor: admin.0.user_id.clone(), change };
+    if let Err(e) = Event::record_event(&db.db, Some(user_id), &event).await {
+        eprintln!("{:?}",e);
+    }
+}
+
+// Sessions of accounts an admin locked out or removed end at once
+async fn revoke_sessions(db: &AppState, user_id: &Uuid) {
+    if let Err(e) = auth::revoke_user_sessions(&mut db.cache(), user_id, None).await {
+        eprintln!("{:?}",e);
+    }
+}
+
+fn batch_size_error(size: usize) -> Option<HttpResponse> {
+    (size == 0 || size > MAX_BATCH_SIZE).then(|| {
+        HttpResponse::build(StatusCode::BAD_REQUEST)
+            .body(format!("Batch must have 1 to {} users", MAX_BATCH_SIZE))
+    })
+}
+
+// Admins can't lock themselves out
+fn self_change_error(admin: &AdminUser, user_id: &Uuid) -> Option<HttpResponse> {
+    (&admin.0.user_id == user_id).then(|| {
+        HttpResponse::build(StatusCode::CONFLICT)
+            .body("Admins can't change their own account here")
+    })
+}
+
+#[derive(Serialize)]
+struct UserSummary {
+    id: Uuid,
+    login: String,
+    name: String,
+    email: Option<String>,
+}
+
+impl From<&User> for UserSummary {
+    fn from(user: &User) -> Self {
+        UserSummary {
+            id: user.id.clone(),
+            login: user.login.to_string(),
+            name: user.name.to_string(),
+            email: user.email.as_ref().map(|email| email.to_string()),
+        }
+    }
+}
+
+// Listing
+
+#[derive(Deserialize)]
+struct ListQuery {
+    search: Option<String>,
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct UserList {
+    users: Vec<UserSummary>,
+    total: i64,
+    page: i64,
+    per_page: i64,
+}
+
+#[get("/users")]
+async fn list_users(_admin: AdminUser, query: Query<ListQuery>, db: Data<AppState>) -> impl Responder {
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    let list_query = UserListQuery {
+        search: query.search.clone().filter(|search| !search.trim().is_empty()),
+        limit: per_page,
+        offset: (page - 1) * per_page,
+    };
+
+    match User::fetch_all_users(&db.db, &list_query).await {
+        Ok(result) => HttpResponse::build(StatusCode::OK)
+                        .json(UserList {
+                            users: result.users.iter().map(UserSummary::from).collect(),
+                            total: result.total,
+                            page,
+                            per_page,
+                        }),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
+
+#[derive(Serialize)]
+struct UserDetails {
+    #[serde(flatten)]
+    user: UserSummary,
+    #[serde(flatten)]
+    state: AccountState,
+}
+
+#[get("/users/{user_id}")]
+async fn get_user(_admin: AdminUser, user_id: Path<Uuid>, db: Data<AppState>) -> impl Responder {
+    let user = match User::fetch_user(&db.db, user_id.clone()).await {
+        Ok(user) => user,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    match User::fetch_account_state(&db.db, &user.id).await {
+        Ok(state) => HttpResponse::build(StatusCode::OK)
+                        .json(UserDetails { user: UserSummary::from(&user), state }),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
+
+// Bulk changes, all or nothing. Validation errors are keyed by paths like `users[2].login`
+
+#[derive(Deserialize)]
+struct NewUser {
+    login: String,
+    name: String,
+    password: String,
+    email: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CreateUsersBody {
+    users: Vec<NewUser>,
+}
+
+fn build_user(path: &FieldPath, new_user: &NewUser, validation_errors: &mut ValidationErrors<'static>) -> Option<(User, Password)> {
+    let mut user_builder = user::Builder::new();
+    let mut password: Option<Password> = None;
+    let mut valid = true;
+
+    match Password::parse(new_user.password.clone()) {
+        Ok(parsed_password) => { password = Some(parsed_password); },
+        Err(err) => { validation_errors.add(&path.field("password"), err); valid = false; },
+    }
+
+    match Login::parse(new_user.login.clone()) {
+        Ok(login) => { user_builder.login(login); },
+        Err(err) => { validation_errors.add(&path.field("login"), err); valid = false; },
+    }
+
+    match Name::parse(new_user.name.clone()) {
+        Ok(name) => { user_builder.name(name); },
+        Err(err) => { validation_errors.add(&path.field("name"), err); valid = false; },
+    }
+
+    if let Some(email) = &new_user.email {
+        match Email::parse(email.clone()) {
+            Ok(email) => { user_builder.email(email); },
+            Err(err) => { validation_errors.add(&path.field("email"), err); valid = false; },
+        }
+    }
+
+    if !valid {
+        return None
+    }
+
+    user_builder.id(Uuid::parse(uuid::Uuid::new_v4()));
+
+    Some((user_builder.try_get().expect("Error building user"), password?))
+}
+
+#[post("/users")]
+async fn create_users(admin: AdminUser, body: Json<CreateUsersBody>, db: Data<AppState>) -> impl Responder {
+    if let Some(response) = batch_size_error(body.users.len()) {
+        return response
+    }
+
+    let mut users = Vec::new();
+    let mut validation_errors = ValidationErrors::new();
+    let path = FieldPath::root().field("users");
+
+    for (index, new_user) in body.users.iter().enumerate() {
+        if let Some(user) = build_user(&path.index(index), new_user, &mut validation_errors) {
+            users.push(user);
+        }
+    }
+
+    if !validation_errors.is_empty() {
+        return HttpResponse::build(StatusCode::BAD_REQUEST)
+                .json(validation_errors)
+    }
+
+    let created: Vec<UserSummary> = users.iter().map(|(user, _)| UserSummary::from(user)).collect();
+
+    if let Err(e) = User::create_many_users(&db.db, users).await {
+        return super::user::taken_or(e)
+    }
+
+    for user in &created {
+        record(&db, &admin, &user.id, AdminChange::Created).await;
+    }
+
+    HttpResponse::build(StatusCode::CREATED)
+        .json(created)
+}
+
+#[derive(Deserialize)]
+struct UserPatch {
+    id: Uuid,
+    name: Option<String>,
+    login: Option<String>,
+    email: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PatchUsersBody {
+    users: Vec<UserPatch>,
+}
+
+#[patch("/users")]
+async fn patch_users(admin: AdminUser, body: Json<PatchUsersBody>, db: Data<AppState>) -> impl Responder {
+    if let Some(response) = batch_size_error(body.users.len()) {
+        return response
+    }
+
+    let mut users = Vec::new();
+    let mut validation_errors = ValidationErrors::new();
+
+    for (index, user_patch) in body.users.iter().enumerate() {
+        let path = FieldPath::root().field("users").index(index);
+
+        let mut user = match User::fetch_undeleted_user(&db.db, user_patch.id.clone()).await {
+            Ok(user) => user,
+            Err(e) => return match StatusCode::from(e) {
+                StatusCode::NOT_FOUND => HttpResponse::build(StatusCode::NOT_FOUND)
+                                            .body(format!("User {} not found", user_patch.id)),
+                err_code => HttpResponse::new(err_code),
+            },
+        };
+
+        let mut user_errors = ValidationErrors::new();
+
+        if let Some(name) = &user_patch.name {
+            match Name::parse(name.clone()) {
+                Ok(name) => { user.name = name; },
+                Err(err) => { user_errors.add(&path.field("name"), err); },
+            }
+        }
+
+        if let Some(login) = &user_patch.login {
+            match Login::parse(login.clone()) {
+                Ok(login) => { user.login = login; },
+                Err(err) => { user_errors.add(&path.field("login"), err); },
+            }
+        }
+
+        if let Some(email) = &user_patch.email {
+            match Email::parse(email.clone()) {
+                Ok(email) => { user.email = Some(email); },
+                Err(err) => { user_errors.add(&path.field("email"), err); },
+            }
+        }
+
+        if user_errors.is_empty() {
+            users.push(user);
+        } else {
+            validation_errors.merge(user_errors);
+        }
+    }
+
+    if !validation_errors.is_empty() {
+        return HttpResponse::build(StatusCode::BAD_REQUEST)
+                .json(validation_errors)
+    }
+
+    let user_ids: Vec<Uuid> = users.iter().map(|user| user.id.clone()).collect();
+
+    if let Err(e) = User::patch_many_users(&db.db, users).await {
+        return super::user::taken_or(e)
+    }
+
+    for user_id in &user_ids {
+        record(&db, &admin, user_id, AdminChange::Updated).await;
+    }
+
+    HttpResponse::new(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct DeleteUsersBody {
+    ids: Vec<Uuid>,
+}
+
+#[delete("/users")]
+async fn delete_users(admin: AdminUser, body: Json<DeleteUsersBody>, db: Data<AppState>) -> impl Responder {
+    if let Some(response) = batch_size_error(body.ids.len()) {
+        return response
+    }
+
+    if let Some(response) = body.ids.iter().find_map(|user_id| self_change_error(&admin, user_id)) {
+        return response
+    }
+
+    let deleted = match User::delete_many_users(&db.db, body.ids.clone()).await {
+        Ok(deleted) => deleted,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    for user_id in &deleted {
+        revoke_sessions(&db, user_id).await;
+        record(&db, &admin, user_id, AdminChange::Deleted).await;
+    }
+
+    HttpResponse::new(StatusCode::NO_CONTENT)
+}
+
+// Single account changes
+
+async fn set_status(admin: AdminUser, user_id: Uuid, status: AccountStatus, db: &AppState) -> HttpResponse {
+    if let Some(response) = self_change_error(&admin, &user_id) {
+        return response
+    }
+
+    match User::set_status(&db.db, &user_id, &status).await {
+        Ok(true) => {},
+        Ok(false) => return HttpResponse::build(StatusCode::NOT_FOUND)
+                            .body("User not found"),
+        Err(e) => return HttpResponse::new(e.into()),
+    }
+
+    let change = match status {
+        AccountStatus::Active => AdminChange::Enabled,
+        AccountStatus::Disabled { reason } => AdminChange::Disabled { reason },
+        AccountStatus::Banned { until, reason } => AdminChange::Banned { until, reason },
+        AccountStatus::Deleted => AdminChange::Deleted,
+    };
+
+    // Requests of blocked accounts are refused anyway, revoking frees the sessions
+    if !matches!(change, AdminChange::Enabled) {
+        revoke_sessions(db, &user_id).await;
+    }
+    record(db, &admin, &user_id, change).await;
+
+    HttpResponse::new(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct DisableBody {
+    reason: Option<String>,
+}
+
+#[post("/users/{user_id}/disable")]
+async fn disable_user(admin: AdminUser, user_id: Path<Uuid>, body: Option<Json<DisableBody>>, db: Data<AppState>) -> impl Responder {
+    let reason = body.and_then(|body| body.into_inner().reason);
+
+    set_status(admin, user_id.into_inner(), AccountStatus::Disabled { reason }, &db).await
+}
+
+#[derive(Deserialize)]
+struct BanBody {
+    // Unix time the ban ends at
+    until: i64,
+    reason: Option<String>,
+}
+
+#[post("/users/{user_id}/ban")]
+async fn ban_user(admin: AdminUser, user_id: Path<Uuid>, body: Json<BanBody>, db: Data<AppState>) -> impl Responder {
+    let BanBody { until, reason } = body.into_inner();
+
+    if until <= unix_time() as i64 {
+        return HttpResponse::build(StatusCode::BAD_REQUEST)
+                .body("Ban has to end in the future")
+    }
+
+    set_status(admin, user_id.into_inner(), AccountStatus::Banned { until, reason }, &db).await
+}
+
+// Lifts a ban as well
+#[post("/users/{user_id}/enable")]
+async fn enable_user(admin: AdminUser, user_id: Path<Uuid>, db: Data<AppState>) -> impl Responder {
+    set_status(admin, user_id.into_inner(), AccountStatus::Active, &db).await
+}
+
+#[derive(Serialize)]
+struct ForcedReset {
+    reset_email_sent: bool,
+}
+
+// Current password stops working, the user sets a new one through the reset mail
+#[post("/users/{user_id}/password-reset")]
+async fn force_password_reset(admin: AdminUser, user_id: Path<Uuid>, db: Data<AppState>) -> impl Responder {
+    let user_id = user_id.into_inner();
+
+    if let Some(response) = self_change_error(&admin, &user_id) {
+        return response
+    }
+
+    let user = match User::fetch_undeleted_user(&db.db, user_id).await {
+        Ok(user) => user,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    let unknown_password = Password::from(uuid::Uuid::new_v4().simple().to_string());
+    if let Err(e) = User::change_password(&db.db, &user.id, unknown_password).await {
+        return super::user::taken_or(e)
+    }
+
+    revoke_sessions(&db, &user.id).await;
+    record(&db, &admin, &user.id, AdminChange::PasswordResetForced).await;
+
+    let Some(email) = user.email else {
+        return HttpResponse::build(StatusCode::OK)
+                .json(ForcedReset { reset_email_sent: false })
+    };
+
+    match OneTimeToken::issue(&mut db.cache(), Purpose::PasswordReset, &user.id, "").await {
+        Ok(token) => {
+            super::user::send_mail(&db, super::user::reset_message(email.to_string(), &token)).await;
+
+            HttpResponse::build(StatusCode::OK)
+                .json(ForcedReset { reset_email_sent: true })
+        },
+        Err(e) => {
+            eprintln!("{:?}",e);
+            HttpResponse::build(StatusCode::OK)
+                .json(ForcedReset { reset_email_sent: false })
+        },
+    }
+}
+
+#[derive(Deserialize)]
+struct RoleBody {
+    role: Role,
+}
+
+// Admins act with their own account only, there is no signing in as another user
+#[put("/users/{user_id}/role")]
+async fn set_role(admin: AdminUser, user_id: Path<Uuid>, body: Json<RoleBody>, db: Data<AppState>) -> impl Responder {
+    let user_id = user_id.into_inner();
+
+    if let Some(response) = self_change_error(&admin, &user_id) {
+        return response
+    }
+
+    match User::set_role(&db.db, &user_id, body.role).await {
+        Ok(true) => {
+            record(&db, &admin, &user_id, AdminChange::RoleChanged { role: body.role }).await;
+
+            HttpResponse::new(StatusCode::NO_CONTENT)
+        },
+        Ok(false) => HttpResponse::build(StatusCode::NOT_FOUND)
+                        .body("User not found"),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
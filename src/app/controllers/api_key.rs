@@ -0,0 +1,142 @@
+use actix_web::{delete, get, http::StatusCode, post, web::{Data, Json, Path}, HttpResponse, Responder, ResponseError};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::{
+        extractors::{AdminUser, AuthorizedUser},
+        models::{
+            audit::{AuditRepository, Event},
+            user::{
+                api_key::{ApiKey, ApiKeyRepository, ApiKeySecret, ApiScope, NAME_MAX_LENGTH},
+                Role, RoleRepository, User, Uuid
+            }
+        }
+    },
+    AppState
+};
+
+#[derive(Deserialize)]
+struct CreateApiKeyBody {
+    name: String,
+    scopes: Vec<ApiScope>,
+}
+
+// The key itself is returned only here
+#[derive(Serialize)]
+struct CreatedApiKey {
+    #[serde(flatten)]
+    api_key: ApiKey,
+    key: String,
+}
+
+async fn record(db: &AppState, user_id: &Uuid, event: Event) {
+    if let Err(e) = Event::record_event(&db.db, Some(user_id), &event).await {
+        eprintln!("{:?}",e);
+    }
+}
+
+async fn create_key(db: &AppState, owner: &Uuid, body: CreateApiKeyBody) -> HttpResponse {
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > NAME_MAX_LENGTH {
+        return HttpResponse::build(StatusCode::BAD_REQUEST)
+                .body(format!("Name must have 1 to {} characters", NAME_MAX_LENGTH))
+    }
+
+    let mut scopes = body.scopes;
+    scopes.sort_unstable();
+    scopes.dedup();
+    if scopes.is_empty() {
+        return HttpResponse::build(StatusCode::BAD_REQUEST)
+                .body("At least one scope is required")
+    }
+
+    let role = match User::fetch_role(&db.db, owner).await {
+        Ok(role) => role,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    if scopes.contains(&ApiScope::Admin) && role != Role::Admin {
+        return HttpResponse::build(StatusCode::FORBIDDEN)
+                .body("Only keys of admins can have the admin scope")
+    }
+
+    let secret = ApiKeySecret::generate();
+
+    let api_key = match User::create_api_key(&db.db, owner, name, &scopes, &secret).await {
+        Ok(api_key) => api_key,
+        Err(e) => {
+            eprintln!("{:?}",e);
+            return HttpResponse::new(e.into())
+        },
+    };
+
+    let event = Event::ApiKeyCreated { key_id: api_key.id.clone(), name: api_key.name.clone(), scopes, actor: owner.clone() };
+    record(db, owner, event).await;
+
+    HttpResponse::build(StatusCode::CREATED)
+        .json(CreatedApiKey { api_key, key: secret.to_string() })
+}
+
+async fn list_keys(db: &AppState, owner: &Uuid) -> HttpResponse {
+    match User::fetch_api_keys(&db.db, owner).await {
+        Ok(api_keys) => HttpResponse::build(StatusCode::OK)
+                            .json(api_keys),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
+
+async fn revoke_key(db: &AppState, owner: &Uuid, actor: &Uuid, key_id: Uuid) -> HttpResponse {
+    match User::revoke_api_key(&db.db, owner, &key_id).await {
+        Ok(true) => {
+            record(db, owner, Event::ApiKeyRevoked { key_id, actor: actor.clone() }).await;
+
+            HttpResponse::new(StatusCode::NO_CONTENT)
+        },
+        Ok(false) => HttpResponse::build(StatusCode::NOT_FOUND)
+                        .body("API key not found"),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
+
+// Own keys, managed from a signed in session
+
+#[post("/me/api-keys")]
+async fn create_api_key(auth: AuthorizedUser, body: Json<CreateApiKeyBody>, db: Data<AppState>) -> impl Responder {
+    if let Err(e) = auth.session() {
+        return e.error_response()
+    }
+
+    create_key(&db, &auth.user_id, body.into_inner()).await
+}
+
+#[get("/me/api-keys")]
+async fn list_api_keys(auth: AuthorizedUser, db: Data<AppState>) -> impl Responder {
+    if let Err(e) = auth.session() {
+        return e.error_response()
+    }
+
+    list_keys(&db, &auth.user_id).await
+}
+
+#[delete("/me/api-keys/{key_id}")]
+async fn revoke_api_key(auth: AuthorizedUser, key_id: Path<Uuid>, db: Data<AppState>) -> impl Responder {
+    if let Err(e) = auth.session() {
+        return e.error_response()
+    }
+
+    revoke_key(&db, &auth.user_id, &auth.user_id, key_id.into_inner()).await
+}
+
+// Keys of any user. Admins can oversee and revoke them, but not create keys acting as someone else
+
+#[get("/users/{user_id}/api-keys")]
+async fn admin_list_api_keys(_admin: AdminUser, user_id: Path<Uuid>, db: Data<AppState>) -> impl Responder {
+    list_keys(&db, &user_id).await
+}
+
+#[delete("/users/{user_id}/api-keys/{key_id}")]
+async fn admin_revoke_api_key(admin: AdminUser, path: Path<(Uuid, Uuid)>, db: Data<AppState>) -> impl Responder {
+    let (user_id, key_id) = path.into_inner();
+
+    revoke_key(&db, &user_id, &admin.0.user_id, key_id).await
+}
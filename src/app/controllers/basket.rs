@@ -0,0 +1,63 @@
+use actix_web::{delete, get, http::StatusCode, post, web::{Data, Json, Path}, HttpResponse, Responder};
+use serde::Deserialize;
+
+use crate::{
+    app::{
+        extractors::AuthorizedUser,
+        models::{
+            basket::{BasketItem, BasketRepository, MAX_QUANTITY},
+            product::variant::{Variant, VariantRepository},
+            user::Uuid
+        }
+    },
+    AppState
+};
+
+#[get("")]
+async fn get_basket(auth: AuthorizedUser, db: Data<AppState>) -> impl Responder {
+    match BasketItem::fetch_basket(&db.db, &auth.user_id).await {
+        Ok(items) => HttpResponse::build(StatusCode::OK)
+                        .json(items),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
+
+#[derive(Deserialize)]
+struct AddBody {
+    variant_id: Uuid,
+    quantity: Option<i32>,
+}
+
+// Stock is checked at checkout, the basket only holds the wish
+#[post("")]
+async fn add_to_basket(auth: AuthorizedUser, body: Json<AddBody>, db: Data<AppState>) -> impl Responder {
+    let quantity = body.quantity.unwrap_or(1);
+
+    if !(1..=MAX_QUANTITY).contains(&quantity) {
+        return HttpResponse::build(StatusCode::BAD_REQUEST)
+                .body(format!("Quantity must be between 1 and {}", MAX_QUANTITY))
+    }
+
+    if let Err(e) = Variant::fetch_variant(&db.db, &body.variant_id).await {
+        return match StatusCode::from(e) {
+            StatusCode::NOT_FOUND => HttpResponse::build(StatusCode::NOT_FOUND)
+                                        .body("Variant not found"),
+            err_code => HttpResponse::new(err_code),
+        }
+    }
+
+    match BasketItem::add_to_basket(&db.db, &auth.user_id, &body.variant_id, quantity).await {
+        Ok(_) => HttpResponse::new(StatusCode::NO_CONTENT),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
+
+#[delete("/{item_id}")]
+async fn remove_from_basket(auth: AuthorizedUser, item_id: Path<Uuid>, db: Data<AppState>) -> impl Responder {
+    match BasketItem::remove_from_basket(&db.db, &auth.user_id, &item_id).await {
+        Ok(true) => HttpResponse::new(StatusCode::NO_CONTENT),
+        Ok(false) => HttpResponse::build(StatusCode::NOT_FOUND)
+                        .body("Item not in basket"),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
@@ -0,0 +1,251 @@
+use actix_web::{delete, get, http::StatusCode, post, put, web::{Data, Json, Path, Query}, HttpResponse, Responder};
+use lib_utils::validation::{FieldPath, ValidationErrors};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::{
+        extractors::AdminUser,
+        models::{
+            product::{
+                category::{Category, CategoryRepository, CategoryTree, Crumb, Slug, CATEGORY_NAME_RULES},
+                search::ProductEvent,
+                Product, ProductRepository
+            },
+            user::Uuid
+        }
+    },
+    AppState
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+async fn fetch_tree(db: &AppState) -> Result<CategoryTree, HttpResponse> {
+    Category::fetch_category_tree(&db.db)
+        .await
+        .map_err(|e| HttpResponse::new(e.into()))
+}
+
+// Browsing
+
+#[get("")]
+async fn list_categories(db: Data<AppState>) -> impl Responder {
+    match fetch_tree(&db).await {
+        Ok(tree) => HttpResponse::build(StatusCode::OK)
+                        .json(tree.nodes()),
+        Err(response) => response,
+    }
+}
+
+#[derive(Deserialize)]
+struct BrowseQuery {
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct CategoryPage<'a> {
+    #[serde(flatten)]
+    category: &'a Category,
+    breadcrumbs: Vec<Crumb>,
+    children: Vec<&'a Category>,
+    products: Vec<Product>,
+    total: i64,
+    page: i64,
+    per_page: i64,
+}
+
+// Products of the category and of every category below it
+#[get("/{slug}")]
+async fn browse_category(slug: Path<String>, query: Query<BrowseQuery>, db: Data<AppState>) -> impl Responder {
+    let tree = match fetch_tree(&db).await {
+        Ok(tree) => tree,
+        Err(response) => return response,
+    };
+
+    let Some(category) = tree.by_slug(&slug) else {
+        return HttpResponse::build(StatusCode::NOT_FOUND)
+                .body("Category not found")
+    };
+
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    let result = match Product::fetch_products_in_categories(&db.db, &tree.descendants(&category.id), per_page, (page - 1) * per_page).await {
+        Ok(result) => result,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    HttpResponse::build(StatusCode::OK)
+        .json(CategoryPage {
+            category,
+            breadcrumbs: tree.breadcrumbs(&category.id),
+            children: tree.children(Some(&category.id)),
+            products: result.products,
+            total: result.total,
+            page,
+            per_page,
+        })
+}
+
+// Management, under /admin
+
+#[derive(Deserialize)]
+struct CategoryBody {
+    name: String,
+    slug: String,
+    parent_id: Option<Uuid>,
+    #[serde(default)]
+    position: i32,
+}
+
+fn build_category(id: Uuid, body: CategoryBody) -> Result<Category, ValidationErrors<'static>> {
+    let mut validation_errors = ValidationErrors::new();
+
+    validation_errors.check(&FieldPath::root().field("name"), &body.name, &CATEGORY_NAME_RULES);
+
+    match Slug::parse(body.slug) {
+        Ok(slug) if validation_errors.is_empty() => Ok(Category { id, parent_id: body.parent_id, slug, name: body.name, position: body.position }),
+        Ok(_) => Err(validation_errors),
+        Err(err) => {
+            validation_errors.add(&FieldPath::root().field("slug"), err);
+            Err(validation_errors)
+        },
+    }
+}
+
+// Parent has to exist and can't be below the category
+fn parent_error(tree: &CategoryTree, category: &Category) -> Option<HttpResponse> {
+    if category.parent_id.as_ref().is_some_and(|parent_id| tree.get(parent_id).is_none()) {
+        return Some(HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .body("Parent category not found"))
+    }
+
+    (!tree.can_move(&category.id, category.parent_id.as_ref())).then(|| {
+        HttpResponse::build(StatusCode::CONFLICT)
+            .body("Category can't be moved below itself")
+    })
+}
+
+fn slug_conflict_or(e: crate::repository::db::SqlxError) -> HttpResponse {
+    match StatusCode::from(e) {
+        StatusCode::CONFLICT => HttpResponse::build(StatusCode::CONFLICT)
+                                    .body("Slug is already taken"),
+        err_code => HttpResponse::new(err_code),
+    }
+}
+
+#[post("/categories")]
+async fn create_category(_admin: AdminUser, body: Json<CategoryBody>, db: Data<AppState>) -> impl Responder {
+    let tree = match fetch_tree(&db).await {
+        Ok(tree) => tree,
+        Err(response) => return response,
+    };
+
+    let category = match build_category(Uuid::parse(uuid::Uuid::new_v4()), body.into_inner()) {
+        Ok(category) => category,
+        Err(validation_errors) => return HttpResponse::build(StatusCode::BAD_REQUEST)
+                                    .json(validation_errors),
+    };
+
+    if let Some(response) = parent_error(&tree, &category) {
+        return response
+    }
+
+    match Category::create_category(&db.db, &category).await {
+        Ok(_) => HttpResponse::build(StatusCode::CREATED)
+                    .json(category),
+        Err(e) => slug_conflict_or(e),
+    }
+}
+
+// Replaces the category, moving it when the parent changes
+#[put("/categories/{category_id}")]
+async fn update_category(_admin: AdminUser, category_id: Path<Uuid>, body: Json<CategoryBody>, db: Data<AppState>) -> impl Responder {
+    let tree = match fetch_tree(&db).await {
+        Ok(tree) => tree,
+        Err(response) => return response,
+    };
+
+    let Some(current) = tree.get(&category_id) else {
+        return HttpResponse::build(StatusCode::NOT_FOUND)
+                .body("Category not found")
+    };
+
+    let parent_id = current.parent_id.clone();
+
+    let category = match build_category(category_id.into_inner(), body.into_inner()) {
+        Ok(category) => category,
+        Err(validation_errors) => return HttpResponse::build(StatusCode::BAD_REQUEST)
+                                    .json(validation_errors),
+    };
+
+    if let Some(response) = parent_error(&tree, &category) {
+        return response
+    }
+
+    match Category::update_category(&db.db, &category).await {
+        Ok(true) => {
+            // Products below a moved category are listed under other ancestors
+            if category.parent_id != parent_id {
+                db.publish(ProductEvent::CategoriesChanged);
+            }
+
+            HttpResponse::build(StatusCode::OK)
+                .json(category)
+        },
+        Ok(false) => HttpResponse::build(StatusCode::NOT_FOUND)
+                        .body("Category not found"),
+        Err(e) => slug_conflict_or(e),
+    }
+}
+
+#[delete("/categories/{category_id}")]
+async fn delete_category(_admin: AdminUser, category_id: Path<Uuid>, db: Data<AppState>) -> impl Responder {
+    match Category::delete_category(&db.db, &category_id).await {
+        Ok(true) => {
+            db.publish(ProductEvent::CategoriesChanged);
+
+            HttpResponse::new(StatusCode::NO_CONTENT)
+        },
+        Ok(false) => HttpResponse::build(StatusCode::NOT_FOUND)
+                        .body("Category not found"),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
+
+#[derive(Deserialize)]
+struct ProductCategoriesBody {
+    category_ids: Vec<Uuid>,
+}
+
+// Replaces the categories the product is listed in
+#[put("/products/{product_id}/categories")]
+async fn set_product_categories(_admin: AdminUser, product_id: Path<Uuid>, body: Json<ProductCategoriesBody>, db: Data<AppState>) -> impl Responder {
+    let tree = match fetch_tree(&db).await {
+        Ok(tree) => tree,
+        Err(response) => return response,
+    };
+
+    if let Some(unknown) = body.category_ids.iter().find(|id| tree.get(id).is_none()) {
+        return HttpResponse::build(StatusCode::BAD_REQUEST)
+                .body(format!("Category {} not found", unknown))
+    }
+
+    if let Err(e) = Product::fetch_product(&db.db, &product_id).await {
+        return HttpResponse::new(e.into())
+    }
+
+    let mut category_ids = body.into_inner().category_ids;
+    category_ids.sort_by_key(|id| *id.as_bytes());
+    category_ids.dedup();
+
+    match Category::set_product_categories(&db.db, &product_id, &category_ids).await {
+        Ok(_) => {
+            db.publish(ProductEvent::Changed(vec![product_id.into_inner()]));
+
+            HttpResponse::new(StatusCode::NO_CONTENT)
+        },
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
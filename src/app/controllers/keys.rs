@@ -0,0 +1,11 @@
+use actix_web::{get, http::{header, StatusCode}, HttpResponse, Responder};
+
+use crate::app::models::user::keys::KEY_RING;
+
+// Public keys for verifying our tokens, shared secrets are never listed
+#[get("/jwks.json")]
+async fn jwks() -> impl Responder {
+    HttpResponse::build(StatusCode::OK)
+        .insert_header((header::CACHE_CONTROL, "public, max-age=300"))
+        .json(KEY_RING.jwks())
+}
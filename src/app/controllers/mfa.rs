@@ -0,0 +1,261 @@
+use actix_web::{delete, http::StatusCode, post, web::{Data, Json}, HttpResponse, Responder, ResponseError};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::{
+        cookies::DeliveryMode,
+        extractors::AuthorizedUser,
+        models::user::{
+            self,
+            auth,
+            mfa::{self, MfaRepository, TotpSecret},
+            one_time::{OneTimeToken, Purpose},
+            session::ClientInfo,
+            throttle::SignInThrottle,
+            Login, User, UserRepository
+        }
+    },
+    AppState
+};
+
+fn issuer() -> String {
+    std::env::var("TOTP_ISSUER").unwrap_or_else(|_| "CWShop".to_string())
+}
+
+// Second factor given by the user: current TOTP code or one of the recovery codes
+#[derive(Deserialize)]
+struct SecondFactor {
+    code: Option<String>,
+    recovery_code: Option<String>,
+}
+
+async fn verify_second_factor(db: &AppState, user_id: &user::Uuid, factor: &SecondFactor) -> Result<bool, HttpResponse> {
+    if let Some(recovery_code) = &factor.recovery_code {
+        return User::use_recovery_code(&db.db, user_id, &mfa::hash_recovery_code(recovery_code))
+            .await
+            .map_err(|e| HttpResponse::new(e.into()))
+    }
+
+    let Some(code) = &factor.code else {
+        return Ok(false)
+    };
+
+    let settings = User::fetch_totp(&db.db, user_id)
+        .await
+        .map_err(|e| HttpResponse::new(e.into()))?;
+
+    let Some(secret) = settings.secret.as_deref().and_then(TotpSecret::parse) else {
+        return Ok(false)
+    };
+
+    mfa::verify_totp(&mut db.cache(), user_id, &secret, code)
+        .await
+        .map_err(|e| e.error_response())
+}
+
+
+// Enrolment
+
+#[derive(Serialize)]
+struct TotpEnrolment {
+    secret: String,
+    otpauth_uri: String,
+}
+
+// Secret stays pending until confirmed with a code from the app
+#[post("/me/mfa/totp")]
+async fn enroll_totp(auth: AuthorizedUser, db: Data<AppState>) -> impl Responder {
+    if let Err(e) = auth.session() {
+        return e.error_response()
+    }
+
+    let user = match User::fetch_user(&db.db, auth.user_id.clone()).await {
+        Ok(user) => user,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    match User::fetch_totp(&db.db, &user.id).await {
+        Ok(settings) if settings.enabled => {
+            return HttpResponse::build(StatusCode::CONFLICT)
+                    .body("Two-factor authentication is already enabled")
+        },
+        Ok(_) => {},
+        Err(e) => return HttpResponse::new(e.into()),
+    }
+
+    let secret = TotpSecret::generate();
+
+    if let Err(e) = User::set_totp_secret(&db.db, &user.id, &secret).await {
+        return HttpResponse::new(e.into());
+    }
+
+    HttpResponse::build(StatusCode::OK)
+        .json(TotpEnrolment {
+            secret: secret.to_string(),
+            otpauth_uri: secret.otpauth_uri(&issuer(), &user.login.to_string()),
+        })
+}
+
+#[derive(Deserialize)]
+struct ConfirmTotpBody {
+    code: String,
+}
+
+#[derive(Serialize)]
+struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+
+#[post("/me/mfa/totp/confirm")]
+async fn confirm_totp(auth: AuthorizedUser, body: Json<ConfirmTotpBody>, db: Data<AppState>) -> impl Responder {
+    if let Err(e) = auth.session() {
+        return e.error_response()
+    }
+
+    let settings = match User::fetch_totp(&db.db, &auth.user_id).await {
+        Ok(settings) => settings,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    if settings.enabled {
+        return HttpResponse::build(StatusCode::CONFLICT)
+                .body("Two-factor authentication is already enabled")
+    }
+
+    let factor = SecondFactor { code: Some(body.code.clone()), recovery_code: None };
+    match verify_second_factor(&db, &auth.user_id, &factor).await {
+        Ok(true) => {},
+        Ok(false) => return HttpResponse::build(StatusCode::BAD_REQUEST)
+                            .body("Invalid code"),
+        Err(response) => return response,
+    }
+
+    let recovery_codes = mfa::generate_recovery_codes();
+    let hashes = recovery_codes.iter().map(|code| mfa::hash_recovery_code(code)).collect();
+
+    match User::enable_totp(&db.db, &auth.user_id, hashes).await {
+        Ok(_) => HttpResponse::build(StatusCode::OK)
+                    .json(RecoveryCodes { recovery_codes }),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
+
+#[delete("/me/mfa/totp")]
+async fn disable_totp(auth: AuthorizedUser, body: Json<SecondFactor>, db: Data<AppState>) -> impl Responder {
+    if let Err(e) = auth.session() {
+        return e.error_response()
+    }
+
+    match verify_second_factor(&db, &auth.user_id, &body).await {
+        Ok(true) => {},
+        Ok(false) => return HttpResponse::build(StatusCode::FORBIDDEN)
+                            .body("Invalid code"),
+        Err(response) => return response,
+    }
+
+    match User::disable_totp(&db.db, &auth.user_id).await {
+        Ok(_) => HttpResponse::new(StatusCode::NO_CONTENT),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
+
+
+// Second sign in step
+
+#[derive(Serialize)]
+pub struct MfaChallenge {
+    mfa_challenge: String,
+    expires_in: u64,
+}
+
+async fn issue_challenge(db: &AppState, user: &User) -> auth::Result<MfaChallenge> {
+    let token = OneTimeToken::issue(&mut db.cache(), Purpose::MfaChallenge, &user.id, &user.login.to_string()).await?;
+
+    Ok(MfaChallenge {
+        mfa_challenge: token.to_string(),
+        expires_in: Purpose::MfaChallenge.ttl(),
+    })
+}
+
+// Issued by sign in instead of tokens when the account has a second factor
+pub async fn challenge_if_enabled(db: &AppState, user: &User) -> Option<HttpResponse> {
+    match User::fetch_totp(&db.db, &user.id).await {
+        Ok(settings) if settings.enabled => Some(
+            match issue_challenge(db, user).await {
+                Ok(challenge) => HttpResponse::build(StatusCode::OK)
+                                    .json(challenge),
+                Err(e) => e.error_response(),
+            }
+        ),
+        Ok(_) => None,
+        Err(e) => Some(HttpResponse::new(e.into())),
+    }
+}
+
+#[derive(Deserialize)]
+struct SignInMfaBody {
+    mfa_challenge: String,
+    #[serde(flatten)]
+    factor: SecondFactor,
+    #[serde(default)]
+    mode: DeliveryMode,
+}
+
+#[post("/signin/mfa")]
+async fn sign_in_mfa(client: ClientInfo, body: Json<SignInMfaBody>, db: Data<AppState>) -> impl Responder {
+    let invalid_challenge = || HttpResponse::build(StatusCode::UNAUTHORIZED)
+                                .body("Invalid or expired MFA challenge");
+
+    let challenge = OneTimeToken::parse(Purpose::MfaChallenge, body.mfa_challenge.clone());
+
+    let claim = match challenge.claim(&mut db.cache()).await {
+        Ok(claim) => claim,
+        Err(auth::Error::InvalidSession) => return invalid_challenge(),
+        Err(e) => return e.error_response(),
+    };
+
+    // Codes are guessed against the same limits as passwords
+    let throttle = SignInThrottle::new(&Login::from(claim.subject), client.ip.clone());
+
+    match throttle.locked_for(&mut db.cache()).await {
+        Ok(Some(retry_after)) => return super::user::too_many_attempts(retry_after),
+        Ok(None) => {},
+        Err(e) => return e.error_response(),
+    }
+
+    match verify_second_factor(&db, &claim.user_id, &body.factor).await {
+        Ok(true) => {},
+        Ok(false) => {
+            return match throttle.register_failure(&mut db.cache()).await {
+                Ok(Some(retry_after)) => super::user::too_many_attempts(retry_after),
+                Ok(None) => HttpResponse::build(StatusCode::UNAUTHORIZED)
+                                .body("Invalid code"),
+                Err(e) => e.error_response(),
+            }
+        },
+        Err(response) => return response,
+    }
+
+    // Consumed only after the code is accepted, a typo doesn't restart sign in
+    if let Err(e) = challenge.consume(&mut db.cache()).await {
+        return match e {
+            auth::Error::InvalidSession => invalid_challenge(),
+            e => e.error_response(),
+        }
+    }
+
+    if let Err(e) = throttle.reset(&mut db.cache()).await {
+        return e.error_response()
+    }
+
+    let user = match User::fetch_user(&db.db, claim.user_id).await {
+        Ok(user) => user,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    if let Err(response) = super::user::sign_in_allowed(&db, &user).await {
+        return response
+    }
+
+    super::user::issue_tokens(&db, &user, &client, body.mode).await
+}
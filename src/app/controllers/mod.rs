@@ -1,9 +1,102 @@
-use actix_web::{web::{self, scope}, Responder, Scope};
+use actix_web::{web::scope, Scope};
 
+pub mod admin;
+pub mod api_key;
+pub mod basket;
+pub mod category;
+pub mod keys;
+pub mod mfa;
+pub mod oauth;
+pub mod order;
+pub mod product;
+pub mod session;
 pub mod user;
 
 pub fn services() -> Scope {
     scope("/user")
         .service(user::sign_up)
         .service(user::sign_in)
+        .service(user::sign_up_schema)
+        .service(user::sign_in_schema)
+        .service(user::get_me)
+        .service(user::patch_me)
+        .service(user::delete_me)
+        .service(user::export_me)
+        .service(user::change_password)
+        .service(user::forgot_password)
+        .service(user::reset_password)
+        .service(user::resend_verification)
+        .service(user::verify_email)
+        .service(mfa::enroll_totp)
+        .service(mfa::confirm_totp)
+        .service(mfa::disable_totp)
+        .service(mfa::sign_in_mfa)
+        .service(session::list_sessions)
+        .service(session::revoke_session)
+        .service(session::refresh)
+        .service(session::sign_out)
+        .service(api_key::create_api_key)
+        .service(api_key::list_api_keys)
+        .service(api_key::revoke_api_key)
+        .service(oauth::authorize)
+        .service(oauth::callback)
+        .service(oauth::link_identity)
+        .service(oauth::list_identities)
+        .service(oauth::unlink_identity)
+}
+
+pub fn admin() -> Scope {
+    scope("/admin")
+        .service(admin::list_users)
+        .service(admin::get_user)
+        .service(admin::create_users)
+        .service(admin::patch_users)
+        .service(admin::delete_users)
+        .service(admin::disable_user)
+        .service(admin::ban_user)
+        .service(admin::enable_user)
+        .service(admin::force_password_reset)
+        .service(admin::set_role)
+        .service(api_key::admin_list_api_keys)
+        .service(api_key::admin_revoke_api_key)
+        .service(category::create_category)
+        .service(category::update_category)
+        .service(category::delete_category)
+        .service(category::set_product_categories)
+        .service(product::create_product)
+        .service(product::update_product)
+        .service(product::delete_product)
+        .service(product::create_variant)
+        .service(product::update_variant)
+        .service(product::delete_variant)
+}
+
+pub fn products() -> Scope {
+    scope("/product")
+        .service(product::search_products)
+        .service(product::get_product)
+}
+
+pub fn baskets() -> Scope {
+    scope("/basket")
+        .service(basket::get_basket)
+        .service(basket::add_to_basket)
+        .service(basket::remove_from_basket)
+}
+
+pub fn orders() -> Scope {
+    scope("/order")
+        .service(order::checkout)
+        .service(order::list_orders)
+}
+
+pub fn categories() -> Scope {
+    scope("/category")
+        .service(category::list_categories)
+        .service(category::browse_category)
+}
+
+pub fn well_known() -> Scope {
+    scope("/.well-known")
+        .service(keys::jwks)
 }
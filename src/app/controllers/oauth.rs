@@ -0,0 +1,192 @@
+use actix_web::{delete, get, http::StatusCode, post, web::{Data, Json, Path}, HttpResponse, Responder, ResponseError};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::{
+        cookies::DeliveryMode,
+        extractors::AuthorizedUser,
+        models::{
+            audit::{AuditRepository, Event},
+            user::{
+                oauth::{self, ExternalIdentity, IdentityRepository, PendingLogin, IDENTITY_PROVIDERS},
+                session::ClientInfo,
+                User, UserRepository, Uuid
+            }
+        }
+    },
+    AppState
+};
+
+#[derive(Serialize)]
+struct AuthorizationRedirect {
+    authorization_url: String,
+}
+
+async fn start(db: &AppState, provider_name: &str, mode: DeliveryMode, link_user: Option<Uuid>) -> HttpResponse {
+    let provider = match IDENTITY_PROVIDERS.get(provider_name) {
+        Ok(provider) => provider,
+        Err(e) => return e.error_response(),
+    };
+
+    match PendingLogin::start(&mut db.cache(), provider_name, provider, mode, link_user).await {
+        Ok(authorization_url) => HttpResponse::build(StatusCode::OK)
+                                    .json(AuthorizationRedirect { authorization_url }),
+        Err(e) => e.error_response(),
+    }
+}
+
+async fn record(db: &AppState, user_id: &Uuid, identity: &ExternalIdentity) {
+    let event = Event::IdentityLinked { provider: identity.provider.clone(), subject: identity.subject.clone() };
+    if let Err(e) = Event::record_event(&db.db, Some(user_id), &event).await {
+        eprintln!("{:?}",e);
+    }
+}
+
+#[derive(Deserialize)]
+struct AuthorizeBody {
+    #[serde(default)]
+    mode: DeliveryMode,
+}
+
+// URL to send the user to, the provider sends them back to the frontend with
+// a code and the state, which it passes to the callback
+#[post("/oauth/{provider}/authorize")]
+async fn authorize(provider: Path<String>, body: Option<Json<AuthorizeBody>>, db: Data<AppState>) -> impl Responder {
+    let mode = body.map(|body| body.mode).unwrap_or_default();
+
+    start(&db, &provider, mode, None).await
+}
+
+#[derive(Deserialize)]
+struct CallbackBody {
+    code: String,
+    state: String,
+}
+
+// Link flows are finished from the session that started them, sign in flows need none
+#[post("/oauth/{provider}/callback")]
+async fn callback(auth: Option<AuthorizedUser>, client: ClientInfo, provider: Path<String>, body: Json<CallbackBody>, db: Data<AppState>) -> impl Responder {
+    let identity_provider = match IDENTITY_PROVIDERS.get(&provider) {
+        Ok(identity_provider) => identity_provider,
+        Err(e) => return e.error_response(),
+    };
+
+    let pending = match PendingLogin::take(&mut db.cache(), &provider, &body.state).await {
+        Ok(pending) => pending,
+        Err(e) => return e.error_response(),
+    };
+
+    let signed_in = auth.as_ref()
+        .filter(|auth| auth.session().is_ok())
+        .map(|auth| &auth.user_id);
+    if let Err(e) = pending.check_linker(signed_in) {
+        return e.error_response()
+    }
+
+    let identity = match pending.complete(identity_provider, &body.code).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            eprintln!("{:?}",e);
+            return e.error_response()
+        },
+    };
+
+    if let Some(user_id) = pending.link_user {
+        return match User::link_identity(&db.db, &user_id, &identity).await {
+            Ok(_) => {
+                record(&db, &user_id, &identity).await;
+
+                HttpResponse::new(StatusCode::NO_CONTENT)
+            },
+            Err(e) => match StatusCode::from(e) {
+                StatusCode::CONFLICT => HttpResponse::build(StatusCode::CONFLICT)
+                                            .body("Identity or provider is already linked"),
+                err_code => HttpResponse::new(err_code),
+            },
+        }
+    }
+
+    let user_id = match User::fetch_identity_owner(&db.db, &identity).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => match provision(&db, &identity).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        },
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    let user = match User::fetch_user(&db.db, user_id).await {
+        Ok(user) => user,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    if let Err(response) = super::user::sign_in_allowed(&db, &user).await {
+        return response
+    }
+
+    if let Some(challenge) = super::mfa::challenge_if_enabled(&db, &user).await {
+        return challenge
+    }
+
+    super::user::issue_tokens(&db, &user, &client, pending.mode).await
+}
+
+// Accounts are never merged by address, the owner links the identity while signed in
+async fn provision(db: &AppState, identity: &ExternalIdentity) -> Result<Uuid, HttpResponse> {
+    let user = oauth::provisioned_user(identity);
+    let user_id = user.id.clone();
+
+    match User::provision_user(&db.db, user, identity).await {
+        Ok(_) => {
+            record(db, &user_id, identity).await;
+
+            Ok(user_id)
+        },
+        Err(e) => Err(match User::taken_field(&e) {
+            Some("email") => HttpResponse::build(StatusCode::CONFLICT)
+                                .body("An account with this email exists, sign in and link the provider"),
+            _ => super::user::taken_or(e),
+        }),
+    }
+}
+
+// Linking, from a signed in session
+
+#[post("/me/identities/{provider}")]
+async fn link_identity(auth: AuthorizedUser, provider: Path<String>, db: Data<AppState>) -> impl Responder {
+    if let Err(e) = auth.session() {
+        return e.error_response()
+    }
+
+    start(&db, &provider, DeliveryMode::Token, Some(auth.user_id)).await
+}
+
+#[get("/me/identities")]
+async fn list_identities(auth: AuthorizedUser, db: Data<AppState>) -> impl Responder {
+    match User::fetch_identities(&db.db, &auth.user_id).await {
+        Ok(identities) => HttpResponse::build(StatusCode::OK)
+                            .json(identities),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
+
+#[delete("/me/identities/{provider}")]
+async fn unlink_identity(auth: AuthorizedUser, provider: Path<String>, db: Data<AppState>) -> impl Responder {
+    if let Err(e) = auth.session() {
+        return e.error_response()
+    }
+
+    match User::unlink_identity(&db.db, &auth.user_id, &provider).await {
+        Ok(true) => {
+            let event = Event::IdentityUnlinked { provider: provider.into_inner() };
+            if let Err(e) = Event::record_event(&db.db, Some(&auth.user_id), &event).await {
+                eprintln!("{:?}",e);
+            }
+
+            HttpResponse::new(StatusCode::NO_CONTENT)
+        },
+        Ok(false) => HttpResponse::build(StatusCode::NOT_FOUND)
+                        .body("Identity not linked"),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
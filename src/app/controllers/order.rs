@@ -0,0 +1,36 @@
+use actix_web::{get, http::StatusCode, post, web::Data, HttpResponse, Responder, ResponseError};
+
+use crate::{
+    app::{
+        extractors::{AuthorizedUser, VerifiedUser},
+        models::{order::{Order, OrderRepository}, product::search::ProductEvent}
+    },
+    AppState
+};
+
+// Places an order for everything in the basket
+#[post("/checkout")]
+async fn checkout(verified: VerifiedUser, db: Data<AppState>) -> impl Responder {
+    match Order::place_order(&db.db, &verified.0.user_id).await {
+        Ok(order) => {
+            let variant_ids = order.items.iter().filter_map(|item| item.variant_id.clone()).collect();
+            db.publish(ProductEvent::StockTaken(variant_ids));
+
+            HttpResponse::build(StatusCode::CREATED)
+                .json(order)
+        },
+        Err(e) => {
+            eprintln!("{:?}",e);
+            e.error_response()
+        },
+    }
+}
+
+#[get("")]
+async fn list_orders(auth: AuthorizedUser, db: Data<AppState>) -> impl Responder {
+    match Order::fetch_orders(&db.db, &auth.user_id).await {
+        Ok(orders) => HttpResponse::build(StatusCode::OK)
+                        .json(orders),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
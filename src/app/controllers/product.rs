@@ -0,0 +1,415 @@
+use actix_web::{delete, get, http::StatusCode, post, put, web::{Data, Json, Path, Query}, HttpResponse, Responder, ResponseError};
+use lib_utils::validation::{FieldPath, ValidationErrors};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::{
+        extractors::AdminUser,
+        models::{
+            product::{
+                category::{Category, CategoryRepository, Crumb},
+                search::{ProductEvent, SearchQuery, SearchResults, SortOrder},
+                validate_attributes,
+                variant::{Variant, VariantRepository},
+                Attributes, Product, ProductRepository, Sku, DESCRIPTION_RULES, TITLE_RULES
+            },
+            user::Uuid
+        }
+    },
+    AppState
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+const MAX_SEARCH_LENGTH: usize = 200;
+
+#[derive(Serialize)]
+struct VariantDetails {
+    #[serde(flatten)]
+    variant: Variant,
+    // Override or the product price
+    price_cents: i64,
+}
+
+#[derive(Serialize)]
+struct ProductDetails {
+    #[serde(flatten)]
+    product: Product,
+    variants: Vec<VariantDetails>,
+    // Path from the root for every category the product is listed in
+    categories: Vec<Vec<Crumb>>,
+}
+
+// Search
+
+#[derive(Deserialize)]
+struct SearchParams {
+    q: Option<String>,
+    // Slug, products below it are included
+    category: Option<String>,
+    min_price_cents: Option<i64>,
+    max_price_cents: Option<i64>,
+    min_rating: Option<f64>,
+    #[serde(default)]
+    in_stock: bool,
+    #[serde(default)]
+    sort: SortOrder,
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct SearchPage {
+    #[serde(flatten)]
+    results: SearchResults,
+    page: i64,
+    per_page: i64,
+}
+
+fn search_error(params: &SearchParams) -> Option<HttpResponse> {
+    if params.q.as_ref().is_some_and(|q| q.chars().count() > MAX_SEARCH_LENGTH) {
+        return Some(HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .body(format!("Search text can't be longer than {} characters", MAX_SEARCH_LENGTH)))
+    }
+
+    if let (Some(min), Some(max)) = (params.min_price_cents, params.max_price_cents) {
+        if min > max {
+            return Some(HttpResponse::build(StatusCode::BAD_REQUEST)
+                        .body("Minimum price is above the maximum price"))
+        }
+    }
+
+    if params.min_rating.is_some_and(|rating| !(0.0..=5.0).contains(&rating)) {
+        return Some(HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .body("Rating must be between 0 and 5"))
+    }
+
+    None
+}
+
+// Full text over title, description and attributes, with facet counts for
+// narrowing the results down
+#[get("/search")]
+async fn search_products(params: Query<SearchParams>, db: Data<AppState>) -> impl Responder {
+    if let Some(response) = search_error(&params) {
+        return response
+    }
+
+    let tree = match Category::fetch_category_tree(&db.db).await {
+        Ok(tree) => tree,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    let category_ids = match params.category.as_deref() {
+        Some(slug) => match tree.by_slug(slug) {
+            Some(category) => Some(tree.descendants(&category.id)),
+            None => return HttpResponse::build(StatusCode::NOT_FOUND)
+                            .body("Category not found"),
+        },
+        None => None,
+    };
+
+    let page = params.page.unwrap_or(1).max(1);
+    let per_page = params.per_page.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    let query = SearchQuery {
+        text: params.q.as_ref().map(|q| q.trim().to_string()).filter(|q| !q.is_empty()),
+        category_ids,
+        min_price_cents: params.min_price_cents,
+        max_price_cents: params.max_price_cents,
+        min_rating: params.min_rating,
+        in_stock_only: params.in_stock,
+        sort: params.sort,
+        limit: per_page,
+        offset: (page - 1) * per_page,
+    };
+
+    match db.search.search(&db.db, &query, &tree).await {
+        Ok(results) => HttpResponse::build(StatusCode::OK)
+                        .json(SearchPage { results, page, per_page }),
+        Err(e) => {
+            eprintln!("{:?}",e);
+            e.error_response()
+        },
+    }
+}
+
+// Details
+
+#[get("/{product_id}")]
+async fn get_product(product_id: Path<Uuid>, db: Data<AppState>) -> impl Responder {
+    let product = match Product::fetch_product(&db.db, &product_id).await {
+        Ok(product) => product,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    let variants = match Variant::fetch_variants(&db.db, &product.id).await {
+        Ok(variants) => variants,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    let category_ids = match Category::fetch_product_categories(&db.db, &product.id).await {
+        Ok(category_ids) => category_ids,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    let tree = match Category::fetch_category_tree(&db.db).await {
+        Ok(tree) => tree,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    let categories = category_ids
+        .iter()
+        .map(|category_id| tree.breadcrumbs(category_id))
+        .filter(|crumbs| !crumbs.is_empty())
+        .collect();
+
+    let variants = variants
+        .into_iter()
+        .map(|variant| VariantDetails { price_cents: variant.price_cents(&product), variant })
+        .collect();
+
+    HttpResponse::build(StatusCode::OK)
+        .json(ProductDetails { product, variants, categories })
+}
+
+// Management, under /admin
+
+fn sku_conflict_or(e: crate::repository::db::SqlxError) -> HttpResponse {
+    match StatusCode::from(e) {
+        StatusCode::CONFLICT => HttpResponse::build(StatusCode::CONFLICT)
+                                    .body("SKU is already taken"),
+        err_code => HttpResponse::new(err_code),
+    }
+}
+
+fn parse_sku(sku: String, validation_errors: &mut ValidationErrors<'static>) -> Option<Sku> {
+    Sku::parse(sku)
+        .map_err(|err| { validation_errors.add(&FieldPath::root().field("sku"), err); })
+        .ok()
+}
+
+#[derive(Deserialize)]
+struct ProductBody {
+    title: String,
+    #[serde(default)]
+    description: String,
+    sku: String,
+    price_cents: i64,
+    #[serde(default)]
+    attributes: Attributes,
+}
+
+fn build_product(id: Uuid, body: ProductBody) -> Result<Product, ValidationErrors<'static>> {
+    let mut validation_errors = ValidationErrors::new();
+
+    validation_errors.check(&FieldPath::root().field("title"), &body.title, &TITLE_RULES);
+    validation_errors.check(&FieldPath::root().field("description"), &body.description, &DESCRIPTION_RULES);
+
+    let sku = parse_sku(body.sku, &mut validation_errors);
+
+    match sku {
+        Some(sku) if validation_errors.is_empty() => Ok(Product {
+            id,
+            title: body.title,
+            description: body.description,
+            sku,
+            attributes: body.attributes,
+            price_cents: body.price_cents,
+            rating: 0.0,
+        }),
+        _ => Err(validation_errors),
+    }
+}
+
+// Prices and attributes are checked apart from the field rules
+fn product_error(price_cents: i64, attributes: &Attributes) -> Option<HttpResponse> {
+    if !(0..=99_999_999).contains(&price_cents) {
+        return Some(HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .body("Price must be between 0 and 99999999 cents"))
+    }
+
+    validate_attributes(attributes).err().map(|message| {
+        HttpResponse::build(StatusCode::BAD_REQUEST)
+            .body(message)
+    })
+}
+
+#[post("/products")]
+async fn create_product(_admin: AdminUser, body: Json<ProductBody>, db: Data<AppState>) -> impl Responder {
+    if let Some(response) = product_error(body.price_cents, &body.attributes) {
+        return response
+    }
+
+    let product = match build_product(Uuid::parse(uuid::Uuid::new_v4()), body.into_inner()) {
+        Ok(product) => product,
+        Err(validation_errors) => return HttpResponse::build(StatusCode::BAD_REQUEST)
+                                    .json(validation_errors),
+    };
+
+    match Product::create_product(&db.db, &product).await {
+        Ok(_) => {
+            db.publish(ProductEvent::Changed(vec![product.id.clone()]));
+
+            HttpResponse::build(StatusCode::CREATED)
+                .json(product)
+        },
+        Err(e) => sku_conflict_or(e),
+    }
+}
+
+// Replaces the product fields, rating and variants stay
+#[put("/products/{product_id}")]
+async fn update_product(_admin: AdminUser, product_id: Path<Uuid>, body: Json<ProductBody>, db: Data<AppState>) -> impl Responder {
+    if let Some(response) = product_error(body.price_cents, &body.attributes) {
+        return response
+    }
+
+    let current = match Product::fetch_product(&db.db, &product_id).await {
+        Ok(product) => product,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    let product = match build_product(current.id, body.into_inner()) {
+        Ok(product) => Product { rating: current.rating, ..product },
+        Err(validation_errors) => return HttpResponse::build(StatusCode::BAD_REQUEST)
+                                    .json(validation_errors),
+    };
+
+    match Product::update_product(&db.db, &product).await {
+        Ok(true) => {
+            db.publish(ProductEvent::Changed(vec![product.id.clone()]));
+
+            HttpResponse::build(StatusCode::OK)
+                .json(product)
+        },
+        Ok(false) => HttpResponse::build(StatusCode::NOT_FOUND)
+                        .body("Product not found"),
+        Err(e) => sku_conflict_or(e),
+    }
+}
+
+#[delete("/products/{product_id}")]
+async fn delete_product(_admin: AdminUser, product_id: Path<Uuid>, db: Data<AppState>) -> impl Responder {
+    match Product::delete_product(&db.db, &product_id).await {
+        Ok(true) => {
+            db.publish(ProductEvent::Deleted(product_id.into_inner()));
+
+            HttpResponse::new(StatusCode::NO_CONTENT)
+        },
+        Ok(false) => HttpResponse::build(StatusCode::NOT_FOUND)
+                        .body("Product not found"),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
+
+// Variants
+
+#[derive(Deserialize)]
+struct VariantBody {
+    sku: String,
+    #[serde(default)]
+    options: Attributes,
+    price_override_cents: Option<i64>,
+    #[serde(default)]
+    stock: i32,
+    #[serde(default)]
+    position: i32,
+}
+
+fn variant_error(body: &VariantBody) -> Option<HttpResponse> {
+    if body.stock < 0 {
+        return Some(HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .body("Stock can't be negative"))
+    }
+
+    product_error(body.price_override_cents.unwrap_or(0), &body.options)
+}
+
+fn build_variant(id: Uuid, product_id: Uuid, body: VariantBody) -> Result<Variant, ValidationErrors<'static>> {
+    let mut validation_errors = ValidationErrors::new();
+
+    match parse_sku(body.sku, &mut validation_errors) {
+        Some(sku) => Ok(Variant {
+            id,
+            product_id,
+            sku,
+            options: body.options,
+            price_override_cents: body.price_override_cents,
+            stock: body.stock,
+            position: body.position,
+        }),
+        None => Err(validation_errors),
+    }
+}
+
+#[post("/products/{product_id}/variants")]
+async fn create_variant(_admin: AdminUser, product_id: Path<Uuid>, body: Json<VariantBody>, db: Data<AppState>) -> impl Responder {
+    if let Err(e) = Product::fetch_product(&db.db, &product_id).await {
+        return HttpResponse::new(e.into())
+    }
+
+    if let Some(response) = variant_error(&body) {
+        return response
+    }
+
+    let variant = match build_variant(Uuid::parse(uuid::Uuid::new_v4()), product_id.into_inner(), body.into_inner()) {
+        Ok(variant) => variant,
+        Err(validation_errors) => return HttpResponse::build(StatusCode::BAD_REQUEST)
+                                    .json(validation_errors),
+    };
+
+    match Variant::create_variant(&db.db, &variant).await {
+        Ok(_) => {
+            db.publish(ProductEvent::Changed(vec![variant.product_id.clone()]));
+
+            HttpResponse::build(StatusCode::CREATED)
+                .json(variant)
+        },
+        Err(e) => sku_conflict_or(e),
+    }
+}
+
+#[put("/products/{product_id}/variants/{variant_id}")]
+async fn update_variant(_admin: AdminUser, path: Path<(Uuid, Uuid)>, body: Json<VariantBody>, db: Data<AppState>) -> impl Responder {
+    let (product_id, variant_id) = path.into_inner();
+
+    if let Some(response) = variant_error(&body) {
+        return response
+    }
+
+    let variant = match build_variant(variant_id, product_id, body.into_inner()) {
+        Ok(variant) => variant,
+        Err(validation_errors) => return HttpResponse::build(StatusCode::BAD_REQUEST)
+                                    .json(validation_errors),
+    };
+
+    match Variant::update_variant(&db.db, &variant).await {
+        Ok(true) => {
+            db.publish(ProductEvent::Changed(vec![variant.product_id.clone()]));
+
+            HttpResponse::build(StatusCode::OK)
+                .json(variant)
+        },
+        Ok(false) => HttpResponse::build(StatusCode::NOT_FOUND)
+                        .body("Variant not found"),
+        Err(e) => sku_conflict_or(e),
+    }
+}
+
+// Baskets lose the variant, orders keep their copy of it
+#[delete("/products/{product_id}/variants/{variant_id}")]
+async fn delete_variant(_admin: AdminUser, path: Path<(Uuid, Uuid)>, db: Data<AppState>) -> impl Responder {
+    let (product_id, variant_id) = path.into_inner();
+
+    match Variant::delete_variant(&db.db, &product_id, &variant_id).await {
+        Ok(true) => {
+            db.publish(ProductEvent::Changed(vec![product_id]));
+
+            HttpResponse::new(StatusCode::NO_CONTENT)
+        },
+        Ok(false) => HttpResponse::build(StatusCode::NOT_FOUND)
+                        .body("Variant not found"),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
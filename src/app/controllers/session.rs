@@ -0,0 +1,130 @@
+use actix_web::{delete, get, http::StatusCode, post, web::{Data, Json, Path}, HttpRequest, HttpResponse, Responder, ResponseError};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::{
+        cookies::{self, DeliveryMode},
+        extractors::AuthorizedUser,
+        models::{
+            audit::{AuditRepository, Event},
+            user::{auth::{self, Authorization, RefreshToken}, session::Session, User, UserRepository}
+        }
+    },
+    AppState
+};
+
+#[derive(Serialize)]
+struct SessionView {
+    #[serde(flatten)]
+    session: Session,
+    current: bool,
+}
+
+#[get("/me/sessions")]
+async fn list_sessions(auth: AuthorizedUser, db: Data<AppState>) -> impl Responder {
+    let current_session = match auth.session_id() {
+        Ok(session) => session,
+        Err(e) => return e.error_response(),
+    };
+
+    match Session::list(&mut db.cache(), &auth.user_id).await {
+        Ok(sessions) => {
+            let sessions: Vec<SessionView> = sessions
+                .into_iter()
+                .map(|session| SessionView { current: session.id == current_session, session })
+                .collect();
+
+            HttpResponse::build(StatusCode::OK)
+                .json(sessions)
+        },
+        Err(e) => e.error_response(),
+    }
+}
+
+#[delete("/me/sessions/{session_id}")]
+async fn revoke_session(auth: AuthorizedUser, session_id: Path<String>, db: Data<AppState>) -> impl Responder {
+    if let Err(e) = auth.session() {
+        return e.error_response()
+    }
+
+    match Session::revoke(&mut db.cache(), &auth.user_id, &session_id).await {
+        Ok(true) => HttpResponse::new(StatusCode::NO_CONTENT),
+        Ok(false) => HttpResponse::build(StatusCode::NOT_FOUND)
+                        .body("Session not found"),
+        Err(e) => e.error_response(),
+    }
+}
+
+// Ends the current session, in cookie mode the cookies are cleared too
+#[post("/signout")]
+async fn sign_out(auth: AuthorizedUser, db: Data<AppState>) -> impl Responder {
+    let session_id = match auth.session_id() {
+        Ok(session_id) => session_id,
+        Err(e) => return e.error_response(),
+    };
+
+    match Session::revoke(&mut db.cache(), &auth.user_id, &session_id).await {
+        Ok(_) => cookies::clear_cookies(HttpResponse::build(StatusCode::NO_CONTENT)),
+        Err(e) => e.error_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct RefreshBody {
+    refresh_token: String,
+}
+
+// Returns a new pair, the presented refresh token can't be used again.
+// Without a body the refresh cookie is used and the pair is set as cookies
+#[post("/token/refresh")]
+async fn refresh(req: HttpRequest, body: Option<Json<RefreshBody>>, db: Data<AppState>) -> impl Responder {
+    let (token, mode) = match (body, req.cookie(cookies::REFRESH_COOKIE)) {
+        (Some(body), _) => (body.into_inner().refresh_token, DeliveryMode::Token),
+        (None, Some(cookie)) => {
+            if let Err(e) = cookies::verify_csrf(&req) {
+                return e.error_response()
+            }
+            (cookie.value().to_string(), DeliveryMode::Cookie)
+        },
+        (None, None) => return HttpResponse::build(StatusCode::BAD_REQUEST)
+                                .body("Missing refresh token"),
+    };
+
+    let refresh = match RefreshToken::parse(token) {
+        Ok(refresh) => refresh,
+        Err(e) => return e.error_response(),
+    };
+
+    let claims = match refresh.decode() {
+        Ok(data) => data.claims,
+        Err(e) => return e.error_response(),
+    };
+
+    let user = match User::fetch_user(&db.db, claims.body.user_id.clone()).await {
+        Ok(user) => user,
+        Err(e) => {
+            let err_code: StatusCode = e.into();
+            return match err_code {
+                StatusCode::NOT_FOUND => auth::Error::InvalidSession.error_response(),
+                _ => HttpResponse::new(err_code),
+            }
+        },
+    };
+
+    if let Err(response) = super::user::sign_in_allowed(&db, &user).await {
+        return response
+    }
+
+    match user.refresh_tokens(&mut db.cache(), &refresh).await {
+        Ok(tokens) => cookies::tokens_response(HttpResponse::build(StatusCode::OK), tokens, mode),
+        Err(auth::Error::RefreshTokenReused) => {
+            let event = Event::RefreshTokenReuse { session_id: claims.body.sid };
+            if let Err(e) = Event::record_event(&db.db, Some(&user.id), &event).await {
+                eprintln!("{:?}",e);
+            }
+
+            auth::Error::RefreshTokenReused.error_response()
+        },
+        Err(e) => e.error_response(),
+    }
+}
@@ -1,61 +1,114 @@
-use std::{collections::HashMap, fmt::Debug};
+use actix_web::{delete, get, http::{header, StatusCode}, patch, post, web::{self, Data, Json}, HttpRequest, HttpResponse, Responder, ResponseError};
+use lib_utils::validation::{object_schema, validate_rules, FieldPath, ValidationErrors};
+use serde::{Deserialize, Serialize};
 
-use actix_web::{http::StatusCode, post, web::{Data, Json}, HttpRequest, HttpResponse, Responder};
-use lib_utils::validation;
-use serde::Deserialize;
-
-use crate::{app::models::user::{self, Login, Name, Password, User, UserRepository}, AppState};
+use crate::{
+    app::{
+        cookies::{self, DeliveryMode},
+        extractors::AuthorizedUser,
+        models::{audit::{AuditRepository, Event}, user::{self, account::{AccountBlocked, AccountRepository}, auth::{self, Authorization}, export::ExportRepository, one_time::{OneTimeToken, Purpose}, session::ClientInfo, throttle::{ResetThrottle, SignInThrottle}, Email, EmailVerificationRepository, Login, Name, NotRecentlyUsed, Password, PasswordHash, PasswordHistoryRepository, PasswordRepository, User, UserRepository, PASSWORD_HISTORY_SIZE}}
+    },
+    mailer::Message,
+    repository::db::SqlxError,
+    AppState
+};
 
 
 #[derive(Deserialize)]
 struct SignUpBody {
     login: String,
     password: String,
-    name: String
+    name: String,
+    // Optional until verified email is required to sign up
+    email: Option<String>,
+}
+
+impl SignUpBody {
+    fn schema() -> serde_json::Value {
+        object_schema(
+            &[
+                ("login", Login::schema()),
+                ("password", Password::schema()),
+                ("name", Name::schema()),
+                ("email", Email::schema()),
+            ],
+            &["login", "password", "name"]
+        )
+    }
+}
+
+// Names the field another account already has. Any other unique violation
+// says nothing to the client about the rest of the accounts
+pub(super) fn taken_or(err: SqlxError) -> HttpResponse {
+    if let Some(field) = User::taken_field(&err) {
+        return HttpResponse::build(StatusCode::CONFLICT)
+                .body(format!("User with this {} already exists", field))
+    }
+
+    match StatusCode::from(err) {
+        StatusCode::CONFLICT => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+        err_code => HttpResponse::new(err_code),
+    }
+}
+
+#[get("/signup/schema")]
+pub async fn sign_up_schema() -> impl Responder {
+    HttpResponse::build(StatusCode::OK)
+        .json(SignUpBody::schema())
 }
 
 #[post("/signup")]
 pub async fn sign_up(_req: HttpRequest, body: Json<SignUpBody>, db: Data<AppState>) -> impl Responder {
-    let mut validation_errors: HashMap<String,Vec<validation::Error<'static>>> = HashMap::new();
+    let mut validation_errors = ValidationErrors::new();
     let mut user_builder = user::Builder::new();
 
+    let mut password: Option<Password> = None;
     match Password::parse(body.password.clone()) {
-        Ok(parsed_password) => { user_builder.password(parsed_password); },
-        Err(err) => { validation_errors.insert("Password".to_string(),err); },
+        Ok(parsed_password) => { password = Some(parsed_password); },
+        Err(err) => { validation_errors.add(&FieldPath::root().field("password"), err); },
     };
     
     match Login::parse(body.login.clone()) {
         Ok(parsed_login) => { user_builder.login(parsed_login); },
-        Err(err) => {validation_errors.insert("Login".to_string(),err); },
+        Err(err) => {validation_errors.add(&FieldPath::root().field("login"), err); },
     };
         
     match Name::parse(body.name.clone()) {
         Ok(parsed_name) => { user_builder.name(parsed_name); },
-        Err(err) => { validation_errors.insert("Name".to_string(),err); },
+        Err(err) => { validation_errors.add(&FieldPath::root().field("name"), err); },
     };
 
+    let mut email: Option<Email> = None;
+    if let Some(body_email) = &body.email {
+        match Email::parse(body_email.clone()) {
+            Ok(parsed_email) => {
+                user_builder.email(parsed_email.clone());
+                email = Some(parsed_email);
+            },
+            Err(err) => { validation_errors.add(&FieldPath::root().field("email"), err); },
+        }
+    }
+
     if validation_errors.is_empty() {
         user_builder.id(user::Uuid::parse(uuid::Uuid::new_v4()));
 
         let user: User = user_builder
             .try_get()
             .expect("Error building user");
+        let user_id = user.id.clone();
+        let password = password.expect("Error building user");
 
-        let res = User::create_user(&db.db, user).await;
+        let res = User::create_user(&db.db, user, password).await;
 
         match res {
             Ok(_) => {
-                HttpResponse::new(StatusCode::CREATED)
-            },
-            Err(err) => {
-                let respose_code: StatusCode = err.into();
-
-                match respose_code {
-                    StatusCode::CONFLICT => HttpResponse::build(respose_code)
-                                                            .body("User already exist"),
-                    _ => HttpResponse::new(respose_code)
+                if let Some(email) = email {
+                    send_verification(&db, &user_id, &email).await;
                 }
+
+                HttpResponse::new(StatusCode::CREATED)
             },
+            Err(err) => taken_or(err),
         }
     } else {
         HttpResponse::build(StatusCode::BAD_REQUEST)
@@ -68,55 +121,596 @@ pub async fn sign_up(_req: HttpRequest, body: Json<SignUpBody>, db: Data<AppStat
 struct SignInBody {
     login: String,
     password: String,
+    #[serde(default)]
+    mode: DeliveryMode,
+}
+
+impl SignInBody {
+    fn schema() -> serde_json::Value {
+        object_schema(
+            &[
+                ("login", Login::schema()),
+                ("password", Password::schema()),
+                ("mode", DeliveryMode::schema()),
+            ],
+            &["login", "password"]
+        )
+    }
+}
+
+#[get("/signin/schema")]
+pub async fn sign_in_schema() -> impl Responder {
+    HttpResponse::build(StatusCode::OK)
+        .json(SignInBody::schema())
+}
+
+// Checked once the user is identified, whichever way they sign in
+pub(super) async fn sign_in_allowed(db: &AppState, user: &User) -> Result<(), HttpResponse> {
+    match User::fetch_status(&db.db, &user.id).await {
+        Ok(status) if status.is_active() => Ok(()),
+        Ok(status) => Err(AccountBlocked(status).error_response()),
+        Err(e) => Err(HttpResponse::new(e.into())),
+    }
+}
+
+// Starts a session for a user who passed every sign in step
+pub(super) async fn issue_tokens(db: &AppState, user: &User, client: &ClientInfo, mode: DeliveryMode) -> HttpResponse {
+    match user.genrate_tokens(&mut db.cache(), client).await {
+        Ok(tokens) => cookies::tokens_response(HttpResponse::build(StatusCode::OK), tokens, mode),
+        Err(e) => {
+            eprintln!("{:?}",e);
+            HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+        },
+    }
+}
+
+pub(super) fn too_many_attempts(retry_after: u64) -> HttpResponse {
+    HttpResponse::build(StatusCode::TOO_MANY_REQUESTS)
+        .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+        .body("Too many failed sign in attempts")
 }
 
 #[post("/signin")]
-async fn sign_in(_req: HttpRequest, body: Json<SignInBody>, db: Data<AppState>) -> impl Responder {
-    let mut validation_errors: HashMap<String,Vec<validation::Error<'static>>> = HashMap::new();
+async fn sign_in(client: ClientInfo, body: Json<SignInBody>, db: Data<AppState>) -> impl Responder {
+    let mut validation_errors = ValidationErrors::new();
 
     let mut login: Option<Login> = None;
     match Login::parse(body.login.to_string()) {
         Ok(val) => { login = Some(val) },
-        Err(e) => { validation_errors.insert("Login".to_string(), e); },
+        Err(e) => { validation_errors.add(&FieldPath::root().field("login"), e); },
     }
 
-    let mut password: Option<Password> = None;
-    match Password::parse(body.password.to_string()) {
-        Ok(val) => { password = Some(val) },
-        Err(e) => { validation_errors.insert("Password".to_string(), e); },
-    }
+    // Password policy applies to new passwords only, accounts created
+    // under older rules still have to be able to sign in
+    let password = Password::from(body.password.to_string());
 
-    if !validation_errors.is_empty() {
+    let Some(login) = login.filter(|_| validation_errors.is_empty()) else {
         return HttpResponse::build(StatusCode::BAD_REQUEST)
                 .json(validation_errors)
+    };
+
+    let throttle = SignInThrottle::new(&login, client.ip.clone());
+
+    match throttle.locked_for(&mut db.cache()).await {
+        Ok(Some(retry_after)) => return too_many_attempts(retry_after),
+        Ok(None) => {},
+        Err(e) => return e.error_response(),
     }
 
     let user = User::get_user(
         &db.db,
         user::UserSearch {
-            login,
-            password,
+            login: Some(login.clone()),
             id: None,
             name: None
         }
     ).await;
 
+    // Wrong password answers as an unknown login does
+    let user = match user {
+        Ok(user) => match User::check_password(&db.db, &user.id, &password).await {
+            Ok(true) => Ok(user),
+            Ok(false) => Err(SqlxError::from(sqlx::Error::RowNotFound)),
+            Err(e) => Err(e),
+        },
+        Err(e) => {
+            PasswordHash::verify_missing(&password);
+            Err(e)
+        },
+    };
+
     match user {
         Ok(user) => {
+            if let Err(response) = sign_in_allowed(&db, &user).await {
+                return response
+            }
+
+            // Failures are reset only once the second factor is passed too
+            if let Some(challenge) = super::mfa::challenge_if_enabled(&db, &user).await {
+                return challenge
+            }
+
+            if let Err(e) = throttle.reset(&mut db.cache()).await {
+                return e.error_response()
+            }
+
+            issue_tokens(&db, &user, &client, body.mode).await
+        },
+        Err(e) => {
+            eprintln!("{:?}",e);
+
+            let err_code: StatusCode = e.into();
+            if err_code == StatusCode::INTERNAL_SERVER_ERROR {
+                return HttpResponse::new(err_code)
+            }
+
+            let lockout = match throttle.register_failure(&mut db.cache()).await {
+                Ok(lockout) => lockout,
+                Err(e) => return e.error_response(),
+            };
+
+            match lockout {
+                Some(retry_after) => {
+                    let event = Event::SignInLockout { login: login.to_string(), ip: client.ip, retry_after };
+                    if let Err(e) = Event::record_event(&db.db, None, &event).await {
+                        eprintln!("{:?}",e);
+                    }
+
+                    too_many_attempts(retry_after)
+                },
+                None => HttpResponse::build(err_code)
+                            .body("Wrong login or password"),
+            }
+        },
+    }
+
+}
+
+
+// Current user profile
+
+#[derive(Serialize)]
+struct Profile {
+    id: user::Uuid,
+    name: Name,
+    login: Login,
+    email: Option<Email>,
+    email_verified: bool,
+}
+
+impl Profile {
+    fn new(user: &User, email_verified: bool) -> Self {
+        Profile {
+            id: user.id.clone(),
+            name: user.name.clone(),
+            login: user.login.clone(),
+            email: user.email.clone(),
+            email_verified,
+        }
+    }
+}
+
+#[get("/me")]
+async fn get_me(auth: AuthorizedUser, db: Data<AppState>) -> impl Responder {
+    let user = match User::fetch_user(&db.db, auth.user_id.clone()).await {
+        Ok(user) => user,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    match User::is_email_verified(&db.db, &auth.user_id).await {
+        Ok(email_verified) => HttpResponse::build(StatusCode::OK)
+                                .json(Profile::new(&user, email_verified)),
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
+
+#[derive(Deserialize)]
+struct PatchMeBody {
+    name: Option<String>,
+    login: Option<String>,
+    email: Option<String>,
+}
+
+#[patch("/me")]
+async fn patch_me(auth: AuthorizedUser, body: Json<PatchMeBody>, db: Data<AppState>) -> impl Responder {
+    if let Err(e) = auth.session() {
+        return e.error_response()
+    }
+
+    let mut validation_errors = ValidationErrors::new();
+
+    let mut user = match User::fetch_user(&db.db, auth.user_id).await {
+        Ok(user) => user,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    if let Some(name) = &body.name {
+        match Name::parse(name.clone()) {
+            Ok(parsed_name) => { user.name = parsed_name; },
+            Err(err) => { validation_errors.add(&FieldPath::root().field("name"), err); },
+        }
+    }
+
+    if let Some(login) = &body.login {
+        match Login::parse(login.clone()) {
+            Ok(parsed_login) => { user.login = parsed_login; },
+            Err(err) => { validation_errors.add(&FieldPath::root().field("login"), err); },
+        }
+    }
+
+    let previous_email = user.email.clone();
+    if let Some(email) = &body.email {
+        match Email::parse(email.clone()) {
+            Ok(parsed_email) => { user.email = Some(parsed_email); },
+            Err(err) => { validation_errors.add(&FieldPath::root().field("email"), err); },
+        }
+    }
+
+    if !validation_errors.is_empty() {
+        return HttpResponse::build(StatusCode::BAD_REQUEST)
+                .json(validation_errors)
+    }
+
+    // Changed address has to be verified again
+    let email_changed = user.email != previous_email;
+    let email_verified = match User::is_email_verified(&db.db, &user.id).await {
+        Ok(verified) => verified && !email_changed,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    let profile = Profile::new(&user, email_verified);
+
+    match User::patch_user(&db.db, user).await {
+        Ok(_) => {
+            if let (true, Some(email)) = (email_changed, &profile.email) {
+                send_verification(&db, &profile.id, email).await;
+            }
+
             HttpResponse::build(StatusCode::OK)
-                .json(user)
+                .json(profile)
         },
+        Err(err) => taken_or(err),
+    }
+}
+
+#[delete("/me")]
+async fn delete_me(auth: AuthorizedUser, db: Data<AppState>) -> impl Responder {
+    if let Err(e) = auth.session() {
+        return e.error_response()
+    }
+
+    if let Err(e) = User::delete_user(&db.db, auth.user_id.clone()).await {
+        return HttpResponse::new(e.into());
+    }
+
+    match auth::revoke_user_sessions(&mut db.cache(), &auth.user_id, None).await {
+        Ok(_) => HttpResponse::new(StatusCode::NO_CONTENT),
+        Err(e) => {
+            eprintln!("{:?}",e);
+            HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+        },
+    }
+}
+
+// Personal data archive, downloaded as a JSON file
+#[get("/me/export")]
+async fn export_me(auth: AuthorizedUser, db: Data<AppState>) -> impl Responder {
+    if let Err(e) = auth.session() {
+        return e.error_response()
+    }
+
+    let export = match User::export_user_data(&db.db, &auth.user_id).await {
+        Ok(export) => export,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    if let Err(e) = Event::record_event(&db.db, Some(&auth.user_id), &Event::DataExported {}).await {
+        eprintln!("{:?}",e);
+    }
+
+    HttpResponse::build(StatusCode::OK)
+        .insert_header((header::CONTENT_DISPOSITION, format!("attachment; filename=\"cwshop-export-{}.json\"", auth.user_id)))
+        .json(export)
+}
+
+#[derive(Deserialize)]
+struct ChangePasswordBody {
+    current_password: String,
+    new_password: String,
+}
+
+#[post("/me/password")]
+async fn change_password(auth: AuthorizedUser, body: Json<ChangePasswordBody>, db: Data<AppState>) -> impl Responder {
+    let current_session = match auth.session_id() {
+        Ok(session) => session,
+        Err(e) => return e.error_response(),
+    };
+
+    let user_id = auth.user_id.clone();
+
+    match User::check_password(&db.db, &user_id, &Password::from(body.current_password.clone())).await {
+        Ok(true) => {},
+        Ok(false) => return HttpResponse::build(StatusCode::FORBIDDEN)
+                            .body("Wrong current password"),
+        Err(e) => return HttpResponse::new(e.into()),
+    }
+
+    let new_password = match parse_new_password(&db, &user_id, body.new_password.clone()).await {
+        Ok(password) => password,
+        Err(response) => return response,
+    };
+
+    if let Err(err) = User::change_password(&db.db, &user_id, new_password).await {
+        return taken_or(err);
+    }
+
+    match auth::revoke_user_sessions(&mut db.cache(), &user_id, Some(&current_session)).await {
+        Ok(_) => HttpResponse::new(StatusCode::NO_CONTENT),
         Err(e) => {
             eprintln!("{:?}",e);
+            HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+        },
+    }
+}
+
+fn new_password_errors<'a>(errors: impl IntoIterator<Item = lib_utils::validation::Error<'a>>) -> ValidationErrors<'a> {
+    let mut validation_errors = ValidationErrors::new();
+    validation_errors.add(&FieldPath::root().field("new_password"), errors);
+    validation_errors
+}
+
+// Applies password rules and forbids reuse of the recent passwords
+async fn parse_new_password(db: &AppState, user_id: &user::Uuid, new_password: String) -> Result<Password, HttpResponse> {
+    let new_password = match Password::parse(new_password) {
+        Ok(password) => password,
+        Err(err) => {
+            return Err(HttpResponse::build(StatusCode::BAD_REQUEST)
+                        .json(new_password_errors(err)))
+        },
+    };
+
+    let recent_passwords = match User::fetch_recent_passwords(&db.db, user_id, PASSWORD_HISTORY_SIZE).await {
+        Ok(recent_passwords) => recent_passwords,
+        Err(e) => return Err(HttpResponse::new(e.into())),
+    };
+
+    let not_recently_used = NotRecentlyUsed::new(user_id.clone(), recent_passwords);
+    let reuse_errors = validate_rules(&new_password, &[&not_recently_used]);
+    if !reuse_errors.is_empty() {
+        return Err(HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(new_password_errors(reuse_errors)))
+    }
+
+    Ok(new_password)
+}
+
 
+// Password reset
+
+#[derive(Deserialize)]
+struct ForgotPasswordBody {
+    login: String,
+}
+
+pub(super) fn reset_message(to: String, token: &OneTimeToken) -> Message {
+    let link = std::env::var("PASSWORD_RESET_URL")
+        .map(|url| format!("\n\n{}?token={}", url, token))
+        .unwrap_or_default();
+
+    Message {
+        to,
+        subject: "Password reset".to_string(),
+        body: format!(
+            "Use this code to reset your password: {}\nIt expires in {} minutes, ignore this message if you didn't ask for it.{}",
+            token,
+            Purpose::PasswordReset.ttl() / 60,
+            link
+        ),
+    }
+}
+
+// Answers the same whether the account exists or not. Every request counts
+// against the address, a mailbox over its limit silently gets no more mail
+#[post("/password/forgot")]
+async fn forgot_password(client: ClientInfo, body: Json<ForgotPasswordBody>, db: Data<AppState>) -> impl Responder {
+    let accepted = HttpResponse::new(StatusCode::ACCEPTED);
+    let throttle = ResetThrottle::new(client.ip.clone());
+
+    match throttle.ip_limited(&mut db.cache()).await {
+        Ok(Some(retry_after)) => return HttpResponse::build(StatusCode::TOO_MANY_REQUESTS)
+                                    .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+                                    .body("Too many password reset requests"),
+        Ok(None) => {},
+        Err(e) => return e.error_response(),
+    }
+
+    let Ok(login) = Login::parse(body.login.clone()) else {
+        return accepted
+    };
+
+    let user = User::get_user(
+        &db.db,
+        user::UserSearch {
+            login: Some(login),
+            id: None,
+            name: None
+        }
+    ).await;
+
+    let user = match user {
+        Ok(user) => user,
+        Err(e) => {
             let err_code: StatusCode = e.into();
-           
-            match err_code {
-                StatusCode::INTERNAL_SERVER_ERROR => HttpResponse::new(err_code),
-                _ => HttpResponse::build(err_code)
-                        .body("Wrong login or password")
+            if err_code != StatusCode::NOT_FOUND {
+                eprintln!("{:?}",err_code);
             }
+            return accepted
         },
+    };
+
+    let email = match User::fetch_user_email(&db.db, &user.id).await {
+        Ok(Some(email)) => email,
+        Ok(None) => return accepted,
+        Err(e) => {
+            eprintln!("{:?}",e);
+            return accepted
+        },
+    };
+
+    match throttle.allows_mail(&email, &mut db.cache()).await {
+        Ok(true) => {},
+        Ok(false) => return accepted,
+        Err(e) => {
+            eprintln!("{:?}",e);
+            return accepted
+        },
+    }
+
+    let token = match OneTimeToken::issue(&mut db.cache(), Purpose::PasswordReset, &user.id, "").await {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("{:?}",e);
+            return accepted
+        },
+    };
+
+    send_mail(&db, reset_message(email, &token)).await;
+
+    accepted
+}
+
+#[derive(Deserialize)]
+struct ResetPasswordBody {
+    token: String,
+    new_password: String,
+}
+
+#[post("/password/reset")]
+async fn reset_password(body: Json<ResetPasswordBody>, db: Data<AppState>) -> impl Responder {
+    let invalid_token = || HttpResponse::build(StatusCode::BAD_REQUEST)
+                            .body("Invalid or expired reset token");
+
+    let token = OneTimeToken::parse(Purpose::PasswordReset, body.token.clone());
+
+    // Token is only looked up here, a rejected password doesn't use it up
+    let user_id = match token.claim(&mut db.cache()).await {
+        Ok(claim) => claim.user_id,
+        Err(auth::Error::InvalidSession) => return invalid_token(),
+        Err(e) => return e.error_response(),
+    };
+
+    let new_password = match parse_new_password(&db, &user_id, body.new_password.clone()).await {
+        Ok(password) => password,
+        Err(response) => return response,
+    };
+
+    let user_id = match token.consume(&mut db.cache()).await {
+        Ok(claim) => claim.user_id,
+        Err(auth::Error::InvalidSession) => return invalid_token(),
+        Err(e) => return e.error_response(),
+    };
+
+    if let Err(err) = User::change_password(&db.db, &user_id, new_password).await {
+        return taken_or(err);
+    }
+
+    match auth::revoke_user_sessions(&mut db.cache(), &user_id, None).await {
+        Ok(_) => HttpResponse::new(StatusCode::NO_CONTENT),
+        Err(e) => {
+            eprintln!("{:?}",e);
+            HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+        },
+    }
+}
+
+
+// Email verification
+
+fn verification_message(to: String, token: &OneTimeToken) -> Message {
+    let link = std::env::var("EMAIL_VERIFICATION_URL")
+        .map(|url| format!("\n\n{}?token={}", url, token))
+        .unwrap_or_default();
+
+    Message {
+        to,
+        subject: "Confirm your email address".to_string(),
+        body: format!(
+            "Use this code to confirm your email address: {}\nIt expires in {} hours.{}",
+            token,
+            Purpose::EmailVerification.ttl() / 3600,
+            link
+        ),
+    }
+}
+
+// Failures are only logged, verification can be requested again
+async fn send_verification(db: &AppState, user_id: &user::Uuid, email: &Email) {
+    let token = match OneTimeToken::issue(&mut db.cache(), Purpose::EmailVerification, user_id, &email.to_string()).await {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("{:?}",e);
+            return
+        },
+    };
+
+    send_mail(db, verification_message(email.to_string(), &token)).await;
+}
+
+pub(super) async fn send_mail(db: &AppState, message: Message) {
+    let mailer = db.mailer.clone();
+
+    match web::block(move || mailer.send(&message)).await {
+        Ok(Ok(_)) => {},
+        Ok(Err(e)) => eprintln!("{:?}",e),
+        Err(e) => eprintln!("{:?}",e),
     }
+}
 
+#[post("/me/email/verification")]
+async fn resend_verification(auth: AuthorizedUser, db: Data<AppState>) -> impl Responder {
+    let user = match User::fetch_user(&db.db, auth.user_id.clone()).await {
+        Ok(user) => user,
+        Err(e) => return HttpResponse::new(e.into()),
+    };
+
+    let Some(email) = user.email else {
+        return HttpResponse::build(StatusCode::CONFLICT)
+                .body("No email address to verify")
+    };
+
+    match User::is_email_verified(&db.db, &user.id).await {
+        Ok(true) => HttpResponse::build(StatusCode::CONFLICT)
+                        .body("Email address is already verified"),
+        Ok(false) => {
+            send_verification(&db, &user.id, &email).await;
+            HttpResponse::new(StatusCode::ACCEPTED)
+        },
+        Err(e) => HttpResponse::new(e.into()),
+    }
+}
+
+#[derive(Deserialize)]
+struct VerifyEmailBody {
+    token: String,
+}
+
+#[post("/email/verify")]
+async fn verify_email(body: Json<VerifyEmailBody>, db: Data<AppState>) -> impl Responder {
+    let invalid_token = || HttpResponse::build(StatusCode::BAD_REQUEST)
+                            .body("Invalid or expired verification token");
+
+    let token = OneTimeToken::parse(Purpose::EmailVerification, body.token.clone());
+
+    let claim = match token.consume(&mut db.cache()).await {
+        Ok(claim) => claim,
+        Err(auth::Error::InvalidSession) => return invalid_token(),
+        Err(e) => return e.error_response(),
+    };
+
+    // Address could have been changed after the token was sent
+    match User::verify_email(&db.db, &claim.user_id, &Email::from(claim.subject)).await {
+        Ok(true) => HttpResponse::new(StatusCode::NO_CONTENT),
+        Ok(false) => invalid_token(),
+        Err(e) => HttpResponse::new(e.into()),
+    }
 }
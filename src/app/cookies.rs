@@ -0,0 +1,121 @@
+use std::sync::LazyLock;
+
+use actix_web::{cookie::{time::Duration, Cookie, SameSite}, http::{Method, StatusCode}, HttpRequest, HttpResponse, HttpResponseBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::app::{models::user::auth::{Tokens, ACCESS_TOKEN_TTL, REFRESH_TOKEN_TTL}, secrets::constant_time_eq};
+
+// Cookie session mode, for browsers: tokens are kept in HttpOnly cookies out of
+// reach of scripts, state changing requests have to echo the readable CSRF
+// cookie in the CSRF header (double submit)
+
+pub const ACCESS_COOKIE: &str = "access_token";
+pub const REFRESH_COOKIE: &str = "refresh_token";
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+// Refresh cookie is only sent to the refresh endpoint
+const REFRESH_COOKIE_PATH: &str = "/user/token";
+
+// Plain http for local development only
+static SECURE_COOKIES: LazyLock<bool> = LazyLock::new(|| {
+    std::env::var("COOKIE_SECURE")
+        .map(|value| value != "false" && value != "0")
+        .unwrap_or(true)
+});
+
+// How sign in hands out tokens
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryMode {
+    #[default]
+    Token,
+    Cookie,
+}
+
+impl DeliveryMode {
+    pub fn schema() -> Value {
+        json!({ "type": "string", "enum": ["token", "cookie"], "default": "token" })
+    }
+}
+
+#[derive(Serialize)]
+struct CookieSession {
+    csrf_token: String,
+}
+
+fn cookie<'c>(name: &'c str, value: String, path: &'c str, max_age: u64, http_only: bool) -> Cookie<'c> {
+    Cookie::build(name, value)
+        .path(path)
+        .max_age(Duration::seconds(max_age as i64))
+        .http_only(http_only)
+        .secure(*SECURE_COOKIES)
+        .same_site(SameSite::Strict)
+        .finish()
+}
+
+pub fn tokens_response(mut response: HttpResponseBuilder, tokens: Tokens, mode: DeliveryMode) -> HttpResponse {
+    match mode {
+        DeliveryMode::Token => response.json(tokens),
+        DeliveryMode::Cookie => {
+            let csrf_token = uuid::Uuid::new_v4().simple().to_string();
+
+            response
+                .cookie(cookie(ACCESS_COOKIE, tokens.access.to_string(), "/", ACCESS_TOKEN_TTL, true))
+                .cookie(cookie(REFRESH_COOKIE, tokens.refresh.to_string(), REFRESH_COOKIE_PATH, REFRESH_TOKEN_TTL, true))
+                .cookie(cookie(CSRF_COOKIE, csrf_token.clone(), "/", REFRESH_TOKEN_TTL, false))
+                .json(CookieSession { csrf_token })
+        },
+    }
+}
+
+// Expired cookies with the same attributes, browsers drop them
+pub fn clear_cookies(mut response: HttpResponseBuilder) -> HttpResponse {
+    for (name, path, http_only) in [(ACCESS_COOKIE, "/", true), (REFRESH_COOKIE, REFRESH_COOKIE_PATH, true), (CSRF_COOKIE, "/", false)] {
+        let mut removal = cookie(name, String::new(), path, 0, http_only);
+        removal.make_removal();
+        response.cookie(removal);
+    }
+
+    response.finish()
+}
+
+// Only unsafe methods are checked, safe ones must not change state
+pub fn verify_csrf(req: &HttpRequest) -> Result<(), actix_web::Error> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(())
+    }
+
+    let cookie = req.cookie(CSRF_COOKIE);
+    let header = req.headers()
+        .get(CSRF_HEADER)
+        .and_then(|header| header.to_str().ok());
+
+    match (cookie, header) {
+        (Some(cookie), Some(header)) if constant_time_eq(cookie.value().as_bytes(), header.as_bytes()) => Ok(()),
+        _ => Err(actix_web::error::InternalError::new("Missing or invalid CSRF token", StatusCode::FORBIDDEN).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn csrf_header_has_to_match_cookie() {
+        let cookie = Cookie::new(CSRF_COOKIE, "secret");
+
+        let matching = TestRequest::post().cookie(cookie.clone()).insert_header((CSRF_HEADER, "secret")).to_http_request();
+        let mismatched = TestRequest::post().cookie(cookie.clone()).insert_header((CSRF_HEADER, "guess")).to_http_request();
+        let missing = TestRequest::delete().cookie(cookie.clone()).to_http_request();
+        let safe = TestRequest::get().cookie(cookie).to_http_request();
+
+        assert!(verify_csrf(&matching).is_ok());
+        assert!(verify_csrf(&mismatched).is_err());
+        assert!(verify_csrf(&missing).is_err());
+        assert!(verify_csrf(&safe).is_ok());
+    }
+}
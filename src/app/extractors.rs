@@ -0,0 +1,227 @@
+use std::{future::{ready, Future, Ready}, pin::Pin, sync::LazyLock};
+
+use actix_web::{dev::Payload, error::{ErrorForbidden, ErrorInternalServerError, ErrorUnauthorized}, http::{header, Method, StatusCode}, web::Data, FromRequest, HttpRequest};
+
+use crate::{
+    app::{
+        cookies,
+        models::user::{
+            account::{AccountBlocked, AccountRepository},
+            api_key::{ApiKeyRepository, ApiKeySecret, ApiScope},
+            auth::{self, AccessToken},
+            session::ClientInfo,
+            EmailVerificationRepository, Role, RoleRepository, User, Uuid
+        }
+    },
+    AppState
+};
+
+// How the caller authorized
+pub enum Credential {
+    Session(AccessToken),
+    ApiKey(Vec<ApiScope>),
+}
+
+// Caller authorized with `Authorization: Bearer <access token or API key>` or,
+// in cookie mode, with the access token cookie and the CSRF header
+pub struct AuthorizedUser {
+    pub user_id: Uuid,
+    pub credential: Credential,
+}
+
+impl AuthorizedUser {
+    // Passwords, second factors, sessions and keys are managed from a signed in
+    // session only, a leaked key can't take the account over
+    pub fn session(&self) -> auth::Result<&AccessToken> {
+        match &self.credential {
+            Credential::Session(token) => Ok(token),
+            Credential::ApiKey(_) => Err(auth::Error::SessionRequired),
+        }
+    }
+
+    pub fn session_id(&self) -> auth::Result<String> {
+        self.session()?.session_id()
+    }
+
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        match &self.credential {
+            Credential::Session(_) => true,
+            Credential::ApiKey(scopes) => scopes.contains(&scope),
+        }
+    }
+}
+
+impl FromRequest for AuthorizedUser {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let token = match bearer_token(req) {
+            Some(token) => Ok(Some(token)),
+            // Browsers attach cookies to cross site requests too
+            None => match req.cookie(cookies::ACCESS_COOKIE) {
+                Some(cookie) => cookies::verify_csrf(req).map(|_| Some(cookie.value().to_string())),
+                None => Ok(None),
+            },
+        };
+        let required_scope = match *req.method() {
+            Method::GET | Method::HEAD | Method::OPTIONS => ApiScope::Read,
+            _ => ApiScope::Write,
+        };
+        let state = req.app_data::<Data<AppState>>().cloned();
+
+        Box::pin(async move {
+            let token = token?.ok_or_else(|| ErrorUnauthorized("Missing access token"))?;
+            let state = state.ok_or_else(|| ErrorInternalServerError("Application state is not configured"))?;
+
+            if let Some(secret) = ApiKeySecret::parse(&token) {
+                let grant = User::authenticate_api_key(&state.db, &secret)
+                    .await
+                    .map_err(|e| {
+                        eprintln!("{:?}",e);
+                        ErrorInternalServerError("Error checking API key")
+                    })?
+                    .ok_or_else(|| ErrorUnauthorized("Invalid API key"))?;
+
+                let authorized = AuthorizedUser {
+                    user_id: grant.user_id,
+                    credential: Credential::ApiKey(grant.scopes),
+                };
+
+                if !authorized.has_scope(required_scope) {
+                    return Err(ErrorForbidden(format!("API key lacks the `{}` scope", required_scope.as_str())))
+                }
+
+                ensure_active(&state, &authorized.user_id).await?;
+
+                return Ok(authorized)
+            }
+
+            let token = AccessToken::parse(token)?;
+            let body = token.decode()?.claims.body;
+
+            token.verify(&mut state.cache()).await?;
+            ensure_active(&state, &body.user_id).await?;
+
+            Ok(AuthorizedUser { user_id: body.user_id, credential: Credential::Session(token) })
+        })
+    }
+}
+
+// Checked on every request, sessions and keys of a blocked account stop working at once
+async fn ensure_active(state: &AppState, user_id: &Uuid) -> Result<(), actix_web::Error> {
+    let status = User::fetch_status(&state.db, user_id)
+        .await
+        .map_err(|e| match StatusCode::from(e) {
+            StatusCode::NOT_FOUND => ErrorUnauthorized("Account no longer exists"),
+            _ => ErrorInternalServerError("Error checking account status"),
+        })?;
+
+    if status.is_active() {
+        Ok(())
+    } else {
+        Err(AccountBlocked(status).into())
+    }
+}
+
+// Authorized caller with the admin role, API keys need the admin scope too
+pub struct AdminUser(pub AuthorizedUser);
+
+impl FromRequest for AdminUser {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let authorized = AuthorizedUser::from_request(req, payload);
+        let state = req.app_data::<Data<AppState>>().cloned();
+
+        Box::pin(async move {
+            let authorized = authorized.await?;
+            let state = state.ok_or_else(|| ErrorInternalServerError("Application state is not configured"))?;
+
+            let role = User::fetch_role(&state.db, &authorized.user_id)
+                .await
+                .map_err(|e| {
+                    eprintln!("{:?}",e);
+                    ErrorInternalServerError("Error checking role")
+                })?;
+
+            if role == Role::Admin && authorized.has_scope(ApiScope::Admin) {
+                Ok(AdminUser(authorized))
+            } else {
+                Err(ErrorForbidden("Requires the admin role"))
+            }
+        })
+    }
+}
+
+// Off by default, accounts created before emails were collected keep working
+static REQUIRE_VERIFIED_EMAIL: LazyLock<bool> = LazyLock::new(|| {
+    std::env::var("REQUIRE_VERIFIED_EMAIL")
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false)
+});
+
+// Authorized caller with a verified email, when REQUIRE_VERIFIED_EMAIL is set.
+// Guards checkout, where we have to be able to contact the customer
+pub struct VerifiedUser(pub AuthorizedUser);
+
+impl FromRequest for VerifiedUser {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let authorized = AuthorizedUser::from_request(req, payload);
+        let state = req.app_data::<Data<AppState>>().cloned();
+
+        Box::pin(async move {
+            let authorized = authorized.await?;
+
+            if !*REQUIRE_VERIFIED_EMAIL {
+                return Ok(VerifiedUser(authorized))
+            }
+
+            let state = state.ok_or_else(|| ErrorInternalServerError("Application state is not configured"))?;
+
+            let verified = User::is_email_verified(&state.db, &authorized.user_id)
+                .await
+                .map_err(|e| {
+                    eprintln!("{:?}",e);
+                    ErrorInternalServerError("Error checking email verification")
+                })?;
+
+            if verified {
+                Ok(VerifiedUser(authorized))
+            } else {
+                Err(ErrorForbidden("Email address is not verified"))
+            }
+        })
+    }
+}
+
+// Peer address is used, forwarding headers are set by the client and can't be trusted
+impl FromRequest for ClientInfo {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let user_agent = req.headers()
+            .get(header::USER_AGENT)
+            .and_then(|user_agent| user_agent.to_str().ok())
+            .map(|user_agent| user_agent.chars().take(256).collect());
+
+        ready(Ok(ClientInfo {
+            user_agent,
+            ip: req.peer_addr().map(|addr| addr.ip().to_string()),
+        }))
+    }
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_string())
+}
@@ -0,0 +1,52 @@
+use std::{sync::{Arc, LazyLock}, time::Duration};
+
+use sqlx::{Pool, Postgres};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::app::models::{
+    product::search::{ProductEvent, SearchBackend},
+    user::{account::AccountRepository, User}
+};
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60*60);
+
+// Days deleted users are kept anonymised before the hard purge
+static USER_RETENTION_DAYS: LazyLock<i64> = LazyLock::new(|| {
+    std::env::var("USER_RETENTION_DAYS")
+        .map(|days| days.parse().expect("USER_RETENTION_DAYS must be a number of days"))
+        .unwrap_or(30)
+});
+
+// Runs for the lifetime of the server, failures are retried on the next tick
+pub fn spawn_user_purge(db: Arc<Pool<Postgres>>) {
+    // Read before the job starts, a bad value stops the server
+    let retention_secs = *USER_RETENTION_DAYS * 24*60*60;
+
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(PURGE_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = User::purge_deleted_users(&db, retention_secs).await {
+                eprintln!("{:?}",e);
+            }
+        }
+    });
+}
+
+// One worker applies events in the order they were published, a failed event
+// leaves the product as it was until its next change or a restart
+pub fn spawn_search_sync(db: Arc<Pool<Postgres>>, search: Arc<dyn SearchBackend>) -> UnboundedSender<ProductEvent> {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+
+    actix_web::rt::spawn(async move {
+        while let Some(event) = receiver.recv().await {
+            if let Err(e) = search.apply(&db, event).await {
+                eprintln!("{:?}",e);
+            }
+        }
+    });
+
+    sender
+}
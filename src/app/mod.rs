@@ -1,2 +1,6 @@
 pub mod models;
 pub mod controllers;
+pub mod cookies;
+pub mod extractors;
+pub mod jobs;
+pub mod secrets;
@@ -0,0 +1,113 @@
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+
+use crate::repository::db::SqlxError;
+
+use super::user::{api_key::ApiScope, Role, Uuid};
+
+// Security relevant events, kept after the user is deleted
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Event {
+    SignInLockout {
+        login: String,
+        ip: Option<String>,
+        retry_after: u64,
+    },
+    RefreshTokenReuse {
+        session_id: String,
+    },
+    ApiKeyCreated {
+        key_id: Uuid,
+        name: String,
+        scopes: Vec<ApiScope>,
+        // User or admin who created it
+        actor: Uuid,
+    },
+    ApiKeyRevoked {
+        key_id: Uuid,
+        actor: Uuid,
+    },
+    IdentityLinked {
+        provider: String,
+        subject: String,
+    },
+    IdentityUnlinked {
+        provider: String,
+    },
+    DataExported {},
+    // Change an admin made to the account the event is recorded for
+    AdminChange {
+        actor: Uuid,
+        #[serde(flatten)]
+        change: AdminChange,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum AdminChange {
+    Created,
+    Updated,
+    Deleted,
+    Disabled {
+        reason: Option<String>,
+    },
+    Banned {
+        until: i64,
+        reason: Option<String>,
+    },
+    Enabled,
+    PasswordResetForced,
+    RoleChanged {
+        role: Role,
+    },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::SignInLockout { .. } => "sign_in_lockout",
+            Event::RefreshTokenReuse { .. } => "refresh_token_reuse",
+            Event::ApiKeyCreated { .. } => "api_key_created",
+            Event::ApiKeyRevoked { .. } => "api_key_revoked",
+            Event::IdentityLinked { .. } => "identity_linked",
+            Event::IdentityUnlinked { .. } => "identity_unlinked",
+            Event::DataExported {} => "data_exported",
+            Event::AdminChange { change, .. } => match change {
+                AdminChange::Created => "admin_user_created",
+                AdminChange::Updated => "admin_user_updated",
+                AdminChange::Deleted => "admin_user_deleted",
+                AdminChange::Disabled { .. } => "admin_user_disabled",
+                AdminChange::Banned { .. } => "admin_user_banned",
+                AdminChange::Enabled => "admin_user_enabled",
+                AdminChange::PasswordResetForced => "admin_password_reset_forced",
+                AdminChange::RoleChanged { .. } => "admin_role_changed",
+            },
+        }
+    }
+}
+
+pub trait AuditRepository<T: sqlx::Database> {
+    async fn record_event(db: &Pool<T>, user_id: Option<&Uuid>, event: &Event) -> Result<(), SqlxError>;
+}
+
+impl AuditRepository<Postgres> for Event {
+    async fn record_event(db: &Pool<Postgres>, user_id: Option<&Uuid>, event: &Event) -> Result<(), SqlxError> {
+        let details = serde_json::to_string(event).expect("Audit event is serializable");
+        let id = uuid::Uuid::new_v4();
+        let user_id: Option<[u8; 16]> = user_id.cloned().map(Into::into);
+
+        sqlx::query!(
+            "INSERT INTO audit_log (id, event, userId, details) VALUES ($1, $2, $3, $4::TEXT::JSONB)",
+            &id.as_bytes()[..],
+            event.name(),
+            user_id.as_ref().map(|user_id| &user_id[..]),
+            details
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use serde::Serialize;
+use sqlx::{types::Json, Pool, Postgres};
+
+use crate::repository::db::SqlxError;
+
+use super::{product::{Attributes, Sku}, user::Uuid};
+
+pub const MAX_QUANTITY: i32 = 99;
+
+// Variant in the basket with what the customer sees of it
+#[derive(Debug, Serialize)]
+pub struct BasketItem {
+    pub id: Uuid,
+    pub variant_id: Uuid,
+    pub product_id: Uuid,
+    pub title: String,
+    pub sku: Sku,
+    pub options: Attributes,
+    pub unit_price_cents: i64,
+    pub quantity: i32,
+    pub in_stock: bool,
+}
+
+pub trait BasketRepository<T: sqlx::Database> {
+    async fn fetch_basket(db: &Pool<T>, user_id: &Uuid) -> Result<Vec<BasketItem>, SqlxError>;
+    // Adding a variant already in the basket raises its quantity, up to the maximum
+    async fn add_to_basket(db: &Pool<T>, user_id: &Uuid, variant_id: &Uuid, quantity: i32) -> Result<(), SqlxError>;
+    async fn remove_from_basket(db: &Pool<T>, user_id: &Uuid, item_id: &Uuid) -> Result<bool, SqlxError>;
+}
+
+impl BasketRepository<Postgres> for BasketItem {
+    async fn fetch_basket(db: &Pool<Postgres>, user_id: &Uuid) -> Result<Vec<BasketItem>, SqlxError> {
+        let rows = sqlx::query!(
+            r#"SELECT basket.id, basket.variantId, basket.quantity, product.id as product_id, product.title,
+                product_variants.sku, product_variants.options as "options: Json<Attributes>",
+                (COALESCE(product_variants.priceOverride, product.price) * 100)::BIGINT as "unit_price_cents!",
+                product_variants.stock >= basket.quantity as "in_stock!"
+            FROM basket
+            JOIN product_variants ON product_variants.id = basket.variantId
+            JOIN product ON product.id = product_variants.productId
+            WHERE basket.userId = $1
+            ORDER BY product.title, product_variants.position"#,
+            &user_id.as_bytes()[..]
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(
+            rows.into_iter()
+                .map(|row| BasketItem {
+                    id: Uuid::from(row.id),
+                    variant_id: Uuid::from(row.variantid),
+                    product_id: Uuid::from(row.product_id),
+                    title: row.title,
+                    sku: Sku::from(row.sku),
+                    options: row.options.0,
+                    unit_price_cents: row.unit_price_cents,
+                    quantity: row.quantity,
+                    in_stock: row.in_stock,
+                })
+                .collect()
+        )
+    }
+
+    async fn add_to_basket(db: &Pool<Postgres>, user_id: &Uuid, variant_id: &Uuid, quantity: i32) -> Result<(), SqlxError> {
+        let id = uuid::Uuid::new_v4();
+
+        sqlx::query!(
+            "INSERT INTO basket (id, userId, variantId, quantity) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (userId, variantId) DO UPDATE SET quantity = LEAST(basket.quantity + EXCLUDED.quantity, $5)",
+            &id.as_bytes()[..],
+            &user_id.as_bytes()[..],
+            &variant_id.as_bytes()[..],
+            quantity,
+            MAX_QUANTITY
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove_from_basket(db: &Pool<Postgres>, user_id: &Uuid, item_id: &Uuid) -> Result<bool, SqlxError> {
+        let result = sqlx::query!(
+            "DELETE FROM basket WHERE id = $1 AND userId = $2",
+            &item_id.as_bytes()[..],
+            &user_id.as_bytes()[..]
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+}
@@ -1,2 +1,6 @@
 // Models
+pub mod audit;
+pub mod basket;
+pub mod order;
+pub mod product;
 pub mod user;
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use actix_web::{http::StatusCode, ResponseError};
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+
+use crate::repository::db::SqlxError;
+
+use super::user::Uuid;
+
+// Items keep the title, SKU and price they were bought with
+#[derive(Debug, Serialize)]
+pub struct OrderItem {
+    // Not set once the variant is deleted
+    pub variant_id: Option<Uuid>,
+    pub sku: String,
+    pub title: String,
+    pub unit_price_cents: i64,
+    pub quantity: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Order {
+    pub id: Uuid,
+    pub total_cents: i64,
+    pub created_at: i64,
+    pub items: Vec<OrderItem>,
+}
+
+pub fn total_cents(items: &[OrderItem]) -> i64 {
+    items.iter().map(|item| item.unit_price_cents * item.quantity as i64).sum()
+}
+
+// Database
+
+pub trait OrderRepository<T: sqlx::Database> {
+    // Turns the basket into an order and takes the items from stock, all or nothing
+    async fn place_order(db: &Pool<T>, user_id: &Uuid) -> Result<Order>;
+    // Newest first
+    async fn fetch_orders(db: &Pool<T>, user_id: &Uuid) -> std::result::Result<Vec<Order>, SqlxError>;
+}
+
+impl OrderRepository<Postgres> for Order {
+    async fn place_order(db: &Pool<Postgres>, user_id: &Uuid) -> Result<Order> {
+        let order_id = uuid::Uuid::new_v4();
+        let mut tx = db.begin().await.map_err(SqlxError::from)?;
+
+        // Variants stay locked until the stock is taken
+        let rows = sqlx::query!(
+            r#"SELECT basket.variantId, basket.quantity, product_variants.sku, product_variants.stock, product.title,
+                (COALESCE(product_variants.priceOverride, product.price) * 100)::BIGINT as "unit_price_cents!"
+            FROM basket
+            JOIN product_variants ON product_variants.id = basket.variantId
+            JOIN product ON product.id = product_variants.productId
+            WHERE basket.userId = $1
+            ORDER BY product_variants.id
+            FOR UPDATE OF product_variants"#,
+            &user_id.as_bytes()[..]
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(SqlxError::from)?;
+
+        if rows.is_empty() {
+            return Err(Error::EmptyBasket)
+        }
+
+        if let Some(row) = rows.iter().find(|row| row.stock < row.quantity) {
+            return Err(Error::OutOfStock(row.sku.clone()))
+        }
+
+        let items: Vec<OrderItem> = rows
+            .into_iter()
+            .map(|row| OrderItem {
+                variant_id: Some(Uuid::from(row.variantid)),
+                sku: row.sku,
+                title: row.title,
+                unit_price_cents: row.unit_price_cents,
+                quantity: row.quantity,
+            })
+            .collect();
+
+        let total_cents = total_cents(&items);
+
+        let created_at = sqlx::query_scalar!(
+            r#"INSERT INTO orders (id, userId, total) VALUES ($1, $2, $3::BIGINT::NUMERIC / 100)
+            RETURNING EXTRACT(EPOCH FROM createdAt)::BIGINT as "created_at!""#,
+            &order_id.as_bytes()[..],
+            &user_id.as_bytes()[..],
+            total_cents
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(SqlxError::from)?;
+
+        for item in &items {
+            let item_id = uuid::Uuid::new_v4();
+            let variant_id = item.variant_id.as_ref().map(|variant_id| &variant_id.as_bytes()[..]);
+
+            sqlx::query!(
+                "UPDATE product_variants SET stock = stock - $2 WHERE id = $1",
+                variant_id,
+                item.quantity
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(SqlxError::from)?;
+
+            sqlx::query!(
+                "INSERT INTO order_items (id, orderId, variantId, sku, title, unitPrice, quantity)
+                VALUES ($1, $2, $3, $4, $5, $6::BIGINT::NUMERIC / 100, $7)",
+                &item_id.as_bytes()[..],
+                &order_id.as_bytes()[..],
+                variant_id,
+                item.sku,
+                item.title,
+                item.unit_price_cents,
+                item.quantity
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(SqlxError::from)?;
+        }
+
+        sqlx::query!("DELETE FROM basket WHERE userId = $1", &user_id.as_bytes()[..])
+            .execute(&mut *tx)
+            .await
+            .map_err(SqlxError::from)?;
+
+        tx.commit().await.map_err(SqlxError::from)?;
+
+        Ok(Order { id: Uuid::parse(order_id), total_cents, created_at, items })
+    }
+
+    async fn fetch_orders(db: &Pool<Postgres>, user_id: &Uuid) -> std::result::Result<Vec<Order>, SqlxError> {
+        let orders = sqlx::query!(
+            r#"SELECT id, (total * 100)::BIGINT as "total_cents!", EXTRACT(EPOCH FROM createdAt)::BIGINT as "created_at!"
+            FROM orders WHERE userId = $1 ORDER BY createdAt DESC"#,
+            &user_id.as_bytes()[..]
+        )
+        .fetch_all(db)
+        .await?;
+
+        let order_ids: Vec<Vec<u8>> = orders.iter().map(|order| order.id.clone()).collect();
+
+        let mut items: HashMap<Vec<u8>, Vec<OrderItem>> = HashMap::new();
+
+        for row in sqlx::query!(
+            r#"SELECT orderId, variantId, sku, title, (unitPrice * 100)::BIGINT as "unit_price_cents!", quantity
+            FROM order_items WHERE orderId = ANY($1) ORDER BY title"#,
+            &order_ids
+        )
+        .fetch_all(db)
+        .await? {
+            items.entry(row.orderid).or_default().push(OrderItem {
+                variant_id: row.variantid.map(Uuid::from),
+                sku: row.sku,
+                title: row.title,
+                unit_price_cents: row.unit_price_cents,
+                quantity: row.quantity,
+            });
+        }
+
+        Ok(
+            orders.into_iter()
+                .map(|order| Order {
+                    items: items.remove(&order.id).unwrap_or_default(),
+                    id: Uuid::from(order.id),
+                    total_cents: order.total_cents,
+                    created_at: order.created_at,
+                })
+                .collect()
+        )
+    }
+}
+
+// Error
+pub type Result<T> = std::result::Result<T,Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    EmptyBasket,
+    // SKU of the variant there isn't enough of
+    OutOfStock(String),
+    Database(SqlxError),
+}
+
+impl From<SqlxError> for Error {
+    fn from(value: SqlxError) -> Self {
+        Error::Database(value)
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::EmptyBasket => { write!(f, "Basket is empty") },
+            Error::OutOfStock(sku) => { write!(f, "Not enough of {} in stock", sku) },
+            Error::Database(error) => { write!(f, "{}", error) },
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::EmptyBasket => StatusCode::BAD_REQUEST,
+            Error::OutOfStock(_) => StatusCode::CONFLICT,
+            Error::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn total_counts_every_unit() {
+        let item = |unit_price_cents, quantity| OrderItem {
+            variant_id: None,
+            sku: "SKU".to_string(),
+            title: "Title".to_string(),
+            unit_price_cents,
+            quantity,
+        };
+
+        assert_eq!(total_cents(&[item(1999, 2), item(500, 1)]), 4498);
+        assert_eq!(total_cents(&[]), 0);
+    }
+}
@@ -0,0 +1,380 @@
+use core::fmt;
+use std::{collections::HashSet, sync::LazyLock};
+
+use lib_utils::{validate, validation::{self, Rules, Validate}};
+use regex::Regex;
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+
+use crate::{app::models::user::Uuid, repository::db::SqlxError};
+
+// Custom validation rules
+
+const SLUG_CHARACTERS: &str = r"^[a-z0-9]+(-[a-z0-9]+)*$";
+
+static SLUG_CHARACTERS_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(SLUG_CHARACTERS).unwrap());
+
+#[derive(Debug)]
+enum CustomRules {
+    SlugCanContain,
+}
+
+impl std::fmt::Display for CustomRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CustomRules::SlugCanContain => {
+                write!(f, "Can contain only lowercase letters and numbers, separated by single hyphens.")
+            },
+        }
+    }
+}
+
+impl validation::Rule for CustomRules {}
+
+impl Validate<String> for CustomRules {
+    fn validate(&self, value: &String) -> validation::Result<'_, &Self> {
+        match self {
+            CustomRules::SlugCanContain => {
+                if SLUG_CHARACTERS_REGEX.is_match(value) {
+                    Ok(self)
+                } else {
+                    Err(validation::Error::RuleNotValidated(self))
+                }
+            },
+        }
+    }
+}
+
+// Slug, the category in URLs
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Slug(String);
+
+impl fmt::Display for Slug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::convert::From<String> for Slug {
+    fn from(value: String) -> Self {
+        Slug(value)
+    }
+}
+
+impl Slug {
+    pub fn parse(slug: String) -> Result<Self, Vec<validation::Error<'static>>> {
+        let validation_errors = validate!(slug => max_length(64), custom(CustomRules::SlugCanContain)).to_vec();
+
+        if validation_errors.is_empty() {
+            Ok(Slug(slug))
+        } else {
+            Err(validation_errors)
+        }
+    }
+}
+
+pub const CATEGORY_NAME_RULES: [&Rules; 2] = [&Rules::MinLength(1), &Rules::MaxLength(255)];
+
+// Category
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Category {
+    pub id: Uuid,
+    pub parent_id: Option<Uuid>,
+    pub slug: Slug,
+    pub name: String,
+    // Siblings are listed by position, then by name
+    pub position: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Crumb {
+    pub id: Uuid,
+    pub slug: Slug,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CategoryNode<'a> {
+    #[serde(flatten)]
+    pub category: &'a Category,
+    pub children: Vec<CategoryNode<'a>>,
+}
+
+// Whole tree, loaded at once. Catalogs have few categories compared to products
+pub struct CategoryTree {
+    categories: Vec<Category>,
+}
+
+impl CategoryTree {
+    pub fn new(mut categories: Vec<Category>) -> Self {
+        categories.sort_by(|a, b| a.position.cmp(&b.position).then_with(|| a.name.cmp(&b.name)));
+
+        CategoryTree { categories }
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<&Category> {
+        self.categories.iter().find(|category| &category.id == id)
+    }
+
+    pub fn by_slug(&self, slug: &str) -> Option<&Category> {
+        self.categories.iter().find(|category| category.slug.0 == slug)
+    }
+
+    pub fn children(&self, parent_id: Option<&Uuid>) -> Vec<&Category> {
+        self.categories
+            .iter()
+            .filter(|category| category.parent_id.as_ref() == parent_id)
+            .collect()
+    }
+
+    pub fn nodes(&self) -> Vec<CategoryNode<'_>> {
+        self.nodes_under(None)
+    }
+
+    fn nodes_under(&self, parent_id: Option<&Uuid>) -> Vec<CategoryNode<'_>> {
+        self.children(parent_id)
+            .into_iter()
+            .map(|category| CategoryNode { category, children: self.nodes_under(Some(&category.id)) })
+            .collect()
+    }
+
+    // The category itself and everything below it
+    pub fn descendants(&self, id: &Uuid) -> Vec<Uuid> {
+        let mut found = vec![id.clone()];
+        let mut next = 0;
+
+        while next < found.len() {
+            let parent_id = found[next].clone();
+            found.extend(self.children(Some(&parent_id)).into_iter().map(|category| category.id.clone()));
+            next += 1;
+        }
+
+        found
+    }
+
+    // Path from the root down to the category
+    pub fn breadcrumbs(&self, id: &Uuid) -> Vec<Crumb> {
+        let mut crumbs = Vec::new();
+        let mut seen = HashSet::new();
+        let mut current = self.get(id);
+
+        while let Some(category) = current {
+            if !seen.insert(category.id.clone()) {
+                break
+            }
+
+            crumbs.push(Crumb { id: category.id.clone(), slug: category.slug.clone(), name: category.name.clone() });
+            current = category.parent_id.as_ref().and_then(|parent_id| self.get(parent_id));
+        }
+
+        crumbs.reverse();
+        crumbs
+    }
+
+    // A category can't end up below itself
+    pub fn can_move(&self, id: &Uuid, parent_id: Option<&Uuid>) -> bool {
+        match parent_id {
+            Some(parent_id) => !self.descendants(id).contains(parent_id),
+            None => true,
+        }
+    }
+}
+
+// Database
+
+pub trait CategoryRepository<T: sqlx::Database> {
+    async fn fetch_category_tree(db: &Pool<T>) -> Result<CategoryTree, SqlxError>;
+    async fn create_category(db: &Pool<T>, category: &Category) -> Result<(), SqlxError>;
+    // False when the category doesn't exist
+    async fn update_category(db: &Pool<T>, category: &Category) -> Result<bool, SqlxError>;
+    // Children move up to the parent of the deleted category
+    async fn delete_category(db: &Pool<T>, category_id: &Uuid) -> Result<bool, SqlxError>;
+    async fn fetch_product_categories(db: &Pool<T>, product_id: &Uuid) -> Result<Vec<Uuid>, SqlxError>;
+    async fn set_product_categories(db: &Pool<T>, product_id: &Uuid, category_ids: &[Uuid]) -> Result<(), SqlxError>;
+}
+
+impl CategoryRepository<Postgres> for Category {
+    async fn fetch_category_tree(db: &Pool<Postgres>) -> Result<CategoryTree, SqlxError> {
+        let categories = sqlx::query!("SELECT id, parentId, slug, name, position FROM categories")
+            .fetch_all(db)
+            .await?
+            .into_iter()
+            .map(|row| Category {
+                id: Uuid::from(row.id),
+                parent_id: row.parentid.map(Uuid::from),
+                slug: Slug::from(row.slug),
+                name: row.name,
+                position: row.position,
+            })
+            .collect();
+
+        Ok(CategoryTree::new(categories))
+    }
+
+    async fn create_category(db: &Pool<Postgres>, category: &Category) -> Result<(), SqlxError> {
+        sqlx::query!(
+            "INSERT INTO categories (id, parentId, slug, name, position) VALUES ($1, $2, $3, $4, $5)",
+            &category.id.as_bytes()[..],
+            category.parent_id.as_ref().map(|parent_id| &parent_id.as_bytes()[..]),
+            category.slug.0,
+            category.name,
+            category.position
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn update_category(db: &Pool<Postgres>, category: &Category) -> Result<bool, SqlxError> {
+        let result = sqlx::query!(
+            "UPDATE categories SET parentId = $2, slug = $3, name = $4, position = $5 WHERE id = $1",
+            &category.id.as_bytes()[..],
+            category.parent_id.as_ref().map(|parent_id| &parent_id.as_bytes()[..]),
+            category.slug.0,
+            category.name,
+            category.position
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn delete_category(db: &Pool<Postgres>, category_id: &Uuid) -> Result<bool, SqlxError> {
+        let mut tx = db.begin().await?;
+
+        sqlx::query!(
+            "UPDATE categories SET parentId = (SELECT parentId FROM categories WHERE id = $1) WHERE parentId = $1",
+            &category_id.as_bytes()[..]
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let result = sqlx::query!("DELETE FROM categories WHERE id = $1", &category_id.as_bytes()[..])
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn fetch_product_categories(db: &Pool<Postgres>, product_id: &Uuid) -> Result<Vec<Uuid>, SqlxError> {
+        let category_ids = sqlx::query_scalar!(
+            "SELECT categoryId FROM product_categories WHERE productId = $1",
+            &product_id.as_bytes()[..]
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(category_ids.into_iter().map(Uuid::from).collect())
+    }
+
+    async fn set_product_categories(db: &Pool<Postgres>, product_id: &Uuid, category_ids: &[Uuid]) -> Result<(), SqlxError> {
+        let category_ids: Vec<Vec<u8>> = category_ids.iter().map(|id| id.as_bytes().to_vec()).collect();
+        let mut tx = db.begin().await?;
+
+        sqlx::query!("DELETE FROM product_categories WHERE productId = $1", &product_id.as_bytes()[..])
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+            "INSERT INTO product_categories (productId, categoryId) SELECT $1, UNNEST($2::BYTEA[])",
+            &product_id.as_bytes()[..],
+            &category_ids
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn category(id: u8, parent_id: Option<u8>, slug: &str, position: i32) -> Category {
+        Category {
+            id: Uuid::from([id; 16]),
+            parent_id: parent_id.map(|parent_id| Uuid::from([parent_id; 16])),
+            slug: Slug::from(slug.to_string()),
+            name: slug.to_string(),
+            position,
+        }
+    }
+
+    fn tree() -> CategoryTree {
+        CategoryTree::new(vec![
+            category(1, None, "clothing", 1),
+            category(2, Some(1), "shirts", 2),
+            category(3, Some(1), "shoes", 1),
+            category(4, Some(3), "boots", 0),
+            category(5, None, "books", 0),
+        ])
+    }
+
+    #[test]
+    fn tree_is_nested_and_ordered() {
+        let tree = tree();
+        let nodes = tree.nodes();
+
+        let roots: Vec<&str> = nodes.iter().map(|node| node.category.name.as_str()).collect();
+        assert_eq!(roots, ["books", "clothing"]);
+
+        let clothing: Vec<&str> = nodes[1].children.iter().map(|node| node.category.name.as_str()).collect();
+        assert_eq!(clothing, ["shoes", "shirts"]);
+        assert_eq!(nodes[1].children[0].children[0].category.name, "boots");
+    }
+
+    #[test]
+    fn descendants_and_breadcrumbs_follow_parents() {
+        let tree = tree();
+
+        let descendants = tree.descendants(&Uuid::from([1; 16]));
+        assert_eq!(descendants.len(), 4);
+        assert!(descendants.contains(&Uuid::from([4; 16])));
+        assert!(!descendants.contains(&Uuid::from([5; 16])));
+
+        let crumbs: Vec<String> = tree.breadcrumbs(&Uuid::from([4; 16]))
+            .into_iter()
+            .map(|crumb| crumb.slug.to_string())
+            .collect();
+        assert_eq!(crumbs, ["clothing", "shoes", "boots"]);
+
+        assert!(tree.can_move(&Uuid::from([3; 16]), Some(&Uuid::from([5; 16]))));
+        assert!(!tree.can_move(&Uuid::from([1; 16]), Some(&Uuid::from([4; 16]))));
+        assert!(!tree.can_move(&Uuid::from([1; 16]), Some(&Uuid::from([1; 16]))));
+    }
+
+    #[test]
+    fn slugs_are_lowercase_words() {
+        assert!(Slug::parse("winter-boots-2".to_string()).is_ok());
+        assert!(Slug::parse("Winter".to_string()).is_err());
+        assert!(Slug::parse("winter--boots".to_string()).is_err());
+        assert!(Slug::parse(String::new()).is_err());
+    }
+
+    #[test]
+    fn slug_errors_match_rule_lists() {
+        let slug = "Winter-".repeat(10);
+        let expected = [
+            lib_utils::validation::validate_rules(&slug, &[&Rules::MaxLength(64)]),
+            lib_utils::validation::validate_rules(&slug, &[&CustomRules::SlugCanContain]),
+        ]
+        .concat();
+
+        let errors = Slug::parse(slug).unwrap_err();
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            expected.iter().map(ToString::to_string).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,348 @@
+pub mod category;
+pub mod search;
+pub mod variant;
+
+use core::fmt;
+use std::{collections::BTreeMap, sync::LazyLock};
+
+use lib_utils::validation::{self, validate_rules, Rules, Validate};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, Pool, Postgres};
+
+use crate::{app::models::user::Uuid, repository::db::SqlxError};
+
+// Custom validation rules
+
+const SKU_CHARACTERS: &str = r"^[A-Za-z0-9][A-Za-z0-9._-]*$";
+
+static SKU_CHARACTERS_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(SKU_CHARACTERS).unwrap());
+
+#[derive(Debug)]
+enum CustomRules {
+    SkuCanContain,
+}
+
+impl std::fmt::Display for CustomRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CustomRules::SkuCanContain => {
+                write!(f, "Can contain only letters, numbers, periods, underscores and hyphens.")
+            },
+        }
+    }
+}
+
+impl validation::Rule for CustomRules {}
+
+impl Validate<String> for CustomRules {
+    fn validate(&self, value: &String) -> validation::Result<'_, &Self> {
+        match self {
+            CustomRules::SkuCanContain => {
+                if SKU_CHARACTERS_REGEX.is_match(value) {
+                    Ok(self)
+                } else {
+                    Err(validation::Error::RuleNotValidated(self))
+                }
+            },
+        }
+    }
+}
+
+// Sku, stock keeping unit of a product or variant
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Sku(String);
+
+impl fmt::Display for Sku {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::convert::From<String> for Sku {
+    fn from(value: String) -> Self {
+        Sku(value)
+    }
+}
+
+const SKU_RULES: [&Rules; 1] = [&Rules::MaxLength(64)];
+const SKU_CUSTOM_RULES: [&CustomRules; 1] = [&CustomRules::SkuCanContain];
+
+impl Sku {
+    pub fn parse(sku: String) -> Result<Self, Vec<validation::Error<'static>>> {
+        let validation_errors = [
+            validate_rules(&sku, &SKU_RULES),
+            validate_rules(&sku, &SKU_CUSTOM_RULES),
+        ]
+        .concat();
+
+        if validation_errors.is_empty() {
+            Ok(Sku(sku))
+        } else {
+            Err(validation_errors)
+        }
+    }
+}
+
+pub const TITLE_RULES: [&Rules; 2] = [&Rules::MinLength(1), &Rules::MaxLength(255)];
+pub const DESCRIPTION_RULES: [&Rules; 1] = [&Rules::MaxLength(10000)];
+
+// Attributes
+
+const MAX_ATTRIBUTES: usize = 32;
+const ATTRIBUTE_NAME_MAX_LENGTH: usize = 64;
+const ATTRIBUTE_TEXT_MAX_LENGTH: usize = 255;
+
+// Value keeps its JSON type, so numbers compare and sort as numbers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AttributeValue {
+    Boolean(bool),
+    Number(f64),
+    Text(String),
+}
+
+// Named values like material or weight, for variants the ones telling them
+// apart like size and colour
+pub type Attributes = BTreeMap<String, AttributeValue>;
+
+pub fn validate_attributes(attributes: &Attributes) -> Result<(), String> {
+    if attributes.len() > MAX_ATTRIBUTES {
+        return Err(format!("At most {} attributes are allowed", MAX_ATTRIBUTES))
+    }
+
+    for (name, value) in attributes {
+        if name.trim().is_empty() || name.chars().count() > ATTRIBUTE_NAME_MAX_LENGTH {
+            return Err(format!("Attribute names must have 1 to {} characters", ATTRIBUTE_NAME_MAX_LENGTH))
+        }
+
+        match value {
+            AttributeValue::Text(text) if text.chars().count() > ATTRIBUTE_TEXT_MAX_LENGTH => {
+                return Err(format!("Attribute `{}` is longer than {} characters", name, ATTRIBUTE_TEXT_MAX_LENGTH))
+            },
+            AttributeValue::Number(number) if !number.is_finite() => {
+                return Err(format!("Attribute `{}` is not a finite number", name))
+            },
+            _ => {},
+        }
+    }
+
+    Ok(())
+}
+
+// Product
+
+// Prices are whole cents, NUMERIC(10,2) in the database
+#[derive(Debug, Clone, Serialize)]
+pub struct Product {
+    pub id: Uuid,
+    pub title: String,
+    pub description: String,
+    pub sku: Sku,
+    pub attributes: Attributes,
+    pub price_cents: i64,
+    pub rating: f64,
+}
+
+pub struct ProductPage {
+    pub products: Vec<Product>,
+    pub total: i64,
+}
+
+// Database
+
+pub trait ProductRepository<T: sqlx::Database> {
+    async fn fetch_product(db: &Pool<T>, product_id: &Uuid) -> Result<Product, SqlxError>;
+    // In no particular order, ids without a product are left out
+    async fn fetch_products(db: &Pool<T>, product_ids: &[Uuid]) -> Result<Vec<Product>, SqlxError>;
+    // Products assigned to any of the categories
+    async fn fetch_products_in_categories(db: &Pool<T>, category_ids: &[Uuid], limit: i64, offset: i64) -> Result<ProductPage, SqlxError>;
+    // Creates the product with a single variant sharing its SKU
+    async fn create_product(db: &Pool<T>, product: &Product) -> Result<(), SqlxError>;
+    // False when the product doesn't exist
+    async fn update_product(db: &Pool<T>, product: &Product) -> Result<bool, SqlxError>;
+    // Comments go with it, orders keep their copy of the title and SKU
+    async fn delete_product(db: &Pool<T>, product_id: &Uuid) -> Result<bool, SqlxError>;
+}
+
+impl ProductRepository<Postgres> for Product {
+    async fn fetch_product(db: &Pool<Postgres>, product_id: &Uuid) -> Result<Product, SqlxError> {
+        let row = sqlx::query!(
+            r#"SELECT id, title, description, sku, attributes as "attributes: Json<Attributes>",
+                (price * 100)::BIGINT as "price_cents!", rating::FLOAT8 as "rating!"
+            FROM product WHERE id = $1"#,
+            &product_id.as_bytes()[..]
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(Product {
+            id: Uuid::from(row.id),
+            title: row.title,
+            description: row.description,
+            sku: Sku::from(row.sku),
+            attributes: row.attributes.0,
+            price_cents: row.price_cents,
+            rating: row.rating,
+        })
+    }
+
+    async fn fetch_products(db: &Pool<Postgres>, product_ids: &[Uuid]) -> Result<Vec<Product>, SqlxError> {
+        let product_ids: Vec<Vec<u8>> = product_ids.iter().map(|id| id.as_bytes().to_vec()).collect();
+
+        let products = sqlx::query!(
+            r#"SELECT id, title, description, sku, attributes as "attributes: Json<Attributes>",
+                (price * 100)::BIGINT as "price_cents!", rating::FLOAT8 as "rating!"
+            FROM product WHERE id = ANY($1)"#,
+            &product_ids
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|row| Product {
+            id: Uuid::from(row.id),
+            title: row.title,
+            description: row.description,
+            sku: Sku::from(row.sku),
+            attributes: row.attributes.0,
+            price_cents: row.price_cents,
+            rating: row.rating,
+        })
+        .collect();
+
+        Ok(products)
+    }
+
+    async fn fetch_products_in_categories(db: &Pool<Postgres>, category_ids: &[Uuid], limit: i64, offset: i64) -> Result<ProductPage, SqlxError> {
+        let category_ids: Vec<Vec<u8>> = category_ids.iter().map(|id| id.as_bytes().to_vec()).collect();
+
+        let products = sqlx::query!(
+            r#"SELECT id, title, description, sku, attributes as "attributes: Json<Attributes>",
+                (price * 100)::BIGINT as "price_cents!", rating::FLOAT8 as "rating!"
+            FROM product
+            WHERE id IN (SELECT productId FROM product_categories WHERE categoryId = ANY($1))
+            ORDER BY title LIMIT $2 OFFSET $3"#,
+            &category_ids,
+            limit,
+            offset
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|row| Product {
+            id: Uuid::from(row.id),
+            title: row.title,
+            description: row.description,
+            sku: Sku::from(row.sku),
+            attributes: row.attributes.0,
+            price_cents: row.price_cents,
+            rating: row.rating,
+        })
+        .collect();
+
+        let total = sqlx::query_scalar!(
+            r#"SELECT COUNT(DISTINCT productId) as "total!" FROM product_categories WHERE categoryId = ANY($1)"#,
+            &category_ids
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(ProductPage { products, total })
+    }
+
+    async fn create_product(db: &Pool<Postgres>, product: &Product) -> Result<(), SqlxError> {
+        let variant_id = uuid::Uuid::new_v4();
+        let mut tx = db.begin().await?;
+
+        sqlx::query!(
+            "INSERT INTO product (id, title, description, sku, attributes, price, rating)
+            VALUES ($1, $2, $3, $4, $5, $6::BIGINT::NUMERIC / 100, 0)",
+            &product.id.as_bytes()[..],
+            product.title,
+            product.description,
+            product.sku.0,
+            Json(&product.attributes) as _,
+            product.price_cents
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO product_variants (id, productId, sku) VALUES ($1, $2, $3)",
+            &variant_id.as_bytes()[..],
+            &product.id.as_bytes()[..],
+            product.sku.0
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn update_product(db: &Pool<Postgres>, product: &Product) -> Result<bool, SqlxError> {
+        let result = sqlx::query!(
+            "UPDATE product SET title = $2, description = $3, sku = $4, attributes = $5, price = $6::BIGINT::NUMERIC / 100
+            WHERE id = $1",
+            &product.id.as_bytes()[..],
+            product.title,
+            product.description,
+            product.sku.0,
+            Json(&product.attributes) as _,
+            product.price_cents
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn delete_product(db: &Pool<Postgres>, product_id: &Uuid) -> Result<bool, SqlxError> {
+        let mut tx = db.begin().await?;
+
+        sqlx::query!("DELETE FROM comment WHERE productId = $1", &product_id.as_bytes()[..])
+            .execute(&mut *tx)
+            .await?;
+
+        let result = sqlx::query!("DELETE FROM product WHERE id = $1", &product_id.as_bytes()[..])
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attributes_keep_their_type() {
+        let attributes: Attributes = serde_json::from_str(r#"{"material": "wool", "weight": 1.5, "washable": true}"#).unwrap();
+
+        assert_eq!(attributes["material"], AttributeValue::Text("wool".to_string()));
+        assert_eq!(attributes["weight"], AttributeValue::Number(1.5));
+        assert_eq!(attributes["washable"], AttributeValue::Boolean(true));
+        assert!(validate_attributes(&attributes).is_ok());
+
+        assert!(serde_json::from_str::<Attributes>(r#"{"sizes": [1, 2]}"#).is_err());
+
+        let long: Attributes = [("material".to_string(), AttributeValue::Text("x".repeat(256)))].into();
+        assert!(validate_attributes(&long).is_err());
+
+        let unnamed: Attributes = [(" ".to_string(), AttributeValue::Boolean(false))].into();
+        assert!(validate_attributes(&unnamed).is_err());
+    }
+
+    #[test]
+    fn skus_are_codes() {
+        assert!(Sku::parse("TSHIRT-RED_XL.2".to_string()).is_ok());
+        assert!(Sku::parse("-TSHIRT".to_string()).is_err());
+        assert!(Sku::parse("T SHIRT".to_string()).is_err());
+        assert!(Sku::parse("A".repeat(65)).is_err());
+    }
+}
@@ -0,0 +1,804 @@
+use std::{cmp::Ordering, collections::{BTreeSet, HashMap}, sync::{Arc, Mutex}};
+
+use actix_web::web;
+use sqlx::{types::Json, Pool, Postgres};
+use tantivy::{
+    collector::{Collector, SegmentCollector},
+    columnar::{Column, StrColumn},
+    directory::MmapDirectory,
+    query::{AllQuery, BooleanQuery, BoostQuery, FuzzyTermQuery, Occur, Query, TermQuery},
+    schema::{Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, FAST, STRING},
+    DocId, Index, IndexReader, IndexWriter, ReloadPolicy, Score, SegmentOrdinal, SegmentReader, TantivyDocument, Term
+};
+
+use crate::app::models::{
+    product::{
+        category::{Category, CategoryRepository, CategoryTree},
+        AttributeValue, Attributes, Product, ProductRepository
+    },
+    user::Uuid
+};
+
+use super::{
+    category_facets, price_bucket, price_facets, rating_facets, BoxFuture, Facets, ProductEvent, Result, SearchBackend,
+    SearchHit, SearchQuery, SearchResults, SortOrder, PRICE_BOUNDS_CENTS, RATING_STEPS
+};
+
+const WRITER_HEAP_BYTES: usize = 50_000_000;
+
+// Fast fields are read by name
+const ID: &str = "id";
+const TITLE_SORT: &str = "title_sort";
+const CATEGORIES: &str = "categories";
+const FROM_PRICE: &str = "from_price";
+const RATING: &str = "rating";
+const IN_STOCK: &str = "in_stock";
+
+// Matches in the title count the most, close spellings less than exact ones
+const TITLE_BOOST: Score = 3.0;
+const ATTRIBUTES_BOOST: Score = 1.5;
+const DESCRIPTION_BOOST: Score = 1.0;
+const TYPO_BOOST: Score = 0.2;
+
+// Short words are rarely misspelled, long ones may have two typos
+pub fn typo_distance(word: &str) -> u8 {
+    match word.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+// What the index knows about a product
+#[derive(Debug, Clone)]
+pub struct IndexedProduct {
+    pub id: Uuid,
+    pub title: String,
+    pub description: String,
+    pub attributes: Attributes,
+    // Categories it's listed in and every one above them
+    pub category_ids: Vec<Uuid>,
+    pub from_price_cents: i64,
+    pub rating: f64,
+    pub in_stock: bool,
+}
+
+// Categories listed in, with all their ancestors once
+pub fn listed_under(tree: &CategoryTree, category_ids: &[Uuid]) -> Vec<Uuid> {
+    let listed: BTreeSet<[u8; 16]> = category_ids
+        .iter()
+        .flat_map(|category_id| tree.breadcrumbs(category_id))
+        .map(|crumb| *crumb.id.as_bytes())
+        .collect();
+
+    listed.into_iter().map(Uuid::from).collect()
+}
+
+fn attribute_text(value: &AttributeValue) -> String {
+    match value {
+        AttributeValue::Boolean(value) => value.to_string(),
+        AttributeValue::Number(value) => value.to_string(),
+        AttributeValue::Text(value) => value.clone(),
+    }
+}
+
+// Index
+
+struct Fields {
+    id: Field,
+    title: Field,
+    title_sort: Field,
+    description: Field,
+    attributes: Field,
+    categories: Field,
+    from_price: Field,
+    rating: Field,
+    in_stock: Field,
+}
+
+fn schema() -> (Schema, Fields) {
+    let mut builder = Schema::builder();
+
+    let text = TextOptions::default().set_indexing_options(
+        TextFieldIndexing::default()
+            .set_tokenizer("en_stem")
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions)
+    );
+
+    let fields = Fields {
+        id: builder.add_text_field(ID, STRING | FAST),
+        title: builder.add_text_field("title", text.clone()),
+        title_sort: builder.add_text_field(TITLE_SORT, FAST),
+        description: builder.add_text_field("description", text.clone()),
+        attributes: builder.add_text_field("attributes", text),
+        categories: builder.add_text_field(CATEGORIES, STRING | FAST),
+        from_price: builder.add_i64_field(FROM_PRICE, FAST),
+        rating: builder.add_f64_field(RATING, FAST),
+        in_stock: builder.add_bool_field(IN_STOCK, FAST),
+    };
+
+    (builder.build(), fields)
+}
+
+// Matching product for a page, products themselves come from the database
+#[derive(Debug)]
+pub struct IndexHit {
+    pub id: Uuid,
+    pub from_price_cents: i64,
+    pub in_stock: bool,
+}
+
+#[derive(Debug)]
+pub struct IndexMatches {
+    pub hits: Vec<IndexHit>,
+    pub total: i64,
+    pub in_stock: i64,
+    pub price_counts: Vec<(usize, i64)>,
+    pub rating_counts: [i64; RATING_STEPS.len()],
+    pub category_counts: Vec<(Uuid, i64)>,
+}
+
+// Blocking, async callers go through `web::block`
+pub struct ProductIndex {
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    fields: Fields,
+}
+
+impl ProductIndex {
+    pub fn in_ram() -> tantivy::Result<Self> {
+        let (schema, fields) = schema();
+
+        ProductIndex::new(Index::create_in_ram(schema), fields)
+    }
+
+    pub fn open(dir: &str) -> tantivy::Result<Self> {
+        let (schema, fields) = schema();
+
+        std::fs::create_dir_all(dir)?;
+
+        ProductIndex::new(Index::open_or_create(MmapDirectory::open(dir)?, schema)?, fields)
+    }
+
+    fn new(index: Index, fields: Fields) -> tantivy::Result<Self> {
+        let reader = index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into()?;
+        let writer = Mutex::new(index.writer_with_num_threads(1, WRITER_HEAP_BYTES)?);
+
+        Ok(ProductIndex { index, reader, writer, fields })
+    }
+
+    fn document(&self, product: &IndexedProduct) -> TantivyDocument {
+        let mut document = TantivyDocument::default();
+
+        document.add_text(self.fields.id, product.id.to_string());
+        document.add_text(self.fields.title, &product.title);
+        document.add_text(self.fields.title_sort, product.title.to_lowercase());
+        document.add_text(self.fields.description, &product.description);
+
+        for value in product.attributes.values() {
+            document.add_text(self.fields.attributes, attribute_text(value));
+        }
+
+        for category_id in &product.category_ids {
+            document.add_text(self.fields.categories, category_id.to_string());
+        }
+
+        document.add_i64(self.fields.from_price, product.from_price_cents);
+        document.add_f64(self.fields.rating, product.rating);
+        document.add_bool(self.fields.in_stock, product.in_stock);
+
+        document
+    }
+
+    // Removes the products, then adds the ones given back, in one commit
+    pub fn replace(&self, removed: &[Uuid], products: &[IndexedProduct]) -> tantivy::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+
+        for id in removed.iter().chain(products.iter().map(|product| &product.id)) {
+            writer.delete_term(Term::from_field_text(self.fields.id, &id.to_string()));
+        }
+
+        for product in products {
+            writer.add_document(self.document(product))?;
+        }
+
+        writer.commit()?;
+        self.reader.reload()
+    }
+
+    pub fn replace_all(&self, products: &[IndexedProduct]) -> tantivy::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+
+        writer.delete_all_documents()?;
+
+        for product in products {
+            writer.add_document(self.document(product))?;
+        }
+
+        writer.commit()?;
+        self.reader.reload()
+    }
+
+    // Every word has to match in some field, exactly or with a typo
+    fn text_query(&self, text: Option<&str>) -> tantivy::Result<Box<dyn Query>> {
+        // Stemmed word with the typos allowed for it as it was typed
+        let mut words: Vec<(String, u8)> = Vec::new();
+
+        if let Some(text) = text {
+            self.index
+                .tokenizer_for_field(self.fields.title)?
+                .token_stream(text)
+                .process(&mut |token| {
+                    let typed = text.get(token.offset_from..token.offset_to).unwrap_or(&token.text);
+                    words.push((token.text.clone(), typo_distance(typed)));
+                });
+        }
+
+        words.sort();
+        words.dedup();
+
+        if words.is_empty() {
+            return Ok(Box::new(AllQuery))
+        }
+
+        let fields = [
+            (self.fields.title, TITLE_BOOST),
+            (self.fields.attributes, ATTRIBUTES_BOOST),
+            (self.fields.description, DESCRIPTION_BOOST),
+        ];
+
+        let clauses = words
+            .iter()
+            .map(|(word, distance)| {
+                let mut alternatives: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+
+                for (field, boost) in fields {
+                    let term = Term::from_field_text(field, word);
+
+                    if *distance > 0 {
+                        let fuzzy = FuzzyTermQuery::new(term.clone(), *distance, true);
+                        alternatives.push((Occur::Should, Box::new(BoostQuery::new(Box::new(fuzzy), boost * TYPO_BOOST))));
+                    }
+
+                    let exact = TermQuery::new(term, IndexRecordOption::WithFreqs);
+                    alternatives.push((Occur::Should, Box::new(BoostQuery::new(Box::new(exact), boost))));
+                }
+
+                (Occur::Must, Box::new(BooleanQuery::new(alternatives)) as Box<dyn Query>)
+            })
+            .collect();
+
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    pub fn find(&self, query: &SearchQuery) -> tantivy::Result<IndexMatches> {
+        let searcher = self.reader.searcher();
+
+        let mut clauses = vec![(Occur::Must, self.text_query(query.text.as_deref())?)];
+
+        if let Some(category_ids) = &query.category_ids {
+            let terms = category_ids
+                .iter()
+                .map(|id| Term::from_field_text(self.fields.categories, &id.to_string()))
+                .collect();
+
+            clauses.push((Occur::Must, Box::new(BooleanQuery::new_multiterms_query(terms))));
+        }
+
+        // Without words to rank by, results go by title
+        let by_title = query.sort == SortOrder::Title || (query.sort == SortOrder::Relevance && query.text.is_none());
+
+        let filter = Filter {
+            min_price_cents: query.min_price_cents,
+            max_price_cents: query.max_price_cents,
+            min_rating: query.min_rating,
+            in_stock_only: query.in_stock_only,
+        };
+
+        let collected = searcher.search(&BooleanQuery::new(clauses), &MatchCollector { filter })?;
+
+        let mut matches = collected.matches;
+        matches.sort_by(|a, b| a.compare(b, query.sort, by_title));
+
+        let hits = matches
+            .iter()
+            .skip(query.offset.max(0) as usize)
+            .take(query.limit.max(0) as usize)
+            .filter_map(|found| {
+                uuid::Uuid::parse_str(&found.id)
+                    .ok()
+                    .map(|id| IndexHit { id: Uuid::parse(id), from_price_cents: found.from_price_cents, in_stock: found.in_stock })
+            })
+            .collect();
+
+        let counts = collected.counts;
+
+        Ok(IndexMatches {
+            hits,
+            total: matches.len() as i64,
+            in_stock: counts.in_stock,
+            price_counts: counts.prices.into_iter().enumerate().collect(),
+            rating_counts: counts.ratings,
+            category_counts: counts.categories
+                .into_iter()
+                .filter_map(|(id, count)| uuid::Uuid::parse_str(&id).ok().map(|id| (Uuid::parse(id), count)))
+                .collect(),
+        })
+    }
+}
+
+// Collecting
+
+#[derive(Debug)]
+struct Match {
+    // As indexed, a hyphenated UUID orders as the id bytes do
+    id: String,
+    score: Score,
+    from_price_cents: i64,
+    rating: f64,
+    in_stock: bool,
+    // Lowercase
+    title: String,
+}
+
+impl Match {
+    fn compare(&self, other: &Match, sort: SortOrder, by_title: bool) -> Ordering {
+        let ordering = match sort {
+            SortOrder::Relevance if !by_title => other.score.total_cmp(&self.score),
+            SortOrder::PriceAsc => self.from_price_cents.cmp(&other.from_price_cents),
+            SortOrder::PriceDesc => other.from_price_cents.cmp(&self.from_price_cents),
+            SortOrder::Rating => other.rating.total_cmp(&self.rating),
+            SortOrder::Relevance | SortOrder::Title => Ordering::Equal,
+        };
+
+        // Ties go by title and id as in Postgres, independent of segments
+        ordering
+            .then_with(|| self.title.cmp(&other.title))
+            .then_with(|| self.id.cmp(&other.id))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    in_stock: i64,
+    prices: [i64; PRICE_BOUNDS_CENTS.len() + 1],
+    ratings: [i64; RATING_STEPS.len()],
+    // Category ids as indexed
+    categories: HashMap<String, i64>,
+}
+
+impl Counts {
+    fn add(&mut self, other: Counts) {
+        self.in_stock += other.in_stock;
+
+        for (count, other) in self.prices.iter_mut().zip(other.prices) {
+            *count += other;
+        }
+
+        for (count, other) in self.ratings.iter_mut().zip(other.ratings) {
+            *count += other;
+        }
+
+        for (id, count) in other.categories {
+            *self.categories.entry(id).or_default() += count;
+        }
+    }
+}
+
+struct Collected {
+    matches: Vec<Match>,
+    counts: Counts,
+}
+
+#[derive(Clone, Copy)]
+struct Filter {
+    min_price_cents: Option<i64>,
+    max_price_cents: Option<i64>,
+    min_rating: Option<f64>,
+    in_stock_only: bool,
+}
+
+impl Filter {
+    fn accepts(&self, from_price_cents: i64, rating: f64, in_stock: bool) -> bool {
+        self.min_price_cents.is_none_or(|min| from_price_cents >= min)
+            && self.max_price_cents.is_none_or(|max| from_price_cents <= max)
+            && self.min_rating.is_none_or(|min| rating >= min)
+            && (in_stock || !self.in_stock_only)
+    }
+}
+
+// Filters on the numbers and counts facets over every match, sorting and
+// paging happen afterwards
+struct MatchCollector {
+    filter: Filter,
+}
+
+struct SegmentMatchCollector {
+    filter: Filter,
+    from_price: Column<i64>,
+    rating: Column<f64>,
+    in_stock: Column<bool>,
+    categories: Option<StrColumn>,
+    titles: Option<StrColumn>,
+    ids: Option<StrColumn>,
+    // Title and id ords, turned into text once the segment is done
+    matches: Vec<(Match, Option<u64>, Option<u64>)>,
+    category_ords: HashMap<u64, i64>,
+    counts: Counts,
+}
+
+impl Collector for MatchCollector {
+    type Fruit = Collected;
+    type Child = SegmentMatchCollector;
+
+    fn for_segment(&self, _segment: SegmentOrdinal, reader: &SegmentReader) -> tantivy::Result<SegmentMatchCollector> {
+        let fast_fields = reader.fast_fields();
+
+        Ok(SegmentMatchCollector {
+            filter: self.filter,
+            from_price: fast_fields.i64(FROM_PRICE)?,
+            rating: fast_fields.f64(RATING)?,
+            in_stock: fast_fields.bool(IN_STOCK)?,
+            categories: fast_fields.str(CATEGORIES)?,
+            titles: fast_fields.str(TITLE_SORT)?,
+            ids: fast_fields.str(ID)?,
+            matches: Vec::new(),
+            category_ords: HashMap::new(),
+            counts: Counts::default(),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        true
+    }
+
+    fn merge_fruits(&self, fruits: Vec<Collected>) -> tantivy::Result<Collected> {
+        let mut merged = Collected { matches: Vec::new(), counts: Counts::default() };
+
+        for fruit in fruits {
+            merged.matches.extend(fruit.matches);
+            merged.counts.add(fruit.counts);
+        }
+
+        Ok(merged)
+    }
+}
+
+impl SegmentCollector for SegmentMatchCollector {
+    type Fruit = Collected;
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        let from_price_cents = self.from_price.first(doc).unwrap_or(0);
+        let rating = self.rating.first(doc).unwrap_or(0.0);
+        let in_stock = self.in_stock.first(doc).unwrap_or(false);
+
+        if !self.filter.accepts(from_price_cents, rating, in_stock) {
+            return
+        }
+
+        if in_stock {
+            self.counts.in_stock += 1;
+        }
+
+        self.counts.prices[price_bucket(from_price_cents)] += 1;
+
+        for (count, step) in self.counts.ratings.iter_mut().zip(RATING_STEPS) {
+            if rating >= step as f64 {
+                *count += 1;
+            }
+        }
+
+        if let Some(categories) = &self.categories {
+            for ord in categories.term_ords(doc) {
+                *self.category_ords.entry(ord).or_default() += 1;
+            }
+        }
+
+        let title_ord = self.titles.as_ref().and_then(|titles| titles.term_ords(doc).next());
+        let id_ord = self.ids.as_ref().and_then(|ids| ids.term_ords(doc).next());
+
+        self.matches.push((
+            Match { id: String::new(), score, from_price_cents, rating, in_stock, title: String::new() },
+            title_ord,
+            id_ord,
+        ));
+    }
+
+    fn harvest(mut self) -> Collected {
+        let text = |column: &Option<StrColumn>, ord: u64| {
+            let mut text = String::new();
+
+            if let Some(column) = column {
+                let _ = column.ord_to_str(ord, &mut text);
+            }
+
+            text
+        };
+
+        for (ord, count) in self.category_ords {
+            self.counts.categories.insert(text(&self.categories, ord), count);
+        }
+
+        let matches = self.matches
+            .into_iter()
+            .map(|(found, title_ord, id_ord)| Match {
+                id: id_ord.map(|ord| text(&self.ids, ord)).unwrap_or_default(),
+                title: title_ord.map(|ord| text(&self.titles, ord)).unwrap_or_default(),
+                ..found
+            })
+            .collect();
+
+        Collected { matches, counts: self.counts }
+    }
+}
+
+// Backend
+
+// Embedded index, kept in step with the catalog through product events
+pub struct TantivySearch {
+    index: Arc<ProductIndex>,
+    // Events go one at a time, each reading the catalog afresh, so the last
+    // one leaves the index as the database is
+    applying: tokio::sync::Mutex<()>,
+}
+
+impl TantivySearch {
+    pub fn new(index: ProductIndex) -> Self {
+        TantivySearch { index: Arc::new(index), applying: tokio::sync::Mutex::new(()) }
+    }
+
+    pub async fn rebuild(&self, db: &Pool<Postgres>) -> Result<()> {
+        let _applying = self.applying.lock().await;
+
+        let products = fetch_indexed_products(db, None).await?;
+        let index = self.index.clone();
+
+        web::block(move || index.replace_all(&products)).await??;
+
+        Ok(())
+    }
+
+    async fn reindex(&self, db: &Pool<Postgres>, product_ids: Vec<Uuid>) -> Result<()> {
+        let _applying = self.applying.lock().await;
+
+        // Products deleted in the meantime aren't found and stay removed
+        let products = fetch_indexed_products(db, Some(&product_ids)).await?;
+        let index = self.index.clone();
+
+        web::block(move || index.replace(&product_ids, &products)).await??;
+
+        Ok(())
+    }
+}
+
+impl SearchBackend for TantivySearch {
+    fn search<'a>(&'a self, db: &'a Pool<Postgres>, query: &'a SearchQuery, tree: &'a CategoryTree) -> BoxFuture<'a, Result<SearchResults>> {
+        Box::pin(async move {
+            let index = self.index.clone();
+            let owned_query = query.clone();
+
+            let matches = web::block(move || index.find(&owned_query)).await??;
+
+            let product_ids: Vec<Uuid> = matches.hits.iter().map(|hit| hit.id.clone()).collect();
+
+            let mut products: HashMap<Uuid, Product> = Product::fetch_products(db, &product_ids)
+                .await?
+                .into_iter()
+                .map(|product| (product.id.clone(), product))
+                .collect();
+
+            let hits = matches.hits
+                .into_iter()
+                .filter_map(|hit| {
+                    products.remove(&hit.id).map(|product| SearchHit {
+                        product,
+                        from_price_cents: hit.from_price_cents,
+                        in_stock: hit.in_stock,
+                    })
+                })
+                .collect();
+
+            Ok(SearchResults {
+                hits,
+                total: matches.total,
+                facets: Facets {
+                    categories: category_facets(tree, matches.category_counts),
+                    prices: price_facets(&matches.price_counts),
+                    ratings: rating_facets(matches.rating_counts),
+                    in_stock: matches.in_stock,
+                },
+            })
+        })
+    }
+
+    fn apply<'a>(&'a self, db: &'a Pool<Postgres>, event: ProductEvent) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            match event {
+                ProductEvent::Changed(product_ids) => self.reindex(db, product_ids).await,
+                ProductEvent::Deleted(product_id) => self.reindex(db, vec![product_id]).await,
+                ProductEvent::StockTaken(variant_ids) => {
+                    let variant_ids: Vec<Vec<u8>> = variant_ids.iter().map(|id| id.as_bytes().to_vec()).collect();
+
+                    let product_ids = sqlx::query_scalar!(
+                        "SELECT DISTINCT productId FROM product_variants WHERE id = ANY($1)",
+                        &variant_ids
+                    )
+                    .fetch_all(db)
+                    .await?
+                    .into_iter()
+                    .map(Uuid::from)
+                    .collect();
+
+                    self.reindex(db, product_ids).await
+                },
+                ProductEvent::CategoriesChanged => self.rebuild(db).await,
+            }
+        })
+    }
+}
+
+// Every product when no ids are given
+async fn fetch_indexed_products(db: &Pool<Postgres>, product_ids: Option<&[Uuid]>) -> Result<Vec<IndexedProduct>> {
+    let tree = Category::fetch_category_tree(db).await?;
+
+    let product_ids: Option<Vec<Vec<u8>>> = product_ids.map(|ids| ids.iter().map(|id| id.as_bytes().to_vec()).collect());
+
+    let products = sqlx::query!(
+        r#"SELECT product.id, product.title, product.description,
+            product.attributes as "attributes: Json<Attributes>",
+            matches.fromPrice as "from_price_cents!", matches.rating as "rating!", matches.inStock as "in_stock!",
+            ARRAY(SELECT categoryId FROM product_categories WHERE productId = product.id) as "category_ids!"
+        FROM search_products(NULL, NULL, NULL, NULL, NULL, FALSE) matches
+        JOIN product ON product.id = matches.id
+        WHERE $1::BYTEA[] IS NULL OR product.id = ANY($1)"#,
+        product_ids.as_deref()
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|row| {
+        let category_ids: Vec<Uuid> = row.category_ids.into_iter().map(Uuid::from).collect();
+
+        IndexedProduct {
+            id: Uuid::from(row.id),
+            title: row.title,
+            description: row.description,
+            attributes: row.attributes.0,
+            category_ids: listed_under(&tree, &category_ids),
+            from_price_cents: row.from_price_cents,
+            rating: row.rating,
+            in_stock: row.in_stock,
+        }
+    })
+    .collect();
+
+    Ok(products)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::models::product::category::Slug;
+
+    fn product(id: u8, title: &str, description: &str, from_price_cents: i64, rating: f64, in_stock: bool) -> IndexedProduct {
+        IndexedProduct {
+            id: Uuid::from([id; 16]),
+            title: title.to_string(),
+            description: description.to_string(),
+            attributes: Attributes::new(),
+            category_ids: Vec::new(),
+            from_price_cents,
+            rating,
+            in_stock,
+        }
+    }
+
+    fn search(index: &ProductIndex, query: SearchQuery) -> Vec<u8> {
+        index.find(&SearchQuery { limit: 20, ..query })
+            .unwrap()
+            .hits
+            .into_iter()
+            .map(|hit| hit.id.as_bytes()[0])
+            .collect()
+    }
+
+    fn text(text: &str) -> SearchQuery {
+        SearchQuery { text: Some(text.to_string()), ..Default::default() }
+    }
+
+    #[test]
+    fn typos_still_find_products() {
+        let index = ProductIndex::in_ram().unwrap();
+        index.replace_all(&[
+            product(1, "Merino wool sweater", "", 5900, 4.5, true),
+            product(2, "Leather boots", "Waterproof", 12900, 4.0, true),
+        ]).unwrap();
+
+        assert_eq!(search(&index, text("sweatr")), [1]);
+        assert_eq!(search(&index, text("merino sweaters")), [1]);
+        assert_eq!(search(&index, text("waterprof bots")), [2]);
+        assert_eq!(typo_distance("hat"), 0);
+        assert!(search(&index, text("hat")).is_empty());
+    }
+
+    #[test]
+    fn exact_title_matches_rank_first() {
+        let index = ProductIndex::in_ram().unwrap();
+        let mut colour = product(3, "Plain shirt", "", 1500, 3.0, true);
+        colour.attributes.insert("colour".to_string(), AttributeValue::Text("linen".to_string()));
+
+        index.replace_all(&[
+            product(1, "Cotton shirt", "Goes well with linen trousers", 1900, 4.0, true),
+            product(2, "Linen shirt", "", 2900, 4.0, true),
+            colour,
+            product(4, "Lines notebook", "", 500, 4.0, true),
+        ]).unwrap();
+
+        assert_eq!(search(&index, text("linen")), [2, 3, 1, 4]);
+    }
+
+    #[test]
+    fn filters_facets_and_pages_cover_every_match() {
+        let category = |id: u8, parent_id: Option<u8>| Category {
+            id: Uuid::from([id; 16]),
+            parent_id: parent_id.map(|parent_id| Uuid::from([parent_id; 16])),
+            slug: Slug::from(format!("c{}", id)),
+            name: format!("c{}", id),
+            position: 0,
+        };
+        let tree = CategoryTree::new(vec![category(10, None), category(11, Some(10)), category(12, Some(10))]);
+
+        let mut shirt = product(1, "Wool shirt", "", 900, 4.5, true);
+        shirt.category_ids = listed_under(&tree, &[Uuid::from([11; 16]), Uuid::from([12; 16])]);
+        let mut socks = product(2, "Wool socks", "", 2600, 2.0, false);
+        socks.category_ids = listed_under(&tree, &[Uuid::from([12; 16])]);
+
+        let index = ProductIndex::in_ram().unwrap();
+        index.replace_all(&[shirt, socks, product(3, "Wool hat", "", 30000, 0.0, true)]).unwrap();
+
+        let all = index.find(&SearchQuery { sort: SortOrder::PriceDesc, limit: 2, offset: 1, ..text("wool") }).unwrap();
+        assert_eq!(all.total, 3);
+        assert_eq!(all.hits.iter().map(|hit| hit.id.as_bytes()[0]).collect::<Vec<u8>>(), [2, 1]);
+        assert_eq!(all.in_stock, 2);
+        assert_eq!(all.price_counts.iter().filter(|(_, count)| *count > 0).collect::<Vec<_>>(), [&(0, 1), &(2, 1), &(5, 1)]);
+        assert_eq!(all.rating_counts, [1, 1, 2, 2]);
+
+        let mut categories = all.category_counts.iter().map(|(id, count)| (id.as_bytes()[0], *count)).collect::<Vec<_>>();
+        categories.sort();
+        assert_eq!(categories, [(10, 2), (11, 1), (12, 2)]);
+
+        let in_parent = SearchQuery { category_ids: Some(tree.descendants(&Uuid::from([10; 16]))), sort: SortOrder::Title, ..Default::default() };
+        assert_eq!(search(&index, in_parent), [1, 2]);
+
+        let filtered = SearchQuery { min_price_cents: Some(500), max_price_cents: Some(5000), in_stock_only: true, ..text("wool") };
+        assert_eq!(search(&index, filtered), [1]);
+        assert_eq!(search(&index, SearchQuery { min_rating: Some(4.0), ..Default::default() }), [1]);
+    }
+
+    #[test]
+    fn ties_go_by_title_then_id_across_segments() {
+        let index = ProductIndex::in_ram().unwrap();
+        // Separate commits, separate segments
+        index.replace(&[], &[product(2, "Wool hat", "", 900, 4.0, true)]).unwrap();
+        index.replace(&[], &[product(1, "Wool hat", "", 900, 4.0, true)]).unwrap();
+        index.replace(&[], &[product(3, "Alpaca scarf", "", 900, 4.0, true)]).unwrap();
+
+        for sort in [SortOrder::PriceAsc, SortOrder::PriceDesc, SortOrder::Rating] {
+            assert_eq!(search(&index, SearchQuery { sort, ..Default::default() }), [3, 1, 2]);
+        }
+    }
+
+    #[test]
+    fn replacing_keeps_one_document_per_product() {
+        let index = ProductIndex::in_ram().unwrap();
+        index.replace(&[], &[product(1, "Wool shirt", "", 900, 0.0, true)]).unwrap();
+        index.replace(&[], &[product(1, "Linen shirt", "", 900, 0.0, true)]).unwrap();
+
+        assert_eq!(search(&index, text("shirt")), [1]);
+        assert!(search(&index, text("wool")).is_empty());
+
+        index.replace(&[Uuid::from([1; 16])], &[]).unwrap();
+        assert!(search(&index, text("shirt")).is_empty());
+    }
+}
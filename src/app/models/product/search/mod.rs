@@ -0,0 +1,306 @@
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use actix_web::{http::StatusCode, ResponseError};
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+
+use crate::{app::models::user::Uuid, repository::db::SqlxError};
+
+use super::{category::{CategoryTree, Slug}, Product};
+
+pub mod index;
+pub mod postgres;
+
+// Upper bounds of the price ranges counted for facets, the last range has none
+pub const PRICE_BOUNDS_CENTS: [i64; 5] = [1000, 2500, 5000, 10000, 25000];
+// Facets count products rated at least this many stars
+pub const RATING_STEPS: [u8; 4] = [4, 3, 2, 1];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    // Best matches first, by title without search text
+    #[default]
+    Relevance,
+    PriceAsc,
+    PriceDesc,
+    Rating,
+    Title,
+}
+
+impl SortOrder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortOrder::Relevance => "relevance",
+            SortOrder::PriceAsc => "price_asc",
+            SortOrder::PriceDesc => "price_desc",
+            SortOrder::Rating => "rating",
+            SortOrder::Title => "title",
+        }
+    }
+}
+
+// Filters left unset match every product
+#[derive(Debug, Clone, Default)]
+pub struct SearchQuery {
+    pub text: Option<String>,
+    // A category and everything below it
+    pub category_ids: Option<Vec<Uuid>>,
+    pub min_price_cents: Option<i64>,
+    pub max_price_cents: Option<i64>,
+    pub min_rating: Option<f64>,
+    pub in_stock_only: bool,
+    pub sort: SortOrder,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub product: Product,
+    // Lowest price among the variants
+    pub from_price_cents: i64,
+    pub in_stock: bool,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CategoryFacet {
+    pub id: Uuid,
+    pub slug: Slug,
+    pub name: String,
+    pub count: i64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PriceFacet {
+    pub min_cents: Option<i64>,
+    // Exclusive
+    pub max_cents: Option<i64>,
+    pub count: i64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct RatingFacet {
+    pub min_rating: u8,
+    pub count: i64,
+}
+
+// Counts over every product matching the query, not only the returned page
+#[derive(Debug, Default, Serialize)]
+pub struct Facets {
+    pub categories: Vec<CategoryFacet>,
+    pub prices: Vec<PriceFacet>,
+    pub ratings: Vec<RatingFacet>,
+    pub in_stock: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub hits: Vec<SearchHit>,
+    pub total: i64,
+    pub facets: Facets,
+}
+
+// Numbered like width_bucket in SQL, 0 below the first bound
+pub fn price_bucket(price_cents: i64) -> usize {
+    PRICE_BOUNDS_CENTS.iter().filter(|bound| **bound <= price_cents).count()
+}
+
+// Ranges without products are left out
+pub fn price_facets(bucket_counts: &[(usize, i64)]) -> Vec<PriceFacet> {
+    let mut facets: Vec<PriceFacet> = bucket_counts
+        .iter()
+        .filter(|(bucket, count)| *count > 0 && *bucket <= PRICE_BOUNDS_CENTS.len())
+        .map(|(bucket, count)| PriceFacet {
+            min_cents: bucket.checked_sub(1).map(|previous| PRICE_BOUNDS_CENTS[previous]),
+            max_cents: PRICE_BOUNDS_CENTS.get(*bucket).copied(),
+            count: *count,
+        })
+        .collect();
+
+    facets.sort_by_key(|facet| facet.min_cents);
+    facets
+}
+
+// Counts are per category including the ones below it, a product listed in
+// two subcategories is counted once for the parent
+pub fn category_facets(tree: &CategoryTree, counts: Vec<(Uuid, i64)>) -> Vec<CategoryFacet> {
+    let mut facets: Vec<CategoryFacet> = counts
+        .into_iter()
+        .filter_map(|(id, count)| {
+            tree.get(&id).map(|category| CategoryFacet {
+                id,
+                slug: category.slug.clone(),
+                name: category.name.clone(),
+                count,
+            })
+        })
+        .collect();
+
+    facets.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    facets
+}
+
+// Counts given in the order of RATING_STEPS
+pub fn rating_facets(counts: [i64; RATING_STEPS.len()]) -> Vec<RatingFacet> {
+    RATING_STEPS
+        .iter()
+        .zip(counts)
+        .map(|(step, count)| RatingFacet { min_rating: *step, count })
+        .collect()
+}
+
+// Backends
+
+// Catalog changes backends keeping their own index follow
+#[derive(Debug, Clone)]
+pub enum ProductEvent {
+    // Fields, variants or categories of the products changed
+    Changed(Vec<Uuid>),
+    Deleted(Uuid),
+    // Stock of the variants was taken at checkout
+    StockTaken(Vec<Uuid>),
+    // Categories moved or went away, listings of any product may be different
+    CategoriesChanged,
+}
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// Products and prices come from the database either way, backends find and count
+pub trait SearchBackend: Send + Sync {
+    fn search<'a>(&'a self, db: &'a Pool<Postgres>, query: &'a SearchQuery, tree: &'a CategoryTree) -> BoxFuture<'a, Result<SearchResults>>;
+    fn apply<'a>(&'a self, db: &'a Pool<Postgres>, event: ProductEvent) -> BoxFuture<'a, Result<()>>;
+}
+
+// SEARCH_BACKEND selects implementation: `postgres` (default) or `tantivy`,
+// an embedded index in SEARCH_INDEX_DIR, in memory when not set. The index
+// is rebuilt from the catalog on start
+pub async fn from_env(db: &Pool<Postgres>) -> Arc<dyn SearchBackend> {
+    dotenv().ok();
+
+    match std::env::var("SEARCH_BACKEND").as_deref() {
+        Ok("postgres") | Err(_) => Arc::new(postgres::PostgresSearch),
+        Ok("tantivy") => {
+            let index = match std::env::var("SEARCH_INDEX_DIR") {
+                Ok(dir) => index::ProductIndex::open(&dir),
+                Err(_) => index::ProductIndex::in_ram(),
+            };
+            let search = index::TantivySearch::new(index.expect("Error opening the search index"));
+
+            search.rebuild(db).await.expect("Error building the search index");
+
+            Arc::new(search)
+        },
+        Ok(other) => panic!("Unknown SEARCH_BACKEND: {}", other),
+    }
+}
+
+// Error
+pub type Result<T> = std::result::Result<T,Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Database(SqlxError),
+    Index(tantivy::TantivyError),
+    // Blocking pool went away before the index work finished
+    Interrupted,
+}
+
+impl From<SqlxError> for Error {
+    fn from(value: SqlxError) -> Self {
+        Error::Database(value)
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(value: sqlx::Error) -> Self {
+        Error::Database(SqlxError::from(value))
+    }
+}
+
+impl From<tantivy::TantivyError> for Error {
+    fn from(value: tantivy::TantivyError) -> Self {
+        Error::Index(value)
+    }
+}
+
+impl From<actix_web::error::BlockingError> for Error {
+    fn from(_: actix_web::error::BlockingError) -> Self {
+        Error::Interrupted
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Database(error) => { write!(f, "{}", error) },
+            Error::Index(error) => { write!(f, "{}", error) },
+            Error::Interrupted => { write!(f, "Search was interrupted") },
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::models::product::category::Category;
+
+    #[test]
+    fn price_buckets_become_ranges() {
+        let facets = price_facets(&[(5, 1), (0, 4), (2, 2), (3, 0)]);
+
+        assert_eq!(facets, [
+            PriceFacet { min_cents: None, max_cents: Some(1000), count: 4 },
+            PriceFacet { min_cents: Some(2500), max_cents: Some(5000), count: 2 },
+            PriceFacet { min_cents: Some(25000), max_cents: None, count: 1 },
+        ]);
+    }
+
+    #[test]
+    fn category_facets_name_known_categories() {
+        let category = |id: u8, slug: &str| Category {
+            id: Uuid::from([id; 16]),
+            parent_id: None,
+            slug: Slug::from(slug.to_string()),
+            name: slug.to_string(),
+            position: 0,
+        };
+        let tree = CategoryTree::new(vec![category(1, "shirts"), category(2, "boots"), category(3, "hats")]);
+
+        let facets = category_facets(&tree, vec![
+            (Uuid::from([1; 16]), 2),
+            (Uuid::from([9; 16]), 7),
+            (Uuid::from([3; 16]), 2),
+            (Uuid::from([2; 16]), 5),
+        ]);
+
+        let names: Vec<(&str, i64)> = facets.iter().map(|facet| (facet.name.as_str(), facet.count)).collect();
+        assert_eq!(names, [("boots", 5), ("hats", 2), ("shirts", 2)]);
+    }
+
+    #[test]
+    fn sort_orders_parse_from_query() {
+        #[derive(Deserialize)]
+        struct Params {
+            #[serde(default)]
+            sort: SortOrder,
+        }
+
+        let parse = |query: &str| actix_web::web::Query::<Params>::from_query(query).map(|params| params.sort);
+
+        assert_eq!(parse("sort=price_desc").unwrap(), SortOrder::PriceDesc);
+        assert_eq!(parse("").unwrap(), SortOrder::Relevance);
+        assert!(parse("sort=cheapest").is_err());
+        assert_eq!(SortOrder::PriceAsc.as_str(), "price_asc");
+    }
+}
@@ -0,0 +1,152 @@
+use sqlx::{types::Json, Pool, Postgres};
+
+use crate::{
+    app::models::{
+        product::{category::CategoryTree, Attributes, Product, Sku},
+        user::Uuid
+    },
+    repository::db::SqlxError
+};
+
+use super::{
+    category_facets, price_facets, rating_facets, BoxFuture, Facets, ProductEvent, Result, SearchBackend, SearchHit,
+    SearchQuery, SearchResults, PRICE_BOUNDS_CENTS
+};
+
+// Searches the tables themselves through search_products() in the database,
+// shared by the page and the facets. Nothing to keep in sync
+pub struct PostgresSearch;
+
+impl PostgresSearch {
+    async fn search_products(db: &Pool<Postgres>, query: &SearchQuery, tree: &CategoryTree) -> std::result::Result<SearchResults, SqlxError> {
+        let category_ids: Option<Vec<Vec<u8>>> = query.category_ids
+            .as_ref()
+            .map(|ids| ids.iter().map(|id| id.as_bytes().to_vec()).collect());
+
+        let hits = sqlx::query!(
+            r#"SELECT product.id, product.title, product.description, product.sku,
+                product.attributes as "attributes: Json<Attributes>",
+                (product.price * 100)::BIGINT as "price_cents!", matches.rating as "rating!",
+                matches.fromPrice as "from_price_cents!", matches.inStock as "in_stock!"
+            FROM search_products($1, $2, $3, $4, $5, $6) matches
+            JOIN product ON product.id = matches.id
+            ORDER BY
+                CASE WHEN $7 = 'price_asc' THEN matches.fromPrice END ASC,
+                CASE WHEN $7 = 'price_desc' THEN matches.fromPrice END DESC,
+                CASE WHEN $7 = 'rating' THEN matches.rating END DESC,
+                CASE WHEN $7 = 'relevance' THEN matches.rank END DESC,
+                product.title, product.id
+            LIMIT $8 OFFSET $9"#,
+            query.text,
+            category_ids.as_deref(),
+            query.min_price_cents,
+            query.max_price_cents,
+            query.min_rating,
+            query.in_stock_only,
+            query.sort.as_str(),
+            query.limit,
+            query.offset
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|row| SearchHit {
+            product: Product {
+                id: Uuid::from(row.id),
+                title: row.title,
+                description: row.description,
+                sku: Sku::from(row.sku),
+                attributes: row.attributes.0,
+                price_cents: row.price_cents,
+                rating: row.rating,
+            },
+            from_price_cents: row.from_price_cents,
+            in_stock: row.in_stock,
+        })
+        .collect();
+
+        let counts = sqlx::query!(
+            r#"SELECT COUNT(*) as "total!",
+                COUNT(*) FILTER (WHERE inStock) as "in_stock!",
+                COUNT(*) FILTER (WHERE rating >= 4) as "rating_4!",
+                COUNT(*) FILTER (WHERE rating >= 3) as "rating_3!",
+                COUNT(*) FILTER (WHERE rating >= 2) as "rating_2!",
+                COUNT(*) FILTER (WHERE rating >= 1) as "rating_1!"
+            FROM search_products($1, $2, $3, $4, $5, $6)"#,
+            query.text,
+            category_ids.as_deref(),
+            query.min_price_cents,
+            query.max_price_cents,
+            query.min_rating,
+            query.in_stock_only
+        )
+        .fetch_one(db)
+        .await?;
+
+        let price_counts: Vec<(usize, i64)> = sqlx::query!(
+            r#"SELECT width_bucket(fromPrice, $7::BIGINT[]) as "bucket!", COUNT(*) as "count!"
+            FROM search_products($1, $2, $3, $4, $5, $6)
+            GROUP BY 1"#,
+            query.text,
+            category_ids.as_deref(),
+            query.min_price_cents,
+            query.max_price_cents,
+            query.min_rating,
+            query.in_stock_only,
+            &PRICE_BOUNDS_CENTS[..]
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|row| (row.bucket as usize, row.count))
+        .collect();
+
+        // Every category a product is listed in counts for its ancestors too
+        let category_counts: Vec<(Uuid, i64)> = sqlx::query!(
+            r#"WITH RECURSIVE ancestry (categoryId, ancestorId) AS (
+                SELECT id, id FROM categories
+                UNION
+                SELECT ancestry.categoryId, categories.parentId FROM ancestry
+                JOIN categories ON categories.id = ancestry.ancestorId
+                WHERE categories.parentId IS NOT NULL
+            )
+            SELECT ancestry.ancestorId as "category_id!", COUNT(DISTINCT matches.id) as "count!"
+            FROM search_products($1, $2, $3, $4, $5, $6) matches
+            JOIN product_categories ON product_categories.productId = matches.id
+            JOIN ancestry ON ancestry.categoryId = product_categories.categoryId
+            GROUP BY ancestry.ancestorId"#,
+            query.text,
+            category_ids.as_deref(),
+            query.min_price_cents,
+            query.max_price_cents,
+            query.min_rating,
+            query.in_stock_only
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|row| (Uuid::from(row.category_id), row.count))
+        .collect();
+
+        Ok(SearchResults {
+            hits,
+            total: counts.total,
+            facets: Facets {
+                categories: category_facets(tree, category_counts),
+                prices: price_facets(&price_counts),
+                ratings: rating_facets([counts.rating_4, counts.rating_3, counts.rating_2, counts.rating_1]),
+                in_stock: counts.in_stock,
+            },
+        })
+    }
+}
+
+impl SearchBackend for PostgresSearch {
+    fn search<'a>(&'a self, db: &'a Pool<Postgres>, query: &'a SearchQuery, tree: &'a CategoryTree) -> BoxFuture<'a, Result<SearchResults>> {
+        Box::pin(async move { Ok(PostgresSearch::search_products(db, query, tree).await?) })
+    }
+
+    fn apply<'a>(&'a self, _db: &'a Pool<Postgres>, _event: ProductEvent) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
@@ -0,0 +1,132 @@
+use serde::Serialize;
+use sqlx::{types::Json, Pool, Postgres};
+
+use crate::{app::models::user::Uuid, repository::db::SqlxError};
+
+use super::{Attributes, Product, Sku};
+
+// What a customer puts in the basket, one size and colour of a product
+#[derive(Debug, Clone, Serialize)]
+pub struct Variant {
+    pub id: Uuid,
+    pub product_id: Uuid,
+    pub sku: Sku,
+    pub options: Attributes,
+    // Price of the product applies when not set
+    pub price_override_cents: Option<i64>,
+    pub stock: i32,
+    pub position: i32,
+}
+
+impl Variant {
+    pub fn price_cents(&self, product: &Product) -> i64 {
+        self.price_override_cents.unwrap_or(product.price_cents)
+    }
+}
+
+// Database
+
+pub trait VariantRepository<T: sqlx::Database> {
+    async fn fetch_variants(db: &Pool<T>, product_id: &Uuid) -> Result<Vec<Variant>, SqlxError>;
+    async fn fetch_variant(db: &Pool<T>, variant_id: &Uuid) -> Result<Variant, SqlxError>;
+    async fn create_variant(db: &Pool<T>, variant: &Variant) -> Result<(), SqlxError>;
+    // False when the variant doesn't belong to the product
+    async fn update_variant(db: &Pool<T>, variant: &Variant) -> Result<bool, SqlxError>;
+    async fn delete_variant(db: &Pool<T>, product_id: &Uuid, variant_id: &Uuid) -> Result<bool, SqlxError>;
+}
+
+impl VariantRepository<Postgres> for Variant {
+    async fn fetch_variants(db: &Pool<Postgres>, product_id: &Uuid) -> Result<Vec<Variant>, SqlxError> {
+        let rows = sqlx::query!(
+            r#"SELECT id, productId, sku, options as "options: Json<Attributes>",
+                (priceOverride * 100)::BIGINT as price_override_cents, stock, position
+            FROM product_variants WHERE productId = $1 ORDER BY position, sku"#,
+            &product_id.as_bytes()[..]
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(
+            rows.into_iter()
+                .map(|row| Variant {
+                    id: Uuid::from(row.id),
+                    product_id: Uuid::from(row.productid),
+                    sku: Sku::from(row.sku),
+                    options: row.options.0,
+                    price_override_cents: row.price_override_cents,
+                    stock: row.stock,
+                    position: row.position,
+                })
+                .collect()
+        )
+    }
+
+    async fn fetch_variant(db: &Pool<Postgres>, variant_id: &Uuid) -> Result<Variant, SqlxError> {
+        let row = sqlx::query!(
+            r#"SELECT id, productId, sku, options as "options: Json<Attributes>",
+                (priceOverride * 100)::BIGINT as price_override_cents, stock, position
+            FROM product_variants WHERE id = $1"#,
+            &variant_id.as_bytes()[..]
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(Variant {
+            id: Uuid::from(row.id),
+            product_id: Uuid::from(row.productid),
+            sku: Sku::from(row.sku),
+            options: row.options.0,
+            price_override_cents: row.price_override_cents,
+            stock: row.stock,
+            position: row.position,
+        })
+    }
+
+    async fn create_variant(db: &Pool<Postgres>, variant: &Variant) -> Result<(), SqlxError> {
+        sqlx::query!(
+            "INSERT INTO product_variants (id, productId, sku, options, priceOverride, stock, position)
+            VALUES ($1, $2, $3, $4, $5::BIGINT::NUMERIC / 100, $6, $7)",
+            &variant.id.as_bytes()[..],
+            &variant.product_id.as_bytes()[..],
+            variant.sku.0,
+            Json(&variant.options) as _,
+            variant.price_override_cents,
+            variant.stock,
+            variant.position
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn update_variant(db: &Pool<Postgres>, variant: &Variant) -> Result<bool, SqlxError> {
+        let result = sqlx::query!(
+            "UPDATE product_variants SET sku = $3, options = $4, priceOverride = $5::BIGINT::NUMERIC / 100, stock = $6, position = $7
+            WHERE id = $1 AND productId = $2",
+            &variant.id.as_bytes()[..],
+            &variant.product_id.as_bytes()[..],
+            variant.sku.0,
+            Json(&variant.options) as _,
+            variant.price_override_cents,
+            variant.stock,
+            variant.position
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn delete_variant(db: &Pool<Postgres>, product_id: &Uuid, variant_id: &Uuid) -> Result<bool, SqlxError> {
+        let result = sqlx::query!(
+            "DELETE FROM product_variants WHERE id = $1 AND productId = $2",
+            &variant_id.as_bytes()[..],
+            &product_id.as_bytes()[..]
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+}
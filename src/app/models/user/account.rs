@@ -0,0 +1,203 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+
+use crate::repository::db::SqlxError;
+
+use super::{session::unix_time, Role, User, Uuid};
+
+// Status
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum AccountStatus {
+    Active,
+    Disabled {
+        reason: Option<String>,
+    },
+    // Lifts itself once `until` (unix time) has passed
+    Banned {
+        until: i64,
+        reason: Option<String>,
+    },
+    // Anonymised, waiting for the purge
+    Deleted,
+}
+
+impl AccountStatus {
+    fn from_row(status: &str, reason: Option<String>, banned_until: Option<i64>, now: i64) -> Self {
+        match (status, banned_until) {
+            ("active", _) => AccountStatus::Active,
+            ("banned", Some(until)) if until > now => AccountStatus::Banned { until, reason },
+            ("banned", _) => AccountStatus::Active,
+            ("deleted", _) => AccountStatus::Deleted,
+            // Anything we don't know keeps the account blocked
+            _ => AccountStatus::Disabled { reason },
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Disabled { .. } => "disabled",
+            AccountStatus::Banned { .. } => "banned",
+            AccountStatus::Deleted => "deleted",
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        *self == AccountStatus::Active
+    }
+
+    fn reason(&self) -> Option<&str> {
+        match self {
+            AccountStatus::Active | AccountStatus::Deleted => None,
+            AccountStatus::Disabled { reason } | AccountStatus::Banned { reason, .. } => reason.as_deref(),
+        }
+    }
+
+    fn banned_until(&self) -> Option<i64> {
+        match self {
+            AccountStatus::Banned { until, .. } => Some(*until),
+            _ => None,
+        }
+    }
+}
+
+// Sign in and every authorized request of a blocked account are refused,
+// the body tells the client why and until when
+#[derive(Debug)]
+pub struct AccountBlocked(pub AccountStatus);
+
+impl core::fmt::Display for AccountBlocked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Account is {}", self.0.as_str())
+    }
+}
+
+impl ResponseError for AccountBlocked {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .json(&self.0)
+    }
+}
+
+// Administrative view of an account
+#[derive(Debug, Serialize)]
+pub struct AccountState {
+    pub role: Role,
+    pub status: AccountStatus,
+    pub email_verified: bool,
+    pub totp_enabled: bool,
+}
+
+// Database
+
+pub trait AccountRepository<T: sqlx::Database> {
+    async fn fetch_account_state(db: &Pool<T>, user_id: &Uuid) -> Result<AccountState, SqlxError>;
+    async fn fetch_status(db: &Pool<T>, user_id: &Uuid) -> Result<AccountStatus, SqlxError>;
+    // False when the user doesn't exist or is deleted
+    async fn set_status(db: &Pool<T>, user_id: &Uuid, status: &AccountStatus) -> Result<bool, SqlxError>;
+    // Hard deletes users deleted longer than the retention ago, with what they left behind
+    async fn purge_deleted_users(db: &Pool<T>, retention_secs: i64) -> Result<u64, SqlxError>;
+}
+
+impl AccountRepository<Postgres> for User {
+    async fn fetch_account_state(db: &Pool<Postgres>, user_id: &Uuid) -> Result<AccountState, SqlxError> {
+        let row = sqlx::query!(
+            r#"SELECT role, status, statusReason, EXTRACT(EPOCH FROM bannedUntil)::BIGINT as banned_until,
+                emailVerified, totpEnabled
+            FROM users WHERE id = $1"#,
+            &user_id.0
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(AccountState {
+            role: Role::parse(&row.role).unwrap_or(Role::User),
+            status: AccountStatus::from_row(&row.status, row.statusreason, row.banned_until, unix_time() as i64),
+            email_verified: row.emailverified,
+            totp_enabled: row.totpenabled,
+        })
+    }
+
+    async fn fetch_status(db: &Pool<Postgres>, user_id: &Uuid) -> Result<AccountStatus, SqlxError> {
+        let row = sqlx::query!(
+            r#"SELECT status, statusReason, EXTRACT(EPOCH FROM bannedUntil)::BIGINT as banned_until
+            FROM users WHERE id = $1"#,
+            &user_id.0
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(AccountStatus::from_row(&row.status, row.statusreason, row.banned_until, unix_time() as i64))
+    }
+
+    async fn set_status(db: &Pool<Postgres>, user_id: &Uuid, status: &AccountStatus) -> Result<bool, SqlxError> {
+        let result = sqlx::query!(
+            "UPDATE users SET status = $2, statusReason = $3, bannedUntil = to_timestamp($4::BIGINT) AT TIME ZONE 'UTC'
+            WHERE id = $1 AND deletedAt IS NULL",
+            &user_id.0,
+            status.as_str(),
+            status.reason(),
+            status.banned_until()
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn purge_deleted_users(db: &Pool<Postgres>, retention_secs: i64) -> Result<u64, SqlxError> {
+        let mut tx = db.begin().await?;
+
+        let user_ids = sqlx::query_scalar!(
+            "SELECT id FROM users WHERE deletedAt < NOW() - make_interval(secs => $1::BIGINT) FOR UPDATE",
+            retention_secs
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        sqlx::query!("DELETE FROM comment WHERE userId = ANY($1)", &user_ids)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!("DELETE FROM basket WHERE userId = ANY($1)", &user_ids)
+            .execute(&mut *tx)
+            .await?;
+
+        let result = sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &user_ids)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bans_lift_once_expired() {
+        let reason = Some("Spam".to_string());
+
+        assert_eq!(
+            AccountStatus::from_row("banned", reason.clone(), Some(200), 100),
+            AccountStatus::Banned { until: 200, reason: reason.clone() }
+        );
+        assert!(AccountStatus::from_row("banned", reason.clone(), Some(100), 100).is_active());
+        assert!(!AccountStatus::from_row("disabled", None, None, 100).is_active());
+        assert!(!AccountStatus::from_row("unknown", None, None, 100).is_active());
+        assert_eq!(AccountStatus::from_row("deleted", None, None, 100), AccountStatus::Deleted);
+
+        let banned = serde_json::to_value(AccountStatus::Banned { until: 200, reason }).unwrap();
+        assert_eq!(banned, serde_json::json!({ "status": "banned", "until": 200, "reason": "Spam" }));
+    }
+}
@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+
+use crate::repository::db::SqlxError;
+
+use super::{User, Uuid};
+
+// Keys are told apart from JWTs by the prefix
+const KEY_PREFIX: &str = "cws_";
+// Start of the key shown in listings, to recognise it
+const DISPLAY_PREFIX_LENGTH: usize = 12;
+pub const NAME_MAX_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+    // Safe requests
+    Read,
+    // Requests changing state
+    Write,
+    // Admin endpoints, only for keys of admins
+    Admin,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::Read => "read",
+            ApiScope::Write => "write",
+            ApiScope::Admin => "admin",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Self> {
+        match scope {
+            "read" => Some(ApiScope::Read),
+            "write" => Some(ApiScope::Write),
+            "admin" => Some(ApiScope::Admin),
+            _ => None,
+        }
+    }
+}
+
+// Key as handed to the user, shown once, only its hash is stored
+pub struct ApiKeySecret(String);
+
+impl ApiKeySecret {
+    pub fn generate() -> Self {
+        let random: String = [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()]
+            .iter()
+            .map(|part| part.simple().to_string())
+            .collect();
+
+        ApiKeySecret(format!("{}{}", KEY_PREFIX, random))
+    }
+
+    pub fn parse(key: &str) -> Option<Self> {
+        let key = key.trim();
+
+        (key.starts_with(KEY_PREFIX) && key.len() > DISPLAY_PREFIX_LENGTH)
+            .then(|| ApiKeySecret(key.to_string()))
+    }
+
+    fn hash(&self) -> String {
+        Sha256::digest(self.0.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    fn prefix(&self) -> &str {
+        &self.0[..DISPLAY_PREFIX_LENGTH]
+    }
+}
+
+impl std::fmt::Display for ApiKeySecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn parse_scopes(scopes: Vec<String>) -> Vec<ApiScope> {
+    scopes.iter().filter_map(|scope| ApiScope::parse(scope)).collect()
+}
+
+// Times are unix seconds, like the ones of sessions
+#[derive(Debug, Serialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<ApiScope>,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+}
+
+// What a presented key grants
+pub struct ApiKeyGrant {
+    pub user_id: Uuid,
+    pub scopes: Vec<ApiScope>,
+}
+
+// Database
+
+pub trait ApiKeyRepository<T: sqlx::Database> {
+    async fn create_api_key(db: &Pool<T>, user_id: &Uuid, name: &str, scopes: &[ApiScope], secret: &ApiKeySecret) -> Result<ApiKey, SqlxError>;
+    // Keys not revoked, newest first
+    async fn fetch_api_keys(db: &Pool<T>, user_id: &Uuid) -> Result<Vec<ApiKey>, SqlxError>;
+    async fn revoke_api_key(db: &Pool<T>, user_id: &Uuid, key_id: &Uuid) -> Result<bool, SqlxError>;
+    // Looks the key up and records its use
+    async fn authenticate_api_key(db: &Pool<T>, secret: &ApiKeySecret) -> Result<Option<ApiKeyGrant>, SqlxError>;
+}
+
+impl ApiKeyRepository<Postgres> for User {
+    async fn create_api_key(db: &Pool<Postgres>, user_id: &Uuid, name: &str, scopes: &[ApiScope], secret: &ApiKeySecret) -> Result<ApiKey, SqlxError> {
+        let id = uuid::Uuid::new_v4();
+        let scope_names: Vec<String> = scopes.iter().map(|scope| scope.as_str().to_string()).collect();
+
+        let created_at = sqlx::query_scalar!(
+            r#"INSERT INTO api_keys (id, userId, name, prefix, keyHash, scopes) VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING EXTRACT(EPOCH FROM createdAt)::BIGINT as "created_at!""#,
+            &id.as_bytes()[..],
+            &user_id.0,
+            name,
+            secret.prefix(),
+            secret.hash(),
+            &scope_names
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(ApiKey {
+            id: Uuid::parse(id),
+            name: name.to_string(),
+            prefix: secret.prefix().to_string(),
+            scopes: scopes.to_vec(),
+            created_at,
+            last_used_at: None,
+        })
+    }
+
+    async fn fetch_api_keys(db: &Pool<Postgres>, user_id: &Uuid) -> Result<Vec<ApiKey>, SqlxError> {
+        let rows = sqlx::query!(
+            r#"SELECT id, name, prefix, scopes,
+                EXTRACT(EPOCH FROM createdAt)::BIGINT as "created_at!",
+                EXTRACT(EPOCH FROM lastUsedAt)::BIGINT as last_used_at
+            FROM api_keys WHERE userId = $1 AND revokedAt IS NULL ORDER BY createdAt DESC"#,
+            &user_id.0
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(
+            rows.into_iter()
+                .map(|row| ApiKey {
+                    id: Uuid::from(row.id),
+                    name: row.name,
+                    prefix: row.prefix,
+                    scopes: parse_scopes(row.scopes),
+                    created_at: row.created_at,
+                    last_used_at: row.last_used_at,
+                })
+                .collect()
+        )
+    }
+
+    async fn revoke_api_key(db: &Pool<Postgres>, user_id: &Uuid, key_id: &Uuid) -> Result<bool, SqlxError> {
+        let result = sqlx::query!(
+            "UPDATE api_keys SET revokedAt = NOW() WHERE id = $1 AND userId = $2 AND revokedAt IS NULL",
+            &key_id.0,
+            &user_id.0
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn authenticate_api_key(db: &Pool<Postgres>, secret: &ApiKeySecret) -> Result<Option<ApiKeyGrant>, SqlxError> {
+        let row = sqlx::query!(
+            "UPDATE api_keys SET lastUsedAt = NOW()
+                WHERE keyHash = $1 AND revokedAt IS NULL
+                RETURNING userId, scopes",
+            secret.hash()
+        )
+        .fetch_optional(db)
+        .await?;
+
+        Ok(row.map(|row| ApiKeyGrant {
+            user_id: Uuid::from(row.userid),
+            scopes: parse_scopes(row.scopes),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_recognised_by_prefix() {
+        let secret = ApiKeySecret::generate();
+        let key = secret.to_string();
+
+        assert!(key.starts_with(KEY_PREFIX));
+        assert_eq!(secret.prefix(), &key[..DISPLAY_PREFIX_LENGTH]);
+        assert_eq!(ApiKeySecret::parse(&key).unwrap().hash(), secret.hash());
+        assert_ne!(ApiKeySecret::generate().hash(), secret.hash());
+
+        // Access tokens are JWTs
+        assert!(ApiKeySecret::parse("eyJhbGciOiJIUzI1NiJ9.e30.signature").is_none());
+    }
+}
@@ -1,177 +1,175 @@
-use jsonwebtoken::{decode_header, DecodingKey, EncodingKey, Header, TokenData, Validation};
-use redis::Commands;
-use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+
+use actix_web::{http::StatusCode, ResponseError};
+use jsonwebtoken::{errors::ErrorKind, TokenData, Validation};
+use redis::AsyncCommands;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 
 use crate::app;
 
-use super::Uuid;
+use super::{keys::{KeyRing, KEY_RING}, session::{self, session_key, unix_time, user_sessions_key, ClientInfo, Rotation, Session}, Uuid};
 
-trait TokenBody {}
+pub const ACCESS_TOKEN_TTL: u64 = 60*60;
+pub const REFRESH_TOKEN_TTL: u64 = 60*60*24;
 
-#[derive(Debug, Clone, Serialize)]
-struct AccessToken(String);
+// Claims
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct AccessTokenBody {
-     user_id: Uuid,
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenType {
+    Access,
+    Refresh,
 }
 
-impl AccessToken {
-    pub async fn encode(cache: &mut redis::Connection,key_id: &Uuid, seconds_to_live: i64, body: AccessTokenBody)  ->  Result<Self> 
-    {
-        let headers: Header = Header {
-            kid: Some(key_id.to_string().clone()),
-            ..Default::default()
-         };
-
-        let key = std::env::var("ACCESS_TOKEN_SECRET")
-            .expect("ACCESS_TOKEN_SECRET env must be provided");
-        let encoded_key = EncodingKey::from_secret(key.as_bytes());
-
-        let token = jsonwebtoken::encode(
-            &headers,
-            &body,
-            &encoded_key
-        )?;
-        
-        redis::pipe()
-            .set(key_id.to_string(), "").ignore()
-            .expire(key_id.to_string(),seconds_to_live)
-            .query(cache)?;
-
-        Ok(AccessToken(token))
-    }
-
-    pub fn decode(&self) -> Result<TokenData<AccessTokenBody>> {
-        let key = std::env::var("ACCESS_TOKEN_SECRET")
-            .expect("ACCESS_TOKEN_SECRET env must be provided");
-        let encoded_key = DecodingKey::from_secret(key.as_bytes());
-        
-        let decoded_token = jsonwebtoken::decode(
-            &self.0,
-            &encoded_key,
-            &Validation::default()
-        )?;
-
-        Ok(decoded_token)
+impl TokenType {
+    pub fn ttl(&self) -> u64 {
+        match self {
+            TokenType::Access => ACCESS_TOKEN_TTL,
+            TokenType::Refresh => REFRESH_TOKEN_TTL,
+        }
     }
+}
 
-    pub async fn verify(&self, cache: &mut redis::Connection) -> Result<&Self> {
-        let header = decode_header(&self.0)?;
+// Registered claims around the token body
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims<B> {
+    pub iss: String,
+    pub aud: String,
+    pub iat: u64,
+    pub exp: u64,
+    pub jti: String,
+    // Keeps refresh tokens from being accepted as access tokens and back
+    pub typ: TokenType,
+    #[serde(flatten)]
+    pub body: B,
+}
 
-        let is_exits: bool = cache
-            .exists(header.kid)?;
+pub struct TokenConfig {
+    issuer: String,
+    audience: String,
+    leeway: u64,
+}
 
-        if is_exits {
-            Ok(self)
-        } else {
-            Err(Error::InvalidSession)
+impl TokenConfig {
+    fn from_env() -> Self {
+        TokenConfig {
+            issuer: std::env::var("JWT_ISSUER").unwrap_or_else(|_| "cwshop".to_string()),
+            audience: std::env::var("JWT_AUDIENCE").unwrap_or_else(|_| "cwshop".to_string()),
+            leeway: std::env::var("JWT_LEEWAY_SECONDS")
+                .map(|leeway| leeway.parse().expect("JWT_LEEWAY_SECONDS must be a number"))
+                .unwrap_or(30),
         }
     }
 
-    pub async fn destroy(&self, cache: &mut redis::Connection) -> Result<&Self> {
-        let header = decode_header(&self.0)?;
-
-        cache
-            .del(header.kid)?;
+    fn claims<B>(&self, typ: TokenType, body: B, now: u64) -> Claims<B> {
+        Claims {
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            iat: now,
+            exp: now + typ.ttl(),
+            jti: uuid::Uuid::new_v4().to_string(),
+            typ,
+            body,
+        }
+    }
 
-        Ok(self)
+    fn validation(&self) -> Validation {
+        let mut validation = Validation::default();
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.leeway = self.leeway;
+        validation
     }
+}
 
-    pub async fn update(&self,cache: &mut redis::Connection, seconds_to_live: i64) -> Result<&Self> {
-        let header = jsonwebtoken::decode_header(&self.0)?;
+static TOKEN_CONFIG: LazyLock<TokenConfig> = LazyLock::new(TokenConfig::from_env);
 
-        cache
-            .expire(header.kid, seconds_to_live)?;
-        
-        Ok(self)
-    }
+// Read on start rather than on the first token check
+pub fn load_token_config() {
+    LazyLock::force(&TOKEN_CONFIG);
+}
 
-    pub fn parse(token_string: String) -> Result<Self> {
-        jsonwebtoken::decode_header(&token_string)?;
+fn encode_token<B: Serialize>(ring: &KeyRing, config: &TokenConfig, typ: TokenType, body: B, now: u64) -> Result<String> {
+    ring.encode(&config.claims(typ, body, now))
+}
 
-        Ok(AccessToken(token_string))
+fn decode_token<B: DeserializeOwned>(ring: &KeyRing, config: &TokenConfig, token: &str, typ: TokenType) -> Result<TokenData<Claims<B>>> {
+    let data = ring.decode::<Claims<B>>(token, &config.validation())?;
+
+    if data.claims.typ == typ {
+        Ok(data)
+    } else {
+        Err(Error::InvalidToken(ErrorKind::InvalidToken))
     }
 }
 
+// Tokens
+
 #[derive(Debug, Clone, Serialize)]
-struct RefreshToken(String);
+pub struct AccessToken(String);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-struct RefreshTokenBody {
-    user_id: Uuid
+pub struct AccessTokenBody {
+     pub user_id: Uuid,
+     // Session the token belongs to
+     pub sid: String,
 }
 
-impl RefreshToken {
-    pub async fn encode(cache: &mut redis::Connection, key_id: &Uuid, seconds_to_live: i64, body: RefreshTokenBody)  ->  Result<Self> 
-    {
-        let headers: Header = Header {
-            kid: Some(key_id.to_string()),
-            ..Default::default()
-         };
-
-        let key = std::env::var("REFRESH_TOKEN_SECRET")
-            .expect("REFRESH_TOKEN_SECRET env must be provided");
-        let encoded_key = EncodingKey::from_secret(key.as_bytes());
-
-        let token = jsonwebtoken::encode(
-            &headers,
-            &body,
-            &encoded_key
-        )?;
-        
-        redis::pipe()
-            .set(key_id.to_string(), "").ignore()
-            .expire(key_id.to_string(),seconds_to_live)
-            .query(cache)?;
-
-        Ok(RefreshToken(token))
+impl AccessToken {
+    pub fn encode(body: AccessTokenBody) -> Result<Self> {
+        Ok(AccessToken(encode_token(&KEY_RING, &TOKEN_CONFIG, TokenType::Access, body, unix_time())?))
     }
 
-    pub fn decode(&self) -> Result<TokenData<RefreshTokenBody>> {
-        let key = std::env::var("REFRESH_TOKEN_SECRET")
-            .expect("REFRESH_TOKEN_SECRET env must be provided");
-        let encoded_key = DecodingKey::from_secret(key.as_bytes());
-        
-        let decoded_token = jsonwebtoken::decode(
-            &self.0,
-            &encoded_key,
-            &Validation::default()
-        )?;
-
-        Ok(decoded_token)
+    pub fn decode(&self) -> Result<TokenData<Claims<AccessTokenBody>>> {
+        decode_token(&KEY_RING, &TOKEN_CONFIG, &self.0, TokenType::Access)
     }
 
-    pub async fn verify(&self, cache: &mut redis::Connection) -> Result<&Self> {
-        let header = decode_header(&self.0)?;
+    // Session has to exist and belong to the token owner
+    pub async fn verify(&self, cache: &mut redis::aio::MultiplexedConnection) -> Result<&Self> {
+        let body = self.decode()?.claims.body;
+        let owner = session::touch(cache, &body.sid).await?;
 
-        let is_exists: bool = cache
-            .exists(header.kid)?;
-
-        if is_exists {
+        if owner == Some(body.user_id.to_string()) {
             Ok(self)
         } else {
             Err(Error::InvalidSession)
         }
     }
 
-    pub async fn destroy(&self, cache: &mut redis::Connection) -> Result<&Self> {
-        let header = decode_header(&self.0)?;
+    pub fn parse(token_string: String) -> Result<Self> {
+        jsonwebtoken::decode_header(&token_string)?;
+
+        Ok(AccessToken(token_string))
+    }
+
+    pub fn session_id(&self) -> Result<String> {
+        Ok(self.decode()?.claims.body.sid)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RefreshToken(String);
 
-        cache
-            .del(header.kid)?;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshTokenBody {
+    pub user_id: Uuid,
+    pub sid: String,
+}
 
-        Ok(self)
+impl RefreshToken {
+    pub fn encode(body: RefreshTokenBody) -> Result<Self> {
+        Ok(RefreshToken(encode_token(&KEY_RING, &TOKEN_CONFIG, TokenType::Refresh, body, unix_time())?))
     }
 
-    pub async fn update(&self,cache: &mut redis::Connection, seconds_to_live: i64) -> Result<&Self> {
-        let header = jsonwebtoken::decode_header(&self.0)?;
+    pub fn decode(&self) -> Result<TokenData<Claims<RefreshTokenBody>>> {
+        decode_token(&KEY_RING, &TOKEN_CONFIG, &self.0, TokenType::Refresh)
+    }
 
-        cache
-            .expire(header.kid, seconds_to_live)?;
-        
-        Ok(self)
+    // Token id, the session keeps the one of the latest refresh token
+    pub fn jti(&self) -> Result<String> {
+        Ok(self.decode()?.claims.jti)
     }
 
     pub fn parse(token_string: String) -> Result<Self> {
@@ -180,36 +178,58 @@ impl RefreshToken {
         Ok(RefreshToken(token_string))
     }
 }
-struct Tokens {
-    refresh: RefreshToken,
-    access: AccessToken
+
+impl std::fmt::Display for AccessToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::fmt::Display for RefreshToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Tokens {
+    pub refresh: RefreshToken,
+    pub access: AccessToken
 }
 
 pub trait Authorization<T> {
-    async fn genrate_tokens(&self, cache: &mut T) -> Result<Tokens>;
-    async fn refresh_tokens(&self, cache: &mut T, tokens: Tokens) -> Result<Tokens>;
+    async fn genrate_tokens(&self, cache: &mut T, client: &ClientInfo) -> Result<Tokens>;
+    async fn refresh_tokens(&self, cache: &mut T, refresh: &RefreshToken) -> Result<Tokens>;
 }
 
-impl Authorization<redis::Connection> for app::models::user::User {
-    async fn genrate_tokens(&self, cache: &mut redis::Connection) -> Result<Tokens> {
+// Destroys sessions of the user, known through the per user index,
+// except the `keep` one when given
+pub async fn revoke_user_sessions(cache: &mut redis::aio::MultiplexedConnection, user_id: &Uuid, keep: Option<&str>) -> Result<()> {
+    let index = user_sessions_key(user_id);
+    let sessions: Vec<String> = cache.smembers(&index).await?;
+
+    let mut pipe = redis::pipe();
+    for session in sessions.iter().filter(|&session| Some(session.as_str()) != keep) {
+        pipe.del(session_key(session)).ignore();
+        pipe.srem(&index, session).ignore();
+    }
+    pipe.query_async::<_, ()>(cache).await?;
+
+    Ok(())
+}
+
+impl Authorization<redis::aio::MultiplexedConnection> for app::models::user::User {
+    async fn genrate_tokens(&self, cache: &mut redis::aio::MultiplexedConnection, client: &ClientInfo) -> Result<Tokens> {
         let tokens_id: Uuid = Uuid::parse(uuid::Uuid::new_v4());
 
-        let access_token_body = AccessTokenBody { user_id: self.id.clone() };
-        let refresh_token_body = RefreshTokenBody { user_id: self.id.clone() };
+        let access_token_body = AccessTokenBody { user_id: self.id.clone(), sid: tokens_id.to_string() };
+        let refresh_token_body = RefreshTokenBody { user_id: self.id.clone(), sid: tokens_id.to_string() };
 
-        let access = AccessToken::encode(
-            cache,
-            &tokens_id,
-            60*60,
-            access_token_body
-        ).await?;
+        let access = AccessToken::encode(access_token_body)?;
+        let refresh = RefreshToken::encode(refresh_token_body)?;
 
-        let refresh = RefreshToken::encode(
-            cache,
-            &tokens_id,
-            60*60*24,
-            refresh_token_body
-        ).await?;
+        // Both tokens share the session, it lives as long as the refresh token
+        Session::create(cache, &tokens_id, &self.id, client, &refresh.jti()?, REFRESH_TOKEN_TTL as i64).await?;
 
         Ok(
             Tokens { refresh, access }
@@ -217,44 +237,43 @@ impl Authorization<redis::Connection> for app::models::user::User {
 
     }
 
-    async fn refresh_tokens(&self, cache: &mut redis::Connection, tokens: Tokens) -> Result<Tokens> {
-        let access_validation_result = tokens.access.verify(cache).await;
-        match access_validation_result {
-            Ok(access) => {
-                access.update(cache, 60*60 ).await?;
-                tokens.refresh.update(cache, 60*60*24 ).await?;
-
-                Ok(tokens)
-            },
-            Err(_) => {
-                tokens.refresh
-                    .verify(cache).await?;
-                
-                let tokens = self.genrate_tokens(cache).await?;
-
-                Ok(tokens)
-            },
+    // Refresh tokens are single use. The session is the token family, presenting
+    // a refresh token that was already rotated revokes the whole session
+    async fn refresh_tokens(&self, cache: &mut redis::aio::MultiplexedConnection, refresh: &RefreshToken) -> Result<Tokens> {
+        let claims = refresh.decode()?.claims;
+        if claims.body.user_id != self.id {
+            return Err(Error::InvalidSession)
+        }
+
+        let sid = claims.body.sid;
+        let access = AccessToken::encode(AccessTokenBody { user_id: self.id.clone(), sid: sid.clone() })?;
+        let next = RefreshToken::encode(RefreshTokenBody { user_id: self.id.clone(), sid: sid.clone() })?;
+
+        match Session::rotate(cache, &self.id, &sid, &claims.jti, &next.jti()?, REFRESH_TOKEN_TTL as i64).await? {
+            Rotation::Rotated => Ok(Tokens { refresh: next, access }),
+            Rotation::Reused => Err(Error::RefreshTokenReused),
+            Rotation::Missing => Err(Error::InvalidSession),
         }
     }
 }
 
 
 // Error
-type Result<T> = std::result::Result<T,Error>;
+pub type Result<T> = std::result::Result<T,Error>;
 
 #[derive(Debug)]
 pub enum Error {
     InvalidToken(jsonwebtoken::errors::ErrorKind),
     InvalidSession,
+    RefreshTokenReused,
+    // Account security actions are not available to API keys
+    SessionRequired,
     RedisError(redis::RedisError)
 }
 
 impl From<jsonwebtoken::errors::Error> for Error {
     fn from(value: jsonwebtoken::errors::Error) -> Self {
-        let error = value.into_kind();
-        match error {
-            _ => Error::InvalidToken(error),
-        }
+        Error::InvalidToken(value.into_kind())
     }
 }
 
@@ -267,11 +286,102 @@ impl From<redis::RedisError> for Error {
 impl core::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Error::InvalidToken(_) => { write!(f, "Invalid token") },
+            Error::InvalidToken(kind) => { write!(f, "Invalid token: {:?}", kind) },
             Error::InvalidSession => { write!(f, "Invalid session") },
+            Error::RefreshTokenReused => { write!(f, "Refresh token was already used") },
+            Error::SessionRequired => { write!(f, "Requires a signed in session, API keys are not accepted") },
             Error::RedisError(error) => { write!(f,"{}",error) },
         }
     }
 }
 
 impl std::error::Error for Error {}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::InvalidToken(_) | Error::InvalidSession | Error::RefreshTokenReused => StatusCode::UNAUTHORIZED,
+            Error::SessionRequired => StatusCode::FORBIDDEN,
+            Error::RedisError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::models::user::keys::SigningKey;
+
+    fn ring() -> KeyRing {
+        KeyRing::new(vec![SigningKey::hmac("test", b"secret")], "test").unwrap()
+    }
+
+    fn config() -> TokenConfig {
+        TokenConfig { issuer: "cwshop".to_string(), audience: "cwshop".to_string(), leeway: 30 }
+    }
+
+    fn access_body() -> AccessTokenBody {
+        AccessTokenBody { user_id: Uuid::parse(uuid::Uuid::new_v4()), sid: "session".to_string() }
+    }
+
+    fn error_kind(result: Result<TokenData<Claims<AccessTokenBody>>>) -> ErrorKind {
+        match result {
+            Err(Error::InvalidToken(kind)) => kind,
+            other => panic!("expected invalid token, got {:?}", other.map(|data| data.claims)),
+        }
+    }
+
+    #[test]
+    fn token_expires_after_ttl_and_leeway() {
+        let now = unix_time();
+
+        let fresh = encode_token(&ring(), &config(), TokenType::Access, access_body(), now).unwrap();
+        let claims = decode_token::<AccessTokenBody>(&ring(), &config(), &fresh, TokenType::Access).unwrap().claims;
+        assert_eq!(claims.exp, now + ACCESS_TOKEN_TTL);
+        assert_eq!(claims.iss, "cwshop");
+
+        // Expired 10 seconds ago, still inside the leeway
+        let issued = now - ACCESS_TOKEN_TTL - 10;
+        let late = encode_token(&ring(), &config(), TokenType::Access, access_body(), issued).unwrap();
+        assert!(decode_token::<AccessTokenBody>(&ring(), &config(), &late, TokenType::Access).is_ok());
+
+        let issued = now - ACCESS_TOKEN_TTL - 60;
+        let expired = encode_token(&ring(), &config(), TokenType::Access, access_body(), issued).unwrap();
+        assert_eq!(
+            error_kind(decode_token(&ring(), &config(), &expired, TokenType::Access)),
+            ErrorKind::ExpiredSignature
+        );
+    }
+
+    #[test]
+    fn token_types_are_not_interchangeable() {
+        let now = unix_time();
+        let refresh_body = RefreshTokenBody { user_id: Uuid::parse(uuid::Uuid::new_v4()), sid: "session".to_string() };
+
+        let refresh = encode_token(&ring(), &config(), TokenType::Refresh, refresh_body, now).unwrap();
+        assert_eq!(
+            error_kind(decode_token(&ring(), &config(), &refresh, TokenType::Access)),
+            ErrorKind::InvalidToken
+        );
+
+        let access = encode_token(&ring(), &config(), TokenType::Access, access_body(), now).unwrap();
+        assert!(decode_token::<RefreshTokenBody>(&ring(), &config(), &access, TokenType::Refresh).is_err());
+    }
+
+    #[test]
+    fn issuer_and_audience_are_checked() {
+        let token = encode_token(&ring(), &config(), TokenType::Access, access_body(), unix_time()).unwrap();
+
+        let other_audience = TokenConfig { audience: "admin".to_string(), ..config() };
+        assert_eq!(
+            error_kind(decode_token(&ring(), &other_audience, &token, TokenType::Access)),
+            ErrorKind::InvalidAudience
+        );
+
+        let other_issuer = TokenConfig { issuer: "elsewhere".to_string(), ..config() };
+        assert_eq!(
+            error_kind(decode_token(&ring(), &other_issuer, &token, TokenType::Access)),
+            ErrorKind::InvalidIssuer
+        );
+    }
+}
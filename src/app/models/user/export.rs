@@ -0,0 +1,97 @@
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+
+use crate::{app::models::order::{Order, OrderRepository}, repository::db::SqlxError};
+
+use super::{
+    account::{AccountRepository, AccountState},
+    api_key::{ApiKey, ApiKeyRepository},
+    oauth::{IdentityRepository, LinkedIdentity},
+    session::unix_time,
+    User, UserRepository, Uuid
+};
+
+// Everything we hold about a user, handed out on request. Secrets like the
+// password, TOTP secret and key hashes are left out
+#[derive(Debug, Serialize)]
+pub struct DataExport {
+    pub exported_at: u64,
+    pub profile: Profile,
+    pub identities: Vec<LinkedIdentity>,
+    pub api_keys: Vec<ApiKey>,
+    pub comments: Vec<ExportedComment>,
+    pub basket: Vec<ExportedBasketItem>,
+    pub orders: Vec<Order>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Profile {
+    pub id: Uuid,
+    pub login: String,
+    pub name: String,
+    pub email: Option<String>,
+    #[serde(flatten)]
+    pub state: AccountState,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportedComment {
+    pub id: Uuid,
+    pub product_id: Uuid,
+    pub comment: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportedBasketItem {
+    pub id: Uuid,
+    pub variant_id: Uuid,
+    pub quantity: i32,
+}
+
+pub trait ExportRepository<T: sqlx::Database> {
+    async fn export_user_data(db: &Pool<T>, user_id: &Uuid) -> Result<DataExport, SqlxError>;
+}
+
+impl ExportRepository<Postgres> for User {
+    async fn export_user_data(db: &Pool<Postgres>, user_id: &Uuid) -> Result<DataExport, SqlxError> {
+        let user = User::fetch_user(db, user_id.clone()).await?;
+
+        let comments = sqlx::query!("SELECT id, productId, comment FROM comment WHERE userId = $1", &user_id.0)
+            .fetch_all(db)
+            .await?
+            .into_iter()
+            .map(|row| ExportedComment {
+                id: Uuid::from(row.id),
+                product_id: Uuid::from(row.productid),
+                comment: row.comment,
+            })
+            .collect();
+
+        let basket = sqlx::query!("SELECT id, variantId, quantity FROM basket WHERE userId = $1", &user_id.0)
+            .fetch_all(db)
+            .await?
+            .into_iter()
+            .map(|row| ExportedBasketItem {
+                id: Uuid::from(row.id),
+                variant_id: Uuid::from(row.variantid),
+                quantity: row.quantity,
+            })
+            .collect();
+
+        Ok(DataExport {
+            exported_at: unix_time(),
+            profile: Profile {
+                id: user.id.clone(),
+                login: user.login.to_string(),
+                name: user.name.to_string(),
+                email: user.email.as_ref().map(|email| email.to_string()),
+                state: User::fetch_account_state(db, user_id).await?,
+            },
+            identities: User::fetch_identities(db, user_id).await?,
+            api_keys: User::fetch_api_keys(db, user_id).await?,
+            comments,
+            basket,
+            orders: Order::fetch_orders(db, user_id).await?,
+        })
+    }
+}
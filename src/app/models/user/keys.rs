@@ -0,0 +1,225 @@
+use std::sync::LazyLock;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{decode_header, errors::ErrorKind, Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use rsa::{pkcs1::DecodeRsaPrivateKey, pkcs8::DecodePrivateKey, traits::PublicKeyParts, RsaPrivateKey};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+use super::auth::{Error, Result};
+
+// Key signing or verifying tokens, found by the `kid` header
+pub struct SigningKey {
+    kid: String,
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    // Public part published in JWKS, none for shared secrets
+    jwk: Option<Value>,
+}
+
+impl SigningKey {
+    pub fn hmac(kid: &str, secret: &[u8]) -> Self {
+        SigningKey {
+            kid: kid.to_string(),
+            algorithm: Algorithm::HS256,
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            jwk: None,
+        }
+    }
+
+    // PKCS#1 or PKCS#8 private key
+    pub fn rsa_pem(kid: &str, pem: &str) -> std::result::Result<Self, String> {
+        let private_key = RsaPrivateKey::from_pkcs8_pem(pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+            .map_err(|e| e.to_string())?;
+
+        let n = URL_SAFE_NO_PAD.encode(private_key.n().to_bytes_be());
+        let e = URL_SAFE_NO_PAD.encode(private_key.e().to_bytes_be());
+
+        Ok(SigningKey {
+            kid: kid.to_string(),
+            algorithm: Algorithm::RS256,
+            encoding: EncodingKey::from_rsa_pem(pem.as_bytes()).map_err(|e| e.to_string())?,
+            decoding: DecodingKey::from_rsa_components(&n, &e).map_err(|e| e.to_string())?,
+            jwk: Some(json!({ "kty": "RSA", "alg": "RS256", "use": "sig", "kid": kid, "n": n, "e": e })),
+        })
+    }
+
+    // PKCS#8 Ed25519 private key
+    pub fn ed25519_pem(kid: &str, pem: &str) -> std::result::Result<Self, String> {
+        let der = pem::parse(pem).map_err(|e| e.to_string())?;
+        let key_pair = ring::signature::Ed25519KeyPair::from_pkcs8_maybe_unchecked(der.contents())
+            .map_err(|e| e.to_string())?;
+
+        let x = URL_SAFE_NO_PAD.encode(ring::signature::KeyPair::public_key(&key_pair));
+
+        Ok(SigningKey {
+            kid: kid.to_string(),
+            algorithm: Algorithm::EdDSA,
+            encoding: EncodingKey::from_ed_der(der.contents()),
+            decoding: DecodingKey::from_ed_components(&x).map_err(|e| e.to_string())?,
+            jwk: Some(json!({ "kty": "OKP", "crv": "Ed25519", "alg": "EdDSA", "use": "sig", "kid": kid, "x": x })),
+        })
+    }
+
+    fn load(entry: &str) -> Self {
+        // kid=ALGORITHM:path
+        let (kid, spec) = entry.split_once('=')
+            .unwrap_or_else(|| panic!("JWT_KEYS entry `{}` must look like kid=ALGORITHM:path", entry));
+        let (algorithm, path) = spec.split_once(':')
+            .unwrap_or_else(|| panic!("JWT_KEYS entry `{}` must look like kid=ALGORITHM:path", entry));
+
+        let content = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Error reading key `{}` from {}: {}", kid, path, e));
+
+        let key = match algorithm {
+            "HS256" => Ok(SigningKey::hmac(kid, content.trim().as_bytes())),
+            "RS256" => SigningKey::rsa_pem(kid, &content),
+            "EdDSA" => SigningKey::ed25519_pem(kid, &content),
+            other => Err(format!("unsupported algorithm {}, use HS256, RS256 or EdDSA", other)),
+        };
+
+        key.unwrap_or_else(|e| panic!("Error loading key `{}`: {}", kid, e))
+    }
+}
+
+// All keys verify, only the active one signs. Rotation: add the new key,
+// make it active, drop the old one once tokens signed with it have expired
+pub struct KeyRing {
+    active: usize,
+    keys: Vec<SigningKey>,
+}
+
+impl KeyRing {
+    pub fn new(keys: Vec<SigningKey>, active_kid: &str) -> Option<Self> {
+        let active = keys.iter().position(|key| key.kid == active_kid)?;
+
+        Some(KeyRing { active, keys })
+    }
+
+    // Without JWT_KEYS a single shared secret from ACCESS_TOKEN_SECRET is used
+    fn from_env() -> Self {
+        let Ok(entries) = std::env::var("JWT_KEYS") else {
+            let secret = std::env::var("ACCESS_TOKEN_SECRET")
+                .expect("JWT_KEYS or ACCESS_TOKEN_SECRET env must be provided");
+
+            return KeyRing::new(vec![SigningKey::hmac("default", secret.as_bytes())], "default")
+                .expect("Default key is in the ring")
+        };
+
+        let keys: Vec<SigningKey> = entries
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(SigningKey::load)
+            .collect();
+
+        let active = std::env::var("JWT_SIGNING_KEY")
+            .ok()
+            .or_else(|| keys.first().map(|key| key.kid.clone()))
+            .expect("JWT_KEYS must contain at least one key");
+
+        KeyRing::new(keys, &active)
+            .unwrap_or_else(|| panic!("JWT_SIGNING_KEY `{}` is not in JWT_KEYS", active))
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String> {
+        let key = &self.keys[self.active];
+
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(key.kid.clone());
+
+        Ok(jsonwebtoken::encode(&header, claims, &key.encoding)?)
+    }
+
+    // Verified with the key named by the header, in the algorithm of that key.
+    // Tokens naming no key of the ring are as invalid as badly signed ones
+    pub fn decode<T: DeserializeOwned>(&self, token: &str, validation: &Validation) -> Result<TokenData<T>> {
+        let kid = decode_header(token)?.kid.ok_or(Error::InvalidToken(ErrorKind::InvalidToken))?;
+        let key = self.keys
+            .iter()
+            .find(|key| key.kid == kid)
+            .ok_or(Error::InvalidToken(ErrorKind::InvalidToken))?;
+
+        let mut validation = validation.clone();
+        validation.algorithms = vec![key.algorithm];
+
+        Ok(jsonwebtoken::decode(token, &key.decoding, &validation)?)
+    }
+
+    pub fn jwks(&self) -> Value {
+        let keys: Vec<Value> = self.keys
+            .iter()
+            .filter_map(|key| key.jwk.clone())
+            .collect();
+
+        json!({ "keys": keys })
+    }
+}
+
+pub static KEY_RING: LazyLock<KeyRing> = LazyLock::new(KeyRing::from_env);
+
+// Keys are read on start, a missing or broken key stops the server
+pub fn load_key_ring() {
+    LazyLock::force(&KEY_RING);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Serialize, serde::Deserialize, PartialEq)]
+    struct Claims {
+        sub: String,
+    }
+
+    fn ed25519_key(kid: &str) -> SigningKey {
+        let der = ring::signature::Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
+        let pem = pem::encode(&pem::Pem::new("PRIVATE KEY", der.as_ref()));
+
+        SigningKey::ed25519_pem(kid, &pem).unwrap()
+    }
+
+    fn validation() -> Validation {
+        let mut validation = Validation::default();
+        validation.required_spec_claims.clear();
+        validation.validate_exp = false;
+        validation
+    }
+
+    #[test]
+    fn previous_signing_key_still_verifies() {
+        let claims = Claims { sub: "boba".to_string() };
+
+        let old = KeyRing::new(vec![SigningKey::hmac("old", b"secret")], "old").unwrap();
+        let old_token = old.encode(&claims).unwrap();
+
+        let rotated = KeyRing::new(vec![ed25519_key("new"), SigningKey::hmac("old", b"secret")], "new").unwrap();
+        let new_token = rotated.encode(&claims).unwrap();
+
+        assert_eq!(decode_header(&new_token).unwrap().kid.as_deref(), Some("new"));
+        assert_eq!(decode_header(&new_token).unwrap().alg, Algorithm::EdDSA);
+        assert_eq!(rotated.decode::<Claims>(&old_token, &validation()).unwrap().claims, claims);
+        assert_eq!(rotated.decode::<Claims>(&new_token, &validation()).unwrap().claims, claims);
+
+        // Unknown keys are rejected once the key is removed from the ring
+        assert!(matches!(
+            old.decode::<Claims>(&new_token, &validation()),
+            Err(Error::InvalidToken(ErrorKind::InvalidToken))
+        ));
+    }
+
+    #[test]
+    fn jwks_publishes_only_public_keys() {
+        let ring = KeyRing::new(vec![ed25519_key("ed"), SigningKey::hmac("hs", b"secret")], "ed").unwrap();
+        let jwks = ring.jwks();
+
+        let keys = jwks["keys"].as_array().unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0]["kid"], "ed");
+        assert_eq!(keys[0]["kty"], "OKP");
+        assert!(keys[0].get("d").is_none());
+    }
+}
@@ -0,0 +1,341 @@
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+
+use crate::{app::secrets::{constant_time_eq, random_bytes}, repository::db::SqlxError};
+
+use super::{auth, session::unix_time, User, Uuid};
+
+// RFC 6238 defaults, the ones authenticator apps support
+const TOTP_STEP: u64 = 30;
+const TOTP_DIGITS: u32 = 6;
+// Codes of the neighbour steps are accepted for clock drift
+const TOTP_SKEW: u64 = 1;
+
+const SECRET_LENGTH: usize = 20;
+pub const RECOVERY_CODES_COUNT: usize = 10;
+// 80 bits, shown as four groups of five hex digits
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+
+    encoded
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in encoded.trim_end_matches('=').chars().filter(|c| !c.is_whitespace()) {
+        let value = BASE32_ALPHABET.iter().position(|&a| a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(bytes)
+}
+
+// Shared TOTP secret, stored base32 encoded as authenticator apps show it
+#[derive(Debug, Clone)]
+pub struct TotpSecret(Vec<u8>);
+
+impl TotpSecret {
+    pub fn generate() -> Self {
+        TotpSecret(random_bytes(SECRET_LENGTH))
+    }
+
+    pub fn parse(encoded: &str) -> Option<Self> {
+        base32_decode(encoded)
+            .filter(|bytes| !bytes.is_empty())
+            .map(TotpSecret)
+    }
+
+    pub fn otpauth_uri(&self, issuer: &str, account: &str) -> String {
+        let label = format!("{}:{}", issuer, account);
+
+        format!(
+            "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            percent_encode(&label),
+            self,
+            percent_encode(issuer),
+            TOTP_DIGITS,
+            TOTP_STEP
+        )
+    }
+
+    fn code_at(&self, step: u64) -> String {
+        let mut mac = Hmac::<Sha1>::new_from_slice(&self.0)
+            .expect("HMAC accepts keys of any size");
+        mac.update(&step.to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+
+        // Dynamic truncation, RFC 4226 section 5.3
+        let offset = (hash[hash.len() - 1] & 0xf) as usize;
+        let binary = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+
+        format!("{:0width$}", binary % 10u32.pow(TOTP_DIGITS), width = TOTP_DIGITS as usize)
+    }
+
+    // Step the code is valid for, if any
+    pub fn verify_at(&self, code: &str, time: u64) -> Option<u64> {
+        let code = code.trim();
+        let current = time / TOTP_STEP;
+
+        (current.saturating_sub(TOTP_SKEW)..=current + TOTP_SKEW)
+            .find(|&step| constant_time_eq(self.code_at(step).as_bytes(), code.as_bytes()))
+    }
+}
+
+impl std::fmt::Display for TotpSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", base32_encode(&self.0))
+    }
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn used_step_key(user_id: &Uuid, step: u64) -> String {
+    format!("totp_used:{}:{}", user_id, step)
+}
+
+// Checks the code against the current time, a code can be used only once
+pub async fn verify_totp(
+    cache: &mut redis::aio::MultiplexedConnection,
+    user_id: &Uuid,
+    secret: &TotpSecret,
+    code: &str
+) -> auth::Result<bool> {
+    let Some(step) = secret.verify_at(code, unix_time()) else {
+        return Ok(false)
+    };
+
+    let first_use: bool = redis::cmd("SET")
+        .arg(used_step_key(user_id, step))
+        .arg("")
+        .arg("NX")
+        .arg("EX")
+        .arg(TOTP_STEP * (2 * TOTP_SKEW + 1))
+        .query_async::<_, Option<String>>(cache).await?
+        .is_some();
+
+    Ok(first_use)
+}
+
+// Recovery codes are shown once, only their hashes are stored. They carry
+// enough randomness that the unsalted hashes can't be brute-forced
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES_COUNT)
+        .map(|_| {
+            let code: String = random_bytes(RECOVERY_CODE_LENGTH)
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            code.as_bytes()
+                .chunks(5)
+                .map(|group| std::str::from_utf8(group).expect("Hex is ASCII"))
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect()
+}
+
+pub fn hash_recovery_code(code: &str) -> String {
+    Sha256::digest(code.trim().to_lowercase().as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// Database
+
+pub struct TotpSettings {
+    pub secret: Option<String>,
+    pub enabled: bool,
+}
+
+pub trait MfaRepository<T: sqlx::Database> {
+    async fn fetch_totp(db: &Pool<T>, user_id: &Uuid) -> Result<TotpSettings, SqlxError>;
+    // Pending secret, not required at sign in until enabled
+    async fn set_totp_secret(db: &Pool<T>, user_id: &Uuid, secret: &TotpSecret) -> Result<(), SqlxError>;
+    async fn enable_totp(db: &Pool<T>, user_id: &Uuid, recovery_hashes: Vec<String>) -> Result<(), SqlxError>;
+    async fn disable_totp(db: &Pool<T>, user_id: &Uuid) -> Result<(), SqlxError>;
+    async fn use_recovery_code(db: &Pool<T>, user_id: &Uuid, code_hash: &str) -> Result<bool, SqlxError>;
+}
+
+impl MfaRepository<Postgres> for User {
+    async fn fetch_totp(db: &Pool<Postgres>, user_id: &Uuid) -> Result<TotpSettings, SqlxError> {
+        Ok(
+            sqlx::query_as!(
+                TotpSettings,
+                r#"SELECT totpSecret as "secret", totpEnabled as "enabled" FROM users WHERE id = $1"#,
+                &user_id.0
+            )
+            .fetch_one(db)
+            .await?
+        )
+    }
+
+    async fn set_totp_secret(db: &Pool<Postgres>, user_id: &Uuid, secret: &TotpSecret) -> Result<(), SqlxError> {
+        sqlx::query!(
+            "UPDATE users SET totpSecret = $2 WHERE id = $1 AND NOT totpEnabled",
+            &user_id.0,
+            secret.to_string()
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn enable_totp(db: &Pool<Postgres>, user_id: &Uuid, recovery_hashes: Vec<String>) -> Result<(), SqlxError> {
+        let mut tx = db.begin().await?;
+
+        sqlx::query!("UPDATE users SET totpEnabled = TRUE WHERE id = $1", &user_id.0)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!("DELETE FROM recovery_codes WHERE userId = $1", &user_id.0)
+            .execute(&mut *tx)
+            .await?;
+
+        for hash in recovery_hashes {
+            sqlx::query!(
+                "INSERT INTO recovery_codes (id, userId, code) VALUES ($1, $2, $3)",
+                &Uuid::parse(uuid::Uuid::new_v4()).0,
+                &user_id.0,
+                hash
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn disable_totp(db: &Pool<Postgres>, user_id: &Uuid) -> Result<(), SqlxError> {
+        let mut tx = db.begin().await?;
+
+        sqlx::query!("UPDATE users SET totpSecret = NULL, totpEnabled = FALSE WHERE id = $1", &user_id.0)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!("DELETE FROM recovery_codes WHERE userId = $1", &user_id.0)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn use_recovery_code(db: &Pool<Postgres>, user_id: &Uuid, code_hash: &str) -> Result<bool, SqlxError> {
+        let unused = sqlx::query!(
+            "SELECT id, code FROM recovery_codes WHERE userId = $1 AND usedAt IS NULL",
+            &user_id.0
+        )
+        .fetch_all(db)
+        .await?;
+
+        // Every unused code is compared, matched or not
+        let matched = unused
+            .iter()
+            .fold(None, |matched, row| match constant_time_eq(row.code.as_bytes(), code_hash.as_bytes()) {
+                true => Some(&row.id),
+                false => matched,
+            });
+
+        let Some(id) = matched else {
+            return Ok(false)
+        };
+
+        let result = sqlx::query!(
+            "UPDATE recovery_codes SET usedAt = NOW() WHERE id = $1 AND usedAt IS NULL",
+            id
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 appendix B, SHA1 secret, last 6 of the 8 digits
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn totp_matches_rfc_vectors() {
+        let secret = TotpSecret(RFC_SECRET.to_vec());
+
+        for (time, code) in [(59, "287082"), (1111111109, "081804"), (1234567890, "005924"), (2000000000, "279037")] {
+            assert_eq!(secret.code_at(time / TOTP_STEP), code);
+            assert_eq!(secret.verify_at(code, time), Some(time / TOTP_STEP));
+        }
+
+        // Neighbour steps pass, older codes don't
+        assert!(secret.verify_at("287082", 59 + TOTP_STEP).is_some());
+        assert!(secret.verify_at("287082", 59 + 2 * TOTP_STEP).is_none());
+    }
+
+    #[test]
+    fn secret_round_trips_through_base32() {
+        let secret = TotpSecret(RFC_SECRET.to_vec());
+        assert_eq!(secret.to_string(), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+
+        let generated = TotpSecret::generate();
+        let parsed = TotpSecret::parse(&generated.to_string()).unwrap();
+        assert_eq!(parsed.0, generated.0);
+        assert_eq!(parsed.0.len(), SECRET_LENGTH);
+
+        let uri = secret.otpauth_uri("CW Shop", "boba");
+        assert!(uri.starts_with("otpauth://totp/CW%20Shop%3Aboba?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=CW%20Shop"));
+    }
+
+    #[test]
+    fn recovery_codes_carry_80_bits() {
+        let codes = generate_recovery_codes();
+
+        assert_eq!(codes.len(), RECOVERY_CODES_COUNT);
+        for code in &codes {
+            assert_eq!(code.len(), 23);
+            assert_eq!(code.replace('-', "").len(), RECOVERY_CODE_LENGTH * 2);
+            assert_eq!(hash_recovery_code(code), hash_recovery_code(&format!(" {} ", code.to_uppercase())));
+        }
+    }
+}
@@ -1,19 +1,33 @@
-mod auth;
-
-use crate::repository::db::SqlxError;
+pub mod account;
+pub mod api_key;
+pub mod auth;
+pub mod export;
+pub mod keys;
+pub mod mfa;
+pub mod oauth;
+pub mod one_time;
+pub mod session;
+pub mod throttle;
+
+use crate::{app::secrets::{constant_time_eq, random_bytes}, repository::db::SqlxError};
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
 use core::fmt;
-use std::any::Any;
-use actix_web::cookie::time::error::Format;
-use lib_utils::validation::{self, validate_rules, Rules, Validate};
-use redis::FromRedisValue;
+use std::{num::NonZeroU32, sync::LazyLock};
+use lib_utils::validation::{self, string_schema, validate_rules, JsonSchema, PasswordBlocklist, PasswordStrength, Rules, Validate};
+use hmac::{Hmac, Mac};
 use regex::Regex;
+use ring::pbkdf2;
 use serde::{Deserialize, Serialize};
-use sqlx::postgres::PgTypeKind;
-use sqlx::{query_as, Decode, Execute, FromRow, QueryBuilder, Type};
+use sha2::Sha256;
+use sqlx::{FromRow, QueryBuilder};
 use sqlx::{Pool, Postgres};
 
 // Custom validation rules
 
+const LOGIN_CHARACTERS: &str = r"^[a-zA-Z0-9_.]*$";
+
+static LOGIN_CHARACTERS_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(LOGIN_CHARACTERS).unwrap());
+
 #[derive(Debug)]
 enum CustomRules {
     LoginCanContain,
@@ -35,12 +49,10 @@ impl std::fmt::Display for CustomRules {
 impl validation::Rule for CustomRules {}
 
 impl Validate<String> for CustomRules {
-    fn validate(&self, value: &String) -> validation::Result<&Self> {
+    fn validate(&self, value: &String) -> validation::Result<'_, &Self> {
         match self {
             CustomRules::LoginCanContain => {
-                let re = r"^[a-zA-Z0-9_.]*$";
-                let regex = Regex::new(re).unwrap();
-                if regex.is_match(value) {
+                if LOGIN_CHARACTERS_REGEX.is_match(value) {
                     Ok(self)
                 } else {
                     Err(validation::Error::RuleNotValidated(self))
@@ -50,10 +62,24 @@ impl Validate<String> for CustomRules {
     }
 }
 
+impl JsonSchema for CustomRules {
+    fn json_schema(&self) -> serde_json::Map<String, serde_json::Value> {
+        let mut schema = serde_json::Map::new();
+
+        match self {
+            CustomRules::LoginCanContain => {
+                schema.insert("pattern".to_string(), serde_json::Value::from(LOGIN_CHARACTERS));
+            },
+        }
+
+        schema
+    }
+}
+
 // Database fields
 
 // Name
-#[derive(Debug, Serialize, Deserialize, sqlx::Decode, sqlx::Encode)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::Decode, sqlx::Encode)]
 pub struct Name(String);
 
 impl fmt::Display for Name {
@@ -68,13 +94,11 @@ impl std::convert::From<String> for Name {
     }
 }
 
+const NAME_RULES: [&Rules; 2] = [&Rules::MinLength(3), &Rules::ContainsDidgits(false)];
+
 impl Name {
     pub fn parse(name: String) -> Result<Self, Vec<validation::Error<'static>>> {
-        let errors = validate_rules(
-            &name,
-            &[&Rules::MinLength(3), &Rules::ContainsDidgits(false)],
-        )
-        .to_vec();
+        let errors = validate_rules(&name, &NAME_RULES).to_vec();
 
         if errors.is_empty() {
             Ok(Name(name))
@@ -82,6 +106,10 @@ impl Name {
             Err(errors)
         }
     }
+
+    pub fn schema() -> serde_json::Value {
+        string_schema(&NAME_RULES.map(|rule| rule as &dyn JsonSchema))
+    }
 }
 
 impl sqlx::Type<sqlx::Postgres> for Name {
@@ -95,7 +123,7 @@ impl sqlx::Type<sqlx::Postgres> for Name {
 }
 
 // Login
-#[derive(Debug, Serialize, Deserialize, sqlx::Decode, sqlx::Encode)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::Decode, sqlx::Encode)]
 pub struct Login(String);
 
 impl fmt::Display for Login {
@@ -110,11 +138,14 @@ impl std::convert::From<String> for Login {
     }
 }
 
+const LOGIN_RULES: [&Rules; 2] = [&Rules::MinLength(3), &Rules::MaxLength(20)];
+const LOGIN_CUSTOM_RULES: [&CustomRules; 1] = [&CustomRules::LoginCanContain];
+
 impl Login {
     pub fn parse(login: String) -> Result<Self, Vec<validation::Error<'static>>> {
         let validation_errors = [
-            validate_rules(&login, &[&Rules::MinLength(3), &Rules::MaxLength(20)]),
-            validate_rules(&login, &[&CustomRules::LoginCanContain]),
+            validate_rules(&login, &LOGIN_RULES),
+            validate_rules(&login, &LOGIN_CUSTOM_RULES),
         ]
         .concat();
 
@@ -124,6 +155,16 @@ impl Login {
             Err(validation_errors)
         }
     }
+
+    pub fn schema() -> serde_json::Value {
+        let rules: Vec<&dyn JsonSchema> = LOGIN_RULES
+            .iter()
+            .map(|&rule| rule as &dyn JsonSchema)
+            .chain(LOGIN_CUSTOM_RULES.iter().map(|&rule| rule as &dyn JsonSchema))
+            .collect();
+
+        string_schema(&rules)
+    }
 }
 
 impl sqlx::Type<sqlx::Postgres> for Login {
@@ -136,6 +177,52 @@ impl sqlx::Type<sqlx::Postgres> for Login {
     }
 }
 
+// Email
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::Decode, sqlx::Encode)]
+pub struct Email(String);
+
+impl fmt::Display for Email {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::convert::From<String> for Email {
+    fn from(value: String) -> Self {
+        Email(value)
+    }
+}
+
+const EMAIL_RULES: [&Rules; 1] = [&Rules::Email];
+
+impl Email {
+    // Addresses are stored lowercased, so uniqueness doesn't depend on case
+    pub fn parse(email: String) -> Result<Self, Vec<validation::Error<'static>>> {
+        let email = email.trim().to_lowercase();
+        let errors = validate_rules(&email, &EMAIL_RULES).to_vec();
+
+        if errors.is_empty() {
+            Ok(Email(email))
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn schema() -> serde_json::Value {
+        string_schema(&EMAIL_RULES.map(|rule| rule as &dyn JsonSchema))
+    }
+}
+
+impl sqlx::Type<sqlx::Postgres> for Email {
+    fn type_info() -> <sqlx::Postgres as sqlx::Database>::TypeInfo {
+        <String as sqlx::Type<sqlx::Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &<sqlx::Postgres as sqlx::Database>::TypeInfo) -> bool {
+        <String as sqlx::Type<sqlx::Postgres>>::compatible(ty)
+    }
+}
+
 // Password
 #[derive(Debug, Serialize, Deserialize, sqlx::Decode, sqlx::Encode)]
 pub struct Password(String);
@@ -153,18 +240,49 @@ impl std::convert::From<String> for Password {
     }
 }
 
+const PASSWORD_RULES: [&Rules; 5] = [
+    &Rules::MinLength(8),
+    &Rules::ContainsLowecaseCharacter(true),
+    &Rules::ContainsUppercaseCharacter(true),
+    &Rules::ContainsDidgits(true),
+    &Rules::ContainsSpecialCharacters(true),
+];
+
+static PASSWORD_STRENGTH: LazyLock<PasswordStrength> = LazyLock::new(|| {
+    let min_score = std::env::var("PASSWORD_MIN_SCORE")
+        .map(|score| score.parse().expect("PASSWORD_MIN_SCORE must be a number from 0 to 4"))
+        .unwrap_or(3);
+
+    PasswordStrength::new(min_score)
+});
+
+static PASSWORD_BLOCKLIST: LazyLock<PasswordBlocklist> = LazyLock::new(|| {
+    match std::env::var("PASSWORD_BLOCKLIST_PATH") {
+        Ok(path) => PasswordBlocklist::from_file(path)
+            .expect("Error reading PASSWORD_BLOCKLIST_PATH file"),
+        Err(_) => PasswordBlocklist::bundled(),
+    }
+});
+
+static PASSWORD_HISTORY_SECRET: LazyLock<String> = LazyLock::new(|| {
+    std::env::var("PASSWORD_HISTORY_SECRET").expect("PASSWORD_HISTORY_SECRET env must be provided")
+});
+
+// Read once on start, so a bad setting stops the server instead of a request
+pub fn load_password_settings() {
+    LazyLock::force(&PASSWORD_STRENGTH);
+    LazyLock::force(&PASSWORD_BLOCKLIST);
+    LazyLock::force(&PASSWORD_HISTORY_SECRET);
+}
+
 impl Password {
     pub fn parse(password: String) -> Result<Self, Vec<validation::Error<'static>>> {
-        let validation_errors = validate_rules(
-            &password,
-            &[
-                &Rules::ContainsLowecaseCharacter(true),
-                &Rules::ContainsUppercaseCharacter(true),
-                &Rules::ContainsDidgits(true),
-                &Rules::ContainsSpecialCharacters(true),
-            ],
-        )
-        .to_vec();
+        let validation_errors = [
+            validate_rules(&password, &PASSWORD_RULES),
+            validate_rules(&password, &[&*PASSWORD_STRENGTH]),
+            validate_rules(&password, &[&*PASSWORD_BLOCKLIST]),
+        ]
+        .concat();
 
         if validation_errors.is_empty() {
             Ok(Password(password))
@@ -172,6 +290,130 @@ impl Password {
             Err(validation_errors)
         }
     }
+
+    pub fn schema() -> serde_json::Value {
+        let rules: Vec<&dyn JsonSchema> = PASSWORD_RULES
+            .iter()
+            .map(|&rule| rule as &dyn JsonSchema)
+            .chain([&*PASSWORD_STRENGTH as &dyn JsonSchema, &*PASSWORD_BLOCKLIST])
+            .collect();
+
+        string_schema(&rules)
+    }
+
+    // Keyed hash kept in password history, so old passwords are never stored as is
+    pub fn history_hash(&self, user_id: &Uuid) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(PASSWORD_HISTORY_SECRET.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(&user_id.0);
+        mac.update(self.0.as_bytes());
+
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    // Salted PBKDF2, the only form the password is kept in
+    pub fn hash(&self) -> PasswordHash {
+        let salt = random_bytes(PASSWORD_SALT_LENGTH);
+        let mut hash = [0; PASSWORD_HASH_LENGTH];
+        pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, PASSWORD_HASH_ITERATIONS, &salt, self.0.as_bytes(), &mut hash);
+
+        PasswordHash(format!(
+            "{}${}${}${}",
+            PASSWORD_HASH_SCHEME,
+            PASSWORD_HASH_ITERATIONS,
+            STANDARD_NO_PAD.encode(salt),
+            STANDARD_NO_PAD.encode(hash)
+        ))
+    }
+}
+
+const PASSWORD_HASH_SCHEME: &str = "pbkdf2-sha256";
+const PASSWORD_HASH_ITERATIONS: NonZeroU32 = NonZeroU32::new(600_000).unwrap();
+const PASSWORD_HASH_LENGTH: usize = 32;
+const PASSWORD_SALT_LENGTH: usize = 16;
+
+// Checked against when the login is unknown, so the response takes as long
+static MISSING_PASSWORD_HASH: LazyLock<PasswordHash> = LazyLock::new(|| Password::from(String::new()).hash());
+
+// Stored password, `scheme$iterations$salt$hash`. Rows from before hashing
+// hold the password itself until it's confirmed once more
+#[derive(Debug)]
+pub struct PasswordHash(String);
+
+impl std::convert::From<String> for PasswordHash {
+    fn from(value: String) -> Self {
+        PasswordHash(value)
+    }
+}
+
+impl PasswordHash {
+    fn parts(&self) -> Option<(NonZeroU32, Vec<u8>, Vec<u8>)> {
+        let mut parts = self.0.split('$');
+
+        if parts.next()? != PASSWORD_HASH_SCHEME {
+            return None
+        }
+
+        let iterations = parts.next()?.parse().ok()?;
+        let salt = STANDARD_NO_PAD.decode(parts.next()?).ok()?;
+        let hash = STANDARD_NO_PAD.decode(parts.next()?).ok()?;
+
+        parts.next().is_none().then_some((iterations, salt, hash))
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.parts().is_none()
+    }
+
+    pub fn verify(&self, password: &Password) -> bool {
+        match self.parts() {
+            Some((iterations, salt, hash)) => {
+                pbkdf2::verify(pbkdf2::PBKDF2_HMAC_SHA256, iterations, &salt, password.0.as_bytes(), &hash).is_ok()
+            },
+            None => constant_time_eq(self.0.as_bytes(), password.0.as_bytes()),
+        }
+    }
+
+    pub fn verify_missing(password: &Password) {
+        MISSING_PASSWORD_HASH.verify(password);
+    }
+}
+
+// Number of last passwords, including the current one, that can't be reused
+pub const PASSWORD_HISTORY_SIZE: i64 = 5;
+
+#[derive(Debug)]
+pub struct NotRecentlyUsed {
+    user_id: Uuid,
+    recent_hashes: Vec<String>,
+}
+
+impl NotRecentlyUsed {
+    pub fn new(user_id: Uuid, recent_hashes: Vec<String>) -> Self {
+        NotRecentlyUsed { user_id, recent_hashes }
+    }
+}
+
+impl std::fmt::Display for NotRecentlyUsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Must not match any of the last {} passwords", PASSWORD_HISTORY_SIZE)
+    }
+}
+
+impl validation::Rule for NotRecentlyUsed {}
+
+impl Validate<Password> for NotRecentlyUsed {
+    fn validate(&self, value: &Password) -> validation::Result<'_, &Self> {
+        if self.recent_hashes.contains(&value.history_hash(&self.user_id)) {
+            Err(validation::Error::RuleNotValidated(self))
+        } else {
+            Ok(self)
+        }
+    }
 }
 
 impl sqlx::Type<sqlx::Postgres> for Password {
@@ -184,8 +426,33 @@ impl sqlx::Type<sqlx::Postgres> for Password {
     }
 }
 
+// Role
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    User,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "user" => Some(Role::User),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
 // Uuid
-#[derive(Debug, Deserialize, Clone, sqlx::Decode, sqlx::Encode)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, sqlx::Decode, sqlx::Encode)]
 pub struct Uuid([u8; 16]);
 
 impl sqlx::Type<sqlx::Postgres> for Uuid {
@@ -246,6 +513,14 @@ impl Serialize for Uuid {
     }
 }
 
+impl<'de> Deserialize<'de> for Uuid {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de> {
+        Ok(Uuid::parse(uuid::Uuid::deserialize(deserializer)?))
+    }
+}
+
 impl std::convert::From<String> for Uuid {
     fn from(value: String) -> Self {
         let bytes: [u8; 16] = value
@@ -270,6 +545,10 @@ impl Uuid {
     pub fn parse(uuid: uuid::Uuid) -> Self {
         Uuid(uuid.into_bytes())
     }
+
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
 }
 
 // Database type
@@ -278,39 +557,94 @@ pub(crate) struct User {
     pub id: Uuid,
     pub name: Name,
     pub login: Login,
-    pub password: Password,
+    pub email: Option<Email>,
+}
+
+// Fields picked by the user that no two accounts can share, by their constraints
+const UNIQUE_FIELDS: [(&str, &str); 3] = [
+    ("users_login_key", "login"),
+    ("users_name_key", "name"),
+    ("users_email_key", "email"),
+];
+
+impl User {
+    pub fn taken_field(err: &SqlxError) -> Option<&'static str> {
+        let constraint = err.unique_constraint()?;
+
+        UNIQUE_FIELDS
+            .iter()
+            .find(|(name, _)| *name == constraint)
+            .map(|(_, field)| *field)
+    }
 }
 
 pub struct UserSearch {
     pub id: Option<Uuid>,
     pub name: Option<Name>,
     pub login: Option<Login>,
-    pub password: Option<Password>,
-    
+}
+
+// Page of the user list, the search matches logins and names
+pub struct UserListQuery {
+    pub search: Option<String>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+pub struct UserPage {
+    pub users: Vec<User>,
+    pub total: i64,
 }
 
 pub trait UserRepository<T: sqlx::Database> {
-    async fn fetch_all_users(db: &Pool<T>) -> Result<Vec<User>, SqlxError>;
+    async fn fetch_all_users(db: &Pool<T>, query: &UserListQuery) -> Result<UserPage, SqlxError>;
     async fn fetch_user(db: &Pool<T>, user_id: Uuid) -> Result<User, SqlxError>;
+    // Deleted users are anonymised, changes must not bring their data back
+    async fn fetch_undeleted_user(db: &Pool<T>, user_id: Uuid) -> Result<User, SqlxError>;
 
-    async fn create_user(db: &Pool<T>, user: User) -> Result<(), SqlxError>;
-    async fn create_many_users(db: &Pool<T>, users: Vec<User>) -> Result<(), SqlxError>;
+    async fn create_user(db: &Pool<T>, user: User, password: Password) -> Result<(), SqlxError>;
+    async fn create_many_users(db: &Pool<T>, users: Vec<(User, Password)>) -> Result<(), SqlxError>;
 
     async fn delete_user(db: &Pool<T>, user_id: Uuid) -> Result<(), SqlxError>;
-    async fn delete_many_users(db: &Pool<T>, users_id: Vec<Uuid>) -> Result<(), SqlxError>;
+    // Ids of the users deleted now, unknown and already deleted ones are left out
+    async fn delete_many_users(db: &Pool<T>, users_id: Vec<Uuid>) -> Result<Vec<Uuid>, SqlxError>;
 
     async fn patch_user(db: &Pool<T>, user: User) -> Result<(), SqlxError>;
     async fn patch_many_users(db: &Pool<T>, users: Vec<User>) -> Result<(), SqlxError>;
 
     async fn get_user(db: &Pool<T>, user: UserSearch) -> Result<User,SqlxError>;
 
+    async fn fetch_user_email(db: &Pool<T>, user_id: &Uuid) -> Result<Option<String>, SqlxError>;
+
+}
+
+pub trait EmailVerificationRepository<T: sqlx::Database> {
+    async fn is_email_verified(db: &Pool<T>, user_id: &Uuid) -> Result<bool, SqlxError>;
+    // Succeeds only while the user still has the verified address
+    async fn verify_email(db: &Pool<T>, user_id: &Uuid, email: &Email) -> Result<bool, SqlxError>;
+}
+
+pub trait RoleRepository<T: sqlx::Database> {
+    async fn fetch_role(db: &Pool<T>, user_id: &Uuid) -> Result<Role, SqlxError>;
+    // False when the user doesn't exist or is deleted
+    async fn set_role(db: &Pool<T>, user_id: &Uuid, role: Role) -> Result<bool, SqlxError>;
+}
+
+pub trait PasswordRepository<T: sqlx::Database> {
+    // Plain text left from before hashing is replaced with a hash once it matches
+    async fn check_password(db: &Pool<T>, user_id: &Uuid, password: &Password) -> Result<bool, SqlxError>;
+}
+
+pub trait PasswordHistoryRepository<T: sqlx::Database> {
+    async fn fetch_recent_passwords(db: &Pool<T>, user_id: &Uuid, limit: i64) -> Result<Vec<String>, SqlxError>;
+    async fn change_password(db: &Pool<T>, user_id: &Uuid, password: Password) -> Result<(), SqlxError>;
 }
 
 pub struct Builder {
     id: Option<Uuid>,
     name: Option<Name>,
     login: Option<Login>,
-    password: Option<Password>,
+    email: Option<Email>,
 }
 
 impl Builder {
@@ -319,7 +653,7 @@ impl Builder {
             id: None,
             name: None,
             login: None,
-            password: None,
+            email: None,
         }
     }
 
@@ -331,8 +665,8 @@ impl Builder {
     pub fn login(&mut self, login: Login) {
         self.login = Some(login);
     }
-    pub fn password(&mut self, password: Password) {
-        self.password = Some(password);
+    pub fn email(&mut self, email: Email) {
+        self.email = Some(email);
     }
 
     pub fn try_get(self) -> Option<User> {
@@ -340,39 +674,49 @@ impl Builder {
             name: self.name?,
             id: self.id?,
             login: self.login?,
-            password: self.password?,
+            email: self.email,
         })
     }
 }
 
 impl UserRepository<Postgres> for User {
-    async fn fetch_all_users(db: &Pool<Postgres>) -> Result<Vec<User>, SqlxError> {
-        // sqlx::query_as::<_,User>("SELECT id, first_name, last_name FROM users")
-        //     .fetch_all(db)
-        //     .await
-        Ok(sqlx::query_as!(User, "SELECT * FROM users",)
-            .fetch_all(db)
-            .await?)
-    }
-
-    async fn create_user(db: &Pool<Postgres>, user: User) -> Result<(), SqlxError> {
-        // sqlx::query_as::<_,User>("INSERT INTO users (id, name, login, password) VALUES $1, $2, $3, $4")
-        //     .bind(user.id)
-        //     .bind(user.name)
-        //     .bind(user.login)
-        //     .bind(user.password)
-        //     .fetch_one(db)
-        //     .await
-        sqlx::query_as!(
+    async fn fetch_all_users(db: &Pool<Postgres>, query: &UserListQuery) -> Result<UserPage, SqlxError> {
+        // Search is a substring, LIKE wildcards in it are matched literally
+        let pattern = query.search.as_ref().map(|search| {
+            let escaped = search.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+            format!("%{}%", escaped)
+        });
+
+        let users = sqlx::query_as!(
             User,
-            "INSERT INTO users (id, name, login, password) VALUES ($1, $2, $3, $4)",
-            &user.id.0,
-            &user.name.0,
-            &user.login.0,
-            &user.password.0
+            r#"SELECT id, name, login, email as "email: Email" FROM users
+            WHERE deletedAt IS NULL AND ($1::TEXT IS NULL OR login ILIKE $1 OR name ILIKE $1)
+            ORDER BY login LIMIT $2 OFFSET $3"#,
+            pattern,
+            query.limit,
+            query.offset
         )
-        .execute(db)
+        .fetch_all(db)
         .await?;
+
+        let total = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) as "total!" FROM users
+            WHERE deletedAt IS NULL AND ($1::TEXT IS NULL OR login ILIKE $1 OR name ILIKE $1)"#,
+            pattern
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(UserPage { users, total })
+    }
+
+    async fn create_user(db: &Pool<Postgres>, user: User, password: Password) -> Result<(), SqlxError> {
+        let mut tx = db.begin().await?;
+
+        insert_user(&mut tx, &user, &password).await?;
+
+        tx.commit().await?;
+
         Ok(())
     }
 
@@ -385,19 +729,20 @@ impl UserRepository<Postgres> for User {
         )
     }
 
-    async fn create_many_users(db: &Pool<Postgres>, users: Vec<User>) -> Result<(), SqlxError> {
+    async fn fetch_undeleted_user(db: &Pool<Postgres>, user_id: Uuid) -> Result<User, SqlxError> {
+        Ok(
+            sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1 AND deletedAt IS NULL")
+                .bind(user_id)
+                .fetch_one(db)
+                .await?,
+        )
+    }
+
+    async fn create_many_users(db: &Pool<Postgres>, users: Vec<(User, Password)>) -> Result<(), SqlxError> {
         let mut tx = db.begin().await?;
 
-        for user in users {
-            sqlx::query!(
-                "INSERT INTO users (id, name, login, password) VALUES ($1, $2, $3, $4)",
-                &user.id.0,
-                &user.name.0,
-                &user.login.0,
-                &user.password.0,
-            )
-            .execute(&mut *tx)
-            .await?;
+        for (user, password) in users {
+            insert_user(&mut tx, &user, &password).await?;
         }
 
         tx.commit().await?;
@@ -406,52 +751,66 @@ impl UserRepository<Postgres> for User {
     }
 
     async fn delete_user(db: &Pool<Postgres>, user_id: Uuid) -> Result<(), SqlxError> {
-        sqlx::query!("DELETE FROM users WHERE id = $1", &user_id.0)
-            .execute(db)
-            .await?;
+        let mut tx = db.begin().await?;
+
+        delete_user_rows(&mut tx, &user_id).await?;
+
+        tx.commit().await?;
+
         Ok(())
     }
 
-    async fn delete_many_users(db: &Pool<Postgres>, users_id: Vec<Uuid>) -> Result<(), SqlxError> {
+    async fn delete_many_users(db: &Pool<Postgres>, users_id: Vec<Uuid>) -> Result<Vec<Uuid>, SqlxError> {
         let mut tx = db.begin().await?;
+        let mut deleted = Vec::new();
 
         for user_id in users_id {
-            sqlx::query!("DELETE FROM users WHERE id = $1", &user_id.0)
-                .execute(&mut *tx)
-                .await?;
+            if delete_user_rows(&mut tx, &user_id).await? {
+                deleted.push(user_id);
+            }
         }
 
         tx.commit().await?;
 
-        Ok(())
+        Ok(deleted)
     }
 
+    // Password has its own flow, so patch leaves it for concurrent changes and resets
     async fn patch_user(db: &Pool<Postgres>, user: User) -> Result<(), SqlxError> {
         sqlx::query!(
-            "UPDATE users SET name = $2, login = $3, password = $4 WHERE id = $1",
+            "UPDATE users SET name = $2, login = $3, email = $4::VARCHAR,
+                emailVerified = emailVerified AND email IS NOT DISTINCT FROM $4::VARCHAR
+                WHERE id = $1",
             &user.id.0,
             &user.name.0,
             &user.login.0,
-            &user.password.0
+            user.email.as_ref().map(|email| &email.0)
         )
         .execute(db)
         .await?;
         Ok(())
     }
 
+    // Same columns as `patch_user`, status and role keep their own admin actions
     async fn patch_many_users(db: &Pool<Postgres>, users: Vec<User>) -> Result<(), SqlxError> {
         let mut tx = db.begin().await?;
 
         for user in users {
-            sqlx::query!(
-                "UPDATE users SET name = $2, login = $3, password = $4 WHERE id = $1",
+            let result = sqlx::query!(
+                "UPDATE users SET name = $2, login = $3, email = $4::VARCHAR,
+                    emailVerified = emailVerified AND email IS NOT DISTINCT FROM $4::VARCHAR
+                    WHERE id = $1 AND deletedAt IS NULL",
                 &user.id.0,
                 &user.name.0,
                 &user.login.0,
-                &user.password.0,
+                user.email.as_ref().map(|email| &email.0),
             )
             .execute(&mut *tx)
             .await?;
+
+            if result.rows_affected() == 0 {
+                return Err(sqlx::Error::RowNotFound.into())
+            }
         }
 
         tx.commit().await?;
@@ -460,28 +819,18 @@ impl UserRepository<Postgres> for User {
     }
 
     async fn get_user(db: &Pool<Postgres>, user: UserSearch) -> Result<User,SqlxError> {
-        let mut query = QueryBuilder::new("SELECT id, name, login, password FROM users WHERE");
-
-        let mut first = true;
+        let mut query = QueryBuilder::new("SELECT id, name, login, email FROM users WHERE TRUE");
 
         if let Some(id) = user.id {
-            if !first { query.push(" AND"); } else { first = false }
-            query.push(format!(" id = '{}'",id.to_string()));
+            query.push(" AND id = ").push_bind(id);
         }
 
         if let Some(name) = user.name {
-            if !first { query.push(" AND"); } else { first = false }
-            query.push(format!(" name = '{}'",name.to_string()));
+            query.push(" AND name = ").push_bind(name);
         }
 
         if let Some(login) = user.login {
-            if !first { query.push(" AND"); } else { first = false }
-            query.push(format!(" login = '{}'",login.to_string()));
-        }
-
-        if let Some(password) = user.password {
-            if !first { query.push(" AND"); } else { first = false }
-            query.push(format!(" password = '{}'",password.to_string()));
+            query.push(" AND login = ").push_bind(login);
         }
 
         Ok(
@@ -492,4 +841,216 @@ impl UserRepository<Postgres> for User {
         )
     }
 
+    async fn fetch_user_email(db: &Pool<Postgres>, user_id: &Uuid) -> Result<Option<String>, SqlxError> {
+        Ok(
+            sqlx::query_scalar!("SELECT email FROM users WHERE id = $1", &user_id.0)
+                .fetch_one(db)
+                .await?
+        )
+    }
+
+}
+
+impl EmailVerificationRepository<Postgres> for User {
+    async fn is_email_verified(db: &Pool<Postgres>, user_id: &Uuid) -> Result<bool, SqlxError> {
+        Ok(
+            sqlx::query_scalar!("SELECT emailVerified FROM users WHERE id = $1", &user_id.0)
+                .fetch_one(db)
+                .await?
+        )
+    }
+
+    async fn verify_email(db: &Pool<Postgres>, user_id: &Uuid, email: &Email) -> Result<bool, SqlxError> {
+        let result = sqlx::query!(
+            "UPDATE users SET emailVerified = TRUE WHERE id = $1 AND email = $2",
+            &user_id.0,
+            &email.0
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+}
+
+impl RoleRepository<Postgres> for User {
+    // Unknown roles grant nothing
+    async fn fetch_role(db: &Pool<Postgres>, user_id: &Uuid) -> Result<Role, SqlxError> {
+        let role = sqlx::query_scalar!("SELECT role FROM users WHERE id = $1", &user_id.0)
+            .fetch_one(db)
+            .await?;
+
+        Ok(Role::parse(&role).unwrap_or(Role::User))
+    }
+
+    async fn set_role(db: &Pool<Postgres>, user_id: &Uuid, role: Role) -> Result<bool, SqlxError> {
+        let result = sqlx::query!("UPDATE users SET role = $2 WHERE id = $1 AND deletedAt IS NULL", &user_id.0, role.as_str())
+            .execute(db)
+            .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+}
+
+impl PasswordRepository<Postgres> for User {
+    async fn check_password(db: &Pool<Postgres>, user_id: &Uuid, password: &Password) -> Result<bool, SqlxError> {
+        let stored = sqlx::query_scalar!("SELECT password FROM users WHERE id = $1", &user_id.0)
+            .fetch_one(db)
+            .await?;
+        let stored = PasswordHash::from(stored);
+
+        if !stored.verify(password) {
+            return Ok(false)
+        }
+
+        if stored.is_legacy() {
+            sqlx::query!(
+                "UPDATE users SET password = $3 WHERE id = $1 AND password = $2",
+                &user_id.0,
+                stored.0,
+                password.hash().0
+            )
+            .execute(db)
+            .await?;
+        }
+
+        Ok(true)
+    }
+}
+
+impl PasswordHistoryRepository<Postgres> for User {
+    async fn fetch_recent_passwords(db: &Pool<Postgres>, user_id: &Uuid, limit: i64) -> Result<Vec<String>, SqlxError> {
+        Ok(
+            sqlx::query_scalar!(
+                "SELECT password FROM password_history WHERE userId = $1 ORDER BY createdAt DESC LIMIT $2",
+                &user_id.0,
+                limit
+            )
+            .fetch_all(db)
+            .await?
+        )
+    }
+
+    async fn change_password(db: &Pool<Postgres>, user_id: &Uuid, password: Password) -> Result<(), SqlxError> {
+        let mut tx = db.begin().await?;
+
+        let result = sqlx::query!(
+            "UPDATE users SET password = $2 WHERE id = $1 AND deletedAt IS NULL",
+            &user_id.0,
+            password.hash().0
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into())
+        }
+
+        insert_password_history(&mut tx, user_id, &password).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+async fn insert_user(tx: &mut sqlx::Transaction<'_, Postgres>, user: &User, password: &Password) -> Result<(), SqlxError> {
+    sqlx::query!(
+        "INSERT INTO users (id, name, login, password, email) VALUES ($1, $2, $3, $4, $5)",
+        &user.id.0,
+        &user.name.0,
+        &user.login.0,
+        password.hash().0,
+        user.email.as_ref().map(|email| &email.0)
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    insert_password_history(tx, &user.id, password).await
+}
+
+async fn insert_password_history(tx: &mut sqlx::Transaction<'_, Postgres>, user_id: &Uuid, password: &Password) -> Result<(), SqlxError> {
+    sqlx::query!(
+        "INSERT INTO password_history (id, userId, password) VALUES ($1, $2, $3)",
+        &Uuid::parse(uuid::Uuid::new_v4()).0,
+        &user_id.0,
+        password.history_hash(user_id)
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Deleting keeps the row, comments keep an anonymous author until the purge.
+// Personal fields are overwritten, credentials and the basket are dropped.
+// False when there was no user to delete
+async fn delete_user_rows(tx: &mut sqlx::Transaction<'_, Postgres>, user_id: &Uuid) -> Result<bool, SqlxError> {
+    let placeholder = format!("deleted-{}", user_id);
+    // Never handed out, nobody can sign in with it
+    let password = Password::from(format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple()));
+
+    let result = sqlx::query!(
+        "UPDATE users SET name = $2, login = $2, password = $3, email = NULL, emailVerified = FALSE,
+            totpSecret = NULL, totpEnabled = FALSE, status = 'deleted', statusReason = NULL, bannedUntil = NULL,
+            deletedAt = NOW()
+        WHERE id = $1 AND deletedAt IS NULL",
+        &user_id.0,
+        placeholder,
+        password.hash().0
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(false)
+    }
+
+    sqlx::query!("DELETE FROM basket WHERE userId = $1", &user_id.0)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM password_history WHERE userId = $1", &user_id.0)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM recovery_codes WHERE userId = $1", &user_id.0)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM api_keys WHERE userId = $1", &user_id.0)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM user_identities WHERE userId = $1", &user_id.0)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password_hash_verifies_only_its_password() {
+        let password = Password::from("Correct-Horse-7".to_string());
+        let hash = password.hash();
+
+        assert!(!hash.is_legacy());
+        assert!(hash.verify(&password));
+        assert!(!hash.verify(&Password::from("Correct-Horse-8".to_string())));
+        // Salted, the same password doesn't give the same hash
+        assert_ne!(hash.0, password.hash().0);
+    }
+
+    #[test]
+    fn legacy_password_is_compared_as_is() {
+        let hash = PasswordHash::from("Plain-Text-1".to_string());
+
+        assert!(hash.is_legacy());
+        assert!(hash.verify(&Password::from("Plain-Text-1".to_string())));
+        assert!(!hash.verify(&Password::from("Plain-Text-2".to_string())));
+    }
 }
@@ -0,0 +1,105 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use jsonwebtoken::jwk::JwkSet;
+use serde_json::json;
+
+use super::{AuthorizationRequest, BoxFuture, Error, IdentityProvider, Pkce, Result};
+use crate::app::models::user::{keys::{KeyRing, SigningKey}, session::unix_time};
+
+const ISSUER: &str = "https://issuer.test";
+const CLIENT_ID: &str = "cwshop-test";
+
+struct IssuedCode {
+    code_challenge: String,
+    nonce: String,
+    claims: serde_json::Value,
+}
+
+// Local OpenID Connect issuer, signs id tokens with its own Ed25519 key
+pub struct MockIssuer {
+    keys: KeyRing,
+    codes: Mutex<HashMap<String, IssuedCode>>,
+}
+
+impl MockIssuer {
+    pub fn new() -> Self {
+        let der = ring::signature::Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
+        let pem = pem::encode(&pem::Pem::new("PRIVATE KEY", der.as_ref()));
+        let key = SigningKey::ed25519_pem("mock", &pem).unwrap();
+
+        MockIssuer {
+            keys: KeyRing::new(vec![key], "mock").unwrap(),
+            codes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // What the provider does once the user consents, a code for the authorization URL
+    pub fn authorize(&self, authorization_url: &str, subject: &str, email: Option<&str>, name: &str) -> String {
+        let url = reqwest::Url::parse(authorization_url).unwrap();
+        let param = |key: &str| url.query_pairs()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.to_string())
+            .unwrap();
+
+        let code = uuid::Uuid::new_v4().simple().to_string();
+        let issued = IssuedCode {
+            code_challenge: param("code_challenge"),
+            nonce: param("nonce"),
+            claims: json!({ "sub": subject, "email": email, "email_verified": true, "name": name }),
+        };
+
+        self.codes.lock().unwrap().insert(code.clone(), issued);
+
+        code
+    }
+}
+
+impl IdentityProvider for MockIssuer {
+    fn issuer(&self) -> &str {
+        ISSUER
+    }
+
+    fn client_id(&self) -> &str {
+        CLIENT_ID
+    }
+
+    fn authorization_url(&self, request: &AuthorizationRequest) -> String {
+        let mut url = reqwest::Url::parse(&format!("{}/authorize", ISSUER)).unwrap();
+        url.query_pairs_mut()
+            .append_pair("client_id", CLIENT_ID)
+            .append_pair("state", request.state)
+            .append_pair("nonce", request.nonce)
+            .append_pair("code_challenge", request.code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        url.to_string()
+    }
+
+    fn exchange_code<'a>(&'a self, code: &'a str, code_verifier: &'a str) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let issued = self.codes
+                .lock()
+                .unwrap()
+                .remove(code)
+                .ok_or_else(|| Error::ExchangeFailed("invalid_grant".to_string()))?;
+
+            if Pkce::challenge(code_verifier) != issued.code_challenge {
+                return Err(Error::ExchangeFailed("invalid_grant".to_string()))
+            }
+
+            let now = unix_time();
+            let mut claims = issued.claims;
+            claims["iss"] = json!(ISSUER);
+            claims["aud"] = json!(CLIENT_ID);
+            claims["iat"] = json!(now);
+            claims["exp"] = json!(now + 300);
+            claims["nonce"] = json!(issued.nonce);
+
+            Ok(self.keys.encode(&claims).unwrap())
+        })
+    }
+
+    fn jwks(&self) -> BoxFuture<'_, Result<JwkSet>> {
+        Box::pin(async move { Ok(serde_json::from_value(self.keys.jwks()).unwrap()) })
+    }
+}
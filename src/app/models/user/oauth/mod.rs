@@ -0,0 +1,679 @@
+use std::{collections::HashMap, future::Future, pin::Pin, sync::LazyLock};
+
+use actix_web::{http::StatusCode, ResponseError};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+
+use crate::{app::{cookies::DeliveryMode, secrets::random_bytes}, repository::db::SqlxError};
+
+use super::{insert_password_history, Builder, Email, Login, Name, Password, User, Uuid};
+
+#[cfg(test)]
+pub mod mock;
+
+// User has 10 minutes to come back from the provider
+const PENDING_LOGIN_TTL: u64 = 10*60;
+// Clock skew allowed for id tokens signed by other servers
+const ID_TOKEN_LEEWAY: u64 = 60;
+// Id tokens are signed with the published keys of the provider, never with shared secrets
+const ID_TOKEN_ALGORITHMS: [Algorithm; 7] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+fn random_string(parts: usize) -> String {
+    std::iter::repeat_with(|| uuid::Uuid::new_v4().simple().to_string())
+        .take(parts)
+        .collect()
+}
+
+// PKCE, RFC 7636: the code is useless without the verifier that never leaves us
+pub struct Pkce {
+    pub verifier: String,
+    pub challenge: String,
+}
+
+impl Pkce {
+    pub fn generate() -> Self {
+        let verifier = random_string(3);
+
+        Pkce { challenge: Pkce::challenge(&verifier), verifier }
+    }
+
+    // S256 method
+    pub fn challenge(verifier: &str) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+    }
+}
+
+pub struct AuthorizationRequest<'a> {
+    pub state: &'a str,
+    pub nonce: &'a str,
+    pub code_challenge: &'a str,
+}
+
+// External OpenID Connect issuer, authorization code flow with PKCE
+pub trait IdentityProvider: Send + Sync {
+    fn issuer(&self) -> &str;
+    fn client_id(&self) -> &str;
+    // Where the user is sent to sign in and consent
+    fn authorization_url(&self, request: &AuthorizationRequest) -> String;
+    // Authorization code for an id token, verified by the caller
+    fn exchange_code<'a>(&'a self, code: &'a str, code_verifier: &'a str) -> BoxFuture<'a, Result<String>>;
+    // Keys the id tokens are signed with
+    fn jwks(&self) -> BoxFuture<'_, Result<JwkSet>>;
+}
+
+// Provider configured with OIDC_<NAME>_* env
+pub struct OidcProvider {
+    issuer: String,
+    client_id: String,
+    // Public clients rely on PKCE alone
+    client_secret: Option<String>,
+    authorization_endpoint: reqwest::Url,
+    token_endpoint: reqwest::Url,
+    jwks_uri: reqwest::Url,
+    redirect_uri: String,
+    scopes: String,
+    http: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+impl OidcProvider {
+    fn from_env(name: &str) -> Self {
+        let prefix = format!("OIDC_{}_", name.to_uppercase());
+        let var = |key: &str| std::env::var(format!("{}{}", prefix, key)).ok();
+        let required = |key: &str| var(key)
+            .unwrap_or_else(|| panic!("{}{} env must be provided for provider `{}`", prefix, key, name));
+        let url = |key: &str| reqwest::Url::parse(&required(key))
+            .unwrap_or_else(|e| panic!("{}{} must be a URL: {}", prefix, key, e));
+
+        OidcProvider {
+            issuer: required("ISSUER"),
+            client_id: required("CLIENT_ID"),
+            client_secret: var("CLIENT_SECRET"),
+            authorization_endpoint: url("AUTHORIZATION_ENDPOINT"),
+            token_endpoint: url("TOKEN_ENDPOINT"),
+            jwks_uri: url("JWKS_URI"),
+            redirect_uri: required("REDIRECT_URI"),
+            scopes: var("SCOPES").unwrap_or_else(|| "openid email profile".to_string()),
+            http: reqwest::Client::new(),
+        }
+    }
+}
+
+impl IdentityProvider for OidcProvider {
+    fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    fn authorization_url(&self, request: &AuthorizationRequest) -> String {
+        let mut url = self.authorization_endpoint.clone();
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", &self.redirect_uri)
+            .append_pair("scope", &self.scopes)
+            .append_pair("state", request.state)
+            .append_pair("nonce", request.nonce)
+            .append_pair("code_challenge", request.code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        url.to_string()
+    }
+
+    fn exchange_code<'a>(&'a self, code: &'a str, code_verifier: &'a str) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let mut form = vec![
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.redirect_uri),
+                ("client_id", &self.client_id),
+                ("code_verifier", code_verifier),
+            ];
+            if let Some(secret) = &self.client_secret {
+                form.push(("client_secret", secret));
+            }
+
+            let response = self.http
+                .post(self.token_endpoint.clone())
+                .form(&form)
+                .send()
+                .await?;
+
+            if !response.status().is_success() {
+                return Err(Error::ExchangeFailed(format!("token endpoint returned {}", response.status())))
+            }
+
+            Ok(response.json::<TokenResponse>().await?.id_token)
+        })
+    }
+
+    // Fetched on every sign in, they are rare and providers rotate keys
+    fn jwks(&self) -> BoxFuture<'_, Result<JwkSet>> {
+        Box::pin(async move {
+            Ok(
+                self.http
+                    .get(self.jwks_uri.clone())
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<JwkSet>()
+                    .await?
+            )
+        })
+    }
+}
+
+// Providers by name, from OIDC_PROVIDERS=name,name
+pub struct IdentityProviders(HashMap<String, Box<dyn IdentityProvider>>);
+
+impl IdentityProviders {
+    fn from_env() -> Self {
+        let names = std::env::var("OIDC_PROVIDERS").unwrap_or_default();
+
+        IdentityProviders(
+            names
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| (name.to_lowercase(), Box::new(OidcProvider::from_env(name)) as Box<dyn IdentityProvider>))
+                .collect()
+        )
+    }
+
+    pub fn get(&self, name: &str) -> Result<&dyn IdentityProvider> {
+        self.0
+            .get(name)
+            .map(|provider| provider.as_ref())
+            .ok_or(Error::UnknownProvider)
+    }
+}
+
+pub static IDENTITY_PROVIDERS: LazyLock<IdentityProviders> = LazyLock::new(IdentityProviders::from_env);
+
+// Providers are configured on start, a misconfigured one stops the server
+pub fn load_identity_providers() {
+    LazyLock::force(&IDENTITY_PROVIDERS);
+}
+
+// Identity asserted by the provider
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    name: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExternalIdentity {
+    pub provider: String,
+    pub subject: String,
+    // Only addresses the provider has verified
+    pub email: Option<Email>,
+    pub name: Option<String>,
+}
+
+pub async fn verify_id_token(provider: &dyn IdentityProvider, provider_name: &str, id_token: &str, nonce: &str) -> Result<ExternalIdentity> {
+    let header = jsonwebtoken::decode_header(id_token)?;
+    if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+        return Err(Error::InvalidIdToken(format!("unsupported algorithm {:?}", header.alg)))
+    }
+
+    let jwks = provider.jwks().await?;
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .ok_or_else(|| Error::InvalidIdToken("unknown signing key".to_string()))?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[provider.issuer()]);
+    validation.set_audience(&[provider.client_id()]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+    validation.leeway = ID_TOKEN_LEEWAY;
+
+    let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &DecodingKey::from_jwk(jwk)?, &validation)?.claims;
+
+    // Ties the token to the sign in we started
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(Error::InvalidIdToken("nonce does not match".to_string()))
+    }
+
+    Ok(ExternalIdentity {
+        provider: provider_name.to_string(),
+        subject: claims.sub,
+        email: claims.email
+            .filter(|_| claims.email_verified)
+            .and_then(|email| Email::parse(email).ok()),
+        name: claims.name,
+    })
+}
+
+// Sign in started with a provider, kept until the user comes back with a code
+#[derive(Serialize, Deserialize)]
+pub struct PendingLogin {
+    pub provider: String,
+    pub code_verifier: String,
+    pub nonce: String,
+    pub mode: DeliveryMode,
+    // Signed in user linking the identity to the account
+    pub link_user: Option<Uuid>,
+}
+
+fn pending_key(state: &str) -> String {
+    format!("oauth_state:{}", state)
+}
+
+impl PendingLogin {
+    // URL to send the user to
+    pub async fn start(
+        cache: &mut redis::aio::MultiplexedConnection,
+        provider_name: &str,
+        provider: &dyn IdentityProvider,
+        mode: DeliveryMode,
+        link_user: Option<Uuid>
+    ) -> Result<String> {
+        let state = random_string(2);
+        let pkce = Pkce::generate();
+
+        let pending = PendingLogin {
+            provider: provider_name.to_string(),
+            code_verifier: pkce.verifier,
+            nonce: random_string(2),
+            mode,
+            link_user,
+        };
+
+        let url = provider.authorization_url(&AuthorizationRequest {
+            state: &state,
+            nonce: &pending.nonce,
+            code_challenge: &pkce.challenge,
+        });
+
+        let pending = serde_json::to_string(&pending).expect("Pending login is serializable");
+        cache.set_ex::<_, _, ()>(pending_key(&state), pending, PENDING_LOGIN_TTL).await?;
+
+        Ok(url)
+    }
+
+    // State is single use, so a code can't be redeemed twice through us
+    pub async fn take(cache: &mut redis::aio::MultiplexedConnection, provider_name: &str, state: &str) -> Result<Self> {
+        let pending: Option<String> = cache.get_del(pending_key(state)).await?;
+
+        pending
+            .and_then(|pending| serde_json::from_str::<PendingLogin>(&pending).ok())
+            .filter(|pending| pending.provider == provider_name)
+            .ok_or(Error::InvalidState)
+    }
+
+    // Link flow has to be finished by the user who started it, otherwise anyone
+    // holding the state could attach their identity to that account
+    pub fn check_linker(&self, signed_in: Option<&Uuid>) -> Result<()> {
+        match &self.link_user {
+            Some(link_user) if signed_in != Some(link_user) => Err(Error::LinkNotAuthorized),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn complete(&self, provider: &dyn IdentityProvider, code: &str) -> Result<ExternalIdentity> {
+        let id_token = provider.exchange_code(code, &self.code_verifier).await?;
+
+        verify_id_token(provider, &self.provider, &id_token, &self.nonce).await
+    }
+}
+
+// Auto provisioning
+
+// Login from the address or the name with a random suffix, within the login rules
+fn provisioned_login(identity: &ExternalIdentity) -> Login {
+    let source = identity.email
+        .as_ref()
+        .and_then(|email| email.0.split('@').next().map(str::to_string))
+        .or_else(|| identity.name.clone())
+        .unwrap_or_default();
+
+    let mut base: String = source
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '.')
+        .take(14)
+        .collect::<String>()
+        .to_lowercase();
+    if base.len() < 3 {
+        base = "user".to_string();
+    }
+
+    Login::parse(format!("{}_{}", base, &random_string(1)[..5]))
+        .expect("Provisioned login follows the login rules")
+}
+
+// Name from the provider or a generic one, names are unique so it gets a
+// random suffix too. Names can't contain digits, the suffix is letters only
+fn provisioned_name(identity: &ExternalIdentity) -> Name {
+    let base = identity.name
+        .as_ref()
+        .map(|name| name.trim().chars().take(64).collect::<String>())
+        .filter(|name| Name::parse(name.clone()).is_ok())
+        .unwrap_or_else(|| "Customer".to_string());
+
+    let suffix: String = random_bytes(5)
+        .iter()
+        .map(|byte| (b'a' + byte % 26) as char)
+        .collect();
+
+    Name::parse(format!("{} {}", base, suffix))
+        .expect("Provisioned name follows the name rules")
+}
+
+// Account for an identity seen for the first time, the user signs in with the
+// provider or sets a password with a password reset
+pub fn provisioned_user(identity: &ExternalIdentity) -> User {
+    let mut builder = Builder::new();
+
+    builder.id(Uuid::parse(uuid::Uuid::new_v4()));
+    builder.login(provisioned_login(identity));
+    builder.name(provisioned_name(identity));
+    if let Some(email) = &identity.email {
+        builder.email(email.clone());
+    }
+
+    builder.try_get().expect("Provisioned user has all fields")
+}
+
+// Database
+
+#[derive(Debug, Serialize)]
+pub struct LinkedIdentity {
+    pub provider: String,
+    pub email: Option<String>,
+    pub created_at: i64,
+}
+
+pub trait IdentityRepository<T: sqlx::Database> {
+    async fn fetch_identity_owner(db: &Pool<T>, identity: &ExternalIdentity) -> std::result::Result<Option<Uuid>, SqlxError>;
+    async fn fetch_identities(db: &Pool<T>, user_id: &Uuid) -> std::result::Result<Vec<LinkedIdentity>, SqlxError>;
+    // One identity per provider and user
+    async fn link_identity(db: &Pool<T>, user_id: &Uuid, identity: &ExternalIdentity) -> std::result::Result<(), SqlxError>;
+    async fn unlink_identity(db: &Pool<T>, user_id: &Uuid, provider: &str) -> std::result::Result<bool, SqlxError>;
+    // Creates the account and links the identity at once, the address counts as verified
+    async fn provision_user(db: &Pool<T>, user: User, identity: &ExternalIdentity) -> std::result::Result<(), SqlxError>;
+}
+
+impl IdentityRepository<Postgres> for User {
+    async fn fetch_identity_owner(db: &Pool<Postgres>, identity: &ExternalIdentity) -> std::result::Result<Option<Uuid>, SqlxError> {
+        let owner = sqlx::query_scalar!(
+            "SELECT userId FROM user_identities WHERE provider = $1 AND subject = $2",
+            identity.provider,
+            identity.subject
+        )
+        .fetch_optional(db)
+        .await?;
+
+        Ok(owner.map(Uuid::from))
+    }
+
+    async fn fetch_identities(db: &Pool<Postgres>, user_id: &Uuid) -> std::result::Result<Vec<LinkedIdentity>, SqlxError> {
+        Ok(
+            sqlx::query_as!(
+                LinkedIdentity,
+                r#"SELECT provider, email, EXTRACT(EPOCH FROM createdAt)::BIGINT as "created_at!"
+                FROM user_identities WHERE userId = $1 ORDER BY createdAt"#,
+                &user_id.0
+            )
+            .fetch_all(db)
+            .await?
+        )
+    }
+
+    async fn link_identity(db: &Pool<Postgres>, user_id: &Uuid, identity: &ExternalIdentity) -> std::result::Result<(), SqlxError> {
+        let id = uuid::Uuid::new_v4();
+
+        sqlx::query!(
+            "INSERT INTO user_identities (id, userId, provider, subject, email) VALUES ($1, $2, $3, $4, $5)",
+            &id.as_bytes()[..],
+            &user_id.0,
+            identity.provider,
+            identity.subject,
+            identity.email.as_ref().map(|email| &email.0)
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn unlink_identity(db: &Pool<Postgres>, user_id: &Uuid, provider: &str) -> std::result::Result<bool, SqlxError> {
+        let result = sqlx::query!(
+            "DELETE FROM user_identities WHERE userId = $1 AND provider = $2",
+            &user_id.0,
+            provider
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    // The password is unknown to anyone
+    async fn provision_user(db: &Pool<Postgres>, user: User, identity: &ExternalIdentity) -> std::result::Result<(), SqlxError> {
+        let password = Password::from(random_string(2));
+        let mut tx = db.begin().await?;
+
+        sqlx::query!(
+            "INSERT INTO users (id, name, login, password, email, emailVerified) VALUES ($1, $2, $3, $4, $5, $6)",
+            &user.id.0,
+            &user.name.0,
+            &user.login.0,
+            password.hash().0,
+            user.email.as_ref().map(|email| &email.0),
+            user.email.is_some()
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        insert_password_history(&mut tx, &user.id, &password).await?;
+
+        let id = uuid::Uuid::new_v4();
+
+        sqlx::query!(
+            "INSERT INTO user_identities (id, userId, provider, subject, email) VALUES ($1, $2, $3, $4, $5)",
+            &id.as_bytes()[..],
+            &user.id.0,
+            identity.provider,
+            identity.subject,
+            identity.email.as_ref().map(|email| &email.0)
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+// Error
+pub type Result<T> = std::result::Result<T,Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownProvider,
+    // Missing, expired, used or started with another provider
+    InvalidState,
+    // Link callback without the session of the linking user
+    LinkNotAuthorized,
+    ExchangeFailed(String),
+    InvalidIdToken(String),
+    RedisError(redis::RedisError),
+}
+
+impl From<jsonwebtoken::errors::Error> for Error {
+    fn from(value: jsonwebtoken::errors::Error) -> Self {
+        Error::InvalidIdToken(value.to_string())
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(value: reqwest::Error) -> Self {
+        Error::ExchangeFailed(value.to_string())
+    }
+}
+
+impl From<redis::RedisError> for Error {
+    fn from(value: redis::RedisError) -> Self {
+        Error::RedisError(value)
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnknownProvider => { write!(f, "Unknown identity provider") },
+            Error::InvalidState => { write!(f, "Invalid or expired sign in state") },
+            Error::LinkNotAuthorized => { write!(f, "Linking must be finished by the signed in user who started it") },
+            Error::ExchangeFailed(reason) => { write!(f, "Identity provider sign in failed: {}", reason) },
+            Error::InvalidIdToken(reason) => { write!(f, "Invalid id token: {}", reason) },
+            Error::RedisError(error) => { write!(f,"{}",error) },
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::UnknownProvider => StatusCode::NOT_FOUND,
+            Error::InvalidState => StatusCode::BAD_REQUEST,
+            Error::LinkNotAuthorized => StatusCode::FORBIDDEN,
+            Error::ExchangeFailed(_) => StatusCode::BAD_GATEWAY,
+            Error::InvalidIdToken(_) => StatusCode::UNAUTHORIZED,
+            Error::RedisError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mock::MockIssuer, *};
+
+    fn pending(pkce: Pkce, nonce: &str) -> PendingLogin {
+        PendingLogin {
+            provider: "mock".to_string(),
+            code_verifier: pkce.verifier,
+            nonce: nonce.to_string(),
+            mode: DeliveryMode::Token,
+            link_user: None,
+        }
+    }
+
+    fn authorize(issuer: &MockIssuer, pkce: &Pkce, nonce: &str) -> String {
+        let url = issuer.authorization_url(&AuthorizationRequest { state: "state", nonce, code_challenge: &pkce.challenge });
+
+        issuer.authorize(&url, "subject-1", Some("Boba@Example.com"), "Boba Fett")
+    }
+
+    #[actix_web::test]
+    async fn code_flow_yields_verified_identity() {
+        let issuer = MockIssuer::new();
+        let pkce = Pkce::generate();
+        let code = authorize(&issuer, &pkce, "nonce");
+
+        let identity = pending(pkce, "nonce").complete(&issuer, &code).await.unwrap();
+
+        assert_eq!(identity.provider, "mock");
+        assert_eq!(identity.subject, "subject-1");
+        assert_eq!(identity.email.map(|email| email.0).as_deref(), Some("boba@example.com"));
+
+        // Codes are single use
+        assert!(pending(Pkce::generate(), "nonce").complete(&issuer, &code).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn code_needs_matching_verifier_and_nonce() {
+        let issuer = MockIssuer::new();
+
+        let pkce = Pkce::generate();
+        let code = authorize(&issuer, &pkce, "nonce");
+        assert!(matches!(pending(Pkce::generate(), "nonce").complete(&issuer, &code).await, Err(Error::ExchangeFailed(_))));
+
+        let pkce = Pkce::generate();
+        let code = authorize(&issuer, &pkce, "nonce");
+        assert!(matches!(pending(pkce, "other").complete(&issuer, &code).await, Err(Error::InvalidIdToken(_))));
+    }
+
+    #[test]
+    fn link_flow_needs_linking_user() {
+        let linker = Uuid::parse(uuid::Uuid::new_v4());
+        let other = Uuid::parse(uuid::Uuid::new_v4());
+        let link = PendingLogin { link_user: Some(linker.clone()), ..pending(Pkce::generate(), "nonce") };
+
+        assert!(link.check_linker(Some(&linker)).is_ok());
+        assert!(matches!(link.check_linker(Some(&other)), Err(Error::LinkNotAuthorized)));
+        assert!(matches!(link.check_linker(None), Err(Error::LinkNotAuthorized)));
+
+        // Sign in flows need no session
+        assert!(pending(Pkce::generate(), "nonce").check_linker(None).is_ok());
+    }
+
+    #[test]
+    fn provisioned_user_follows_rules() {
+        let identity = ExternalIdentity {
+            provider: "mock".to_string(),
+            subject: "subject-1".to_string(),
+            email: Some(Email::parse("Very.Long-Name+shop@example.com".to_string()).unwrap()),
+            name: Some("R2D2".to_string()),
+        };
+
+        let user = provisioned_user(&identity);
+
+        assert!(Login::parse(user.login.0.clone()).is_ok());
+        assert!(user.login.0.starts_with("very.longname"));
+        // Name with digits is not a valid name
+        assert!(Name::parse(user.name.0.clone()).is_ok());
+        assert!(user.name.0.starts_with("Customer "));
+        assert_eq!(user.email.map(|email| email.0).as_deref(), Some("very.long-name+shop@example.com"));
+    }
+
+    #[test]
+    fn nameless_identities_get_distinct_names() {
+        let identity = |subject: &str| ExternalIdentity {
+            provider: "mock".to_string(),
+            subject: subject.to_string(),
+            email: None,
+            name: None,
+        };
+
+        let first = provisioned_user(&identity("subject-1"));
+        let second = provisioned_user(&identity("subject-2"));
+
+        assert!(Name::parse(first.name.0.clone()).is_ok());
+        assert!(Name::parse(second.name.0.clone()).is_ok());
+        assert_ne!(first.name.0, second.name.0);
+        assert_ne!(first.login.0, second.login.0);
+    }
+}
@@ -0,0 +1,132 @@
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{auth, Uuid};
+
+// Single use tokens handed out to users: mailed codes and sign in challenges
+#[derive(Debug, Clone, Copy)]
+pub enum Purpose {
+    PasswordReset,
+    EmailVerification,
+    MfaChallenge,
+}
+
+impl Purpose {
+    fn prefix(&self) -> &'static str {
+        match self {
+            Purpose::PasswordReset => "password_reset",
+            Purpose::EmailVerification => "email_verification",
+            Purpose::MfaChallenge => "mfa_challenge",
+        }
+    }
+
+    // Reset tokens live for 15 minutes, verification tokens for a day,
+    // second sign in step has to follow the first one shortly
+    pub fn ttl(&self) -> u64 {
+        match self {
+            Purpose::PasswordReset => 15*60,
+            Purpose::EmailVerification => 24*60*60,
+            Purpose::MfaChallenge => 5*60,
+        }
+    }
+}
+
+// Only a hash of the token is kept in redis
+fn token_key(purpose: Purpose, token_hash: &str) -> String {
+    format!("{}:{}", purpose.prefix(), token_hash)
+}
+
+fn user_key(purpose: Purpose, user_id: &Uuid) -> String {
+    format!("{}_user:{}", purpose.prefix(), user_id)
+}
+
+// What the token was issued for: owner and, for verification, the address,
+// for MFA challenge, the login
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claim {
+    pub user_id: Uuid,
+    pub subject: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct OneTimeToken {
+    purpose: Purpose,
+    token: String,
+}
+
+impl OneTimeToken {
+    fn generate(purpose: Purpose) -> Self {
+        let random = [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()];
+        OneTimeToken {
+            purpose,
+            token: random.iter().map(|part| part.simple().to_string()).collect(),
+        }
+    }
+
+    fn hash(&self) -> String {
+        Sha256::digest(self.token.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    // Issuing a token invalidates the previous one of the same user and purpose
+    pub async fn issue(
+        cache: &mut redis::aio::MultiplexedConnection,
+        purpose: Purpose,
+        user_id: &Uuid,
+        subject: &str
+    ) -> auth::Result<Self> {
+        let token = OneTimeToken::generate(purpose);
+        let hash = token.hash();
+
+        let claim = Claim { user_id: user_id.clone(), subject: subject.to_string() };
+        let claim = serde_json::to_string(&claim).map_err(|_| auth::Error::InvalidSession)?;
+
+        let previous: Option<String> = cache.get(user_key(purpose, user_id)).await?;
+
+        let mut pipe = redis::pipe();
+        if let Some(previous) = previous {
+            pipe.del(token_key(purpose, &previous)).ignore();
+        }
+        pipe.set_ex(token_key(purpose, &hash), claim, purpose.ttl()).ignore()
+            .set_ex(user_key(purpose, user_id), &hash, purpose.ttl()).ignore()
+            .query_async::<_, ()>(cache).await?;
+
+        Ok(token)
+    }
+
+    // Looks up the claim without using the token up
+    pub async fn claim(&self, cache: &mut redis::aio::MultiplexedConnection) -> auth::Result<Claim> {
+        let claim: Option<String> = cache.get(token_key(self.purpose, &self.hash())).await?;
+
+        parse_claim(claim)
+    }
+
+    // Atomically removes the token, so only one request can succeed with it
+    pub async fn consume(self, cache: &mut redis::aio::MultiplexedConnection) -> auth::Result<Claim> {
+        let claim: Option<String> = cache.get_del(token_key(self.purpose, &self.hash())).await?;
+        let claim = parse_claim(claim)?;
+
+        cache.del::<_, ()>(user_key(self.purpose, &claim.user_id)).await?;
+
+        Ok(claim)
+    }
+
+    pub fn parse(purpose: Purpose, token_string: String) -> Self {
+        OneTimeToken { purpose, token: token_string.trim().to_string() }
+    }
+}
+
+impl std::fmt::Display for OneTimeToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.token)
+    }
+}
+
+fn parse_claim(claim: Option<String>) -> auth::Result<Claim> {
+    claim
+        .and_then(|claim| serde_json::from_str(&claim).ok())
+        .ok_or(auth::Error::InvalidSession)
+}
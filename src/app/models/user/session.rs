@@ -0,0 +1,193 @@
+use std::{collections::HashMap, sync::LazyLock, time::{SystemTime, UNIX_EPOCH}};
+
+use redis::AsyncCommands;
+use serde::Serialize;
+
+use super::{auth, Uuid};
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is after unix epoch")
+        .as_secs()
+}
+
+// Sessions are redis hashes, named by the `sid` claim of the tokens
+pub(super) fn session_key(session_id: &str) -> String {
+    format!("session:{}", session_id)
+}
+
+pub(super) fn user_sessions_key(user_id: &Uuid) -> String {
+    format!("user_sessions:{}", user_id)
+}
+
+// Device the session was started from
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+pub enum Rotation {
+    Rotated,
+    // Presented refresh token was rotated before, the session is revoked
+    Reused,
+    Missing,
+}
+
+// Compare and swap of the current refresh token id, in one step so two
+// requests with the same token can't both rotate it
+static ROTATE_SCRIPT: LazyLock<redis::Script> = LazyLock::new(|| redis::Script::new(r"
+    local current = redis.call('HGET', KEYS[1], 'refresh_jti')
+    if not current then
+        return 0
+    end
+    if current ~= ARGV[1] then
+        redis.call('DEL', KEYS[1])
+        redis.call('SREM', KEYS[2], ARGV[3])
+        return -1
+    end
+    redis.call('HSET', KEYS[1], 'refresh_jti', ARGV[2], 'last_seen_at', ARGV[4])
+    redis.call('EXPIRE', KEYS[1], ARGV[5])
+    return 1
+"));
+
+#[derive(Debug, Serialize)]
+pub struct Session {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: u64,
+    pub last_seen_at: u64,
+}
+
+impl Session {
+    fn from_fields(id: String, mut fields: HashMap<String, String>) -> Option<Self> {
+        let time = |value: Option<String>| value.and_then(|value| value.parse().ok());
+
+        Some(Session {
+            id,
+            user_agent: fields.remove("user_agent"),
+            ip: fields.remove("ip"),
+            created_at: time(fields.remove("created_at"))?,
+            last_seen_at: time(fields.remove("last_seen_at"))?,
+        })
+    }
+
+    pub async fn create(
+        cache: &mut redis::aio::MultiplexedConnection,
+        session_id: &Uuid,
+        user_id: &Uuid,
+        client: &ClientInfo,
+        refresh_jti: &str,
+        seconds_to_live: i64
+    ) -> auth::Result<()> {
+        let key = session_key(&session_id.to_string());
+        let now = unix_time().to_string();
+
+        let mut fields = vec![
+            ("user_id", user_id.to_string()),
+            ("created_at", now.clone()),
+            ("last_seen_at", now),
+            ("refresh_jti", refresh_jti.to_string()),
+        ];
+        if let Some(user_agent) = &client.user_agent {
+            fields.push(("user_agent", user_agent.clone()));
+        }
+        if let Some(ip) = &client.ip {
+            fields.push(("ip", ip.clone()));
+        }
+
+        redis::pipe()
+            .hset_multiple(&key, &fields).ignore()
+            .expire(&key, seconds_to_live).ignore()
+            .sadd(user_sessions_key(user_id), session_id.to_string()).ignore()
+            .query_async::<_, ()>(cache).await?;
+
+        Ok(())
+    }
+
+    // Sessions of the user, expired ones are dropped from the index on the way
+    pub async fn list(cache: &mut redis::aio::MultiplexedConnection, user_id: &Uuid) -> auth::Result<Vec<Session>> {
+        let index = user_sessions_key(user_id);
+        let session_ids: Vec<String> = cache.smembers(&index).await?;
+
+        let mut sessions = Vec::new();
+        let mut expired = Vec::new();
+
+        for session_id in session_ids {
+            let fields: HashMap<String, String> = cache.hgetall(session_key(&session_id)).await?;
+
+            match Session::from_fields(session_id.clone(), fields) {
+                Some(session) => sessions.push(session),
+                None => expired.push(session_id),
+            }
+        }
+
+        if !expired.is_empty() {
+            cache.srem::<_, _, ()>(&index, expired).await?;
+        }
+
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.last_seen_at));
+
+        Ok(sessions)
+    }
+
+    // Revokes a session of the user, false when the user has no such session
+    pub async fn revoke(cache: &mut redis::aio::MultiplexedConnection, user_id: &Uuid, session_id: &str) -> auth::Result<bool> {
+        let removed: u64 = cache.srem(user_sessions_key(user_id), session_id).await?;
+        if removed == 0 {
+            return Ok(false)
+        }
+
+        cache.del::<_, ()>(session_key(session_id)).await?;
+
+        Ok(true)
+    }
+
+    pub async fn rotate(
+        cache: &mut redis::aio::MultiplexedConnection,
+        user_id: &Uuid,
+        session_id: &str,
+        presented_jti: &str,
+        next_jti: &str,
+        seconds_to_live: i64
+    ) -> auth::Result<Rotation> {
+        let result: i64 = ROTATE_SCRIPT
+            .key(session_key(session_id))
+            .key(user_sessions_key(user_id))
+            .arg(presented_jti)
+            .arg(next_jti)
+            .arg(session_id)
+            .arg(unix_time())
+            .arg(seconds_to_live)
+            .invoke_async(cache)
+            .await?;
+
+        Ok(match result {
+            1 => Rotation::Rotated,
+            -1 => Rotation::Reused,
+            _ => Rotation::Missing,
+        })
+    }
+}
+
+// Records last use in one step, so an expiring session isn't recreated without TTL
+static TOUCH_SCRIPT: LazyLock<redis::Script> = LazyLock::new(|| redis::Script::new(r"
+    if redis.call('EXISTS', KEYS[1]) == 0 then
+        return false
+    end
+    redis.call('HSET', KEYS[1], 'last_seen_at', ARGV[1])
+    return redis.call('HGET', KEYS[1], 'user_id')
+"));
+
+// Owner of the session, when it exists
+pub(super) async fn touch(cache: &mut redis::aio::MultiplexedConnection, session_id: &str) -> auth::Result<Option<String>> {
+    Ok(
+        TOUCH_SCRIPT
+            .key(session_key(session_id))
+            .arg(unix_time())
+            .invoke_async(cache)
+            .await?
+    )
+}
@@ -0,0 +1,217 @@
+use std::sync::LazyLock;
+
+use redis::AsyncCommands;
+
+use super::{auth, Login};
+
+// Failed sign in limits. Every failure past the free attempts locks
+// sign in for twice as long as the previous one, up to `max_lockout`.
+// Password reset requests are counted per mailbox and per address in
+// windows of `reset_window` seconds
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    pub login_attempts: u64,
+    pub ip_attempts: u64,
+    pub base_lockout: u64,
+    pub max_lockout: u64,
+    pub reset_email_requests: u64,
+    pub reset_ip_requests: u64,
+    pub reset_window: u64,
+}
+
+impl Policy {
+    fn from_env() -> Self {
+        let var = |name: &str, default: u64| {
+            std::env::var(name)
+                .map(|value| value.parse().unwrap_or_else(|_| panic!("{} must be a number", name)))
+                .unwrap_or(default)
+        };
+
+        Policy {
+            login_attempts: var("SIGNIN_MAX_ATTEMPTS", 5),
+            ip_attempts: var("SIGNIN_MAX_ATTEMPTS_PER_IP", 20),
+            base_lockout: var("SIGNIN_LOCKOUT_SECONDS", 30),
+            max_lockout: var("SIGNIN_MAX_LOCKOUT_SECONDS", 60*60),
+            reset_email_requests: var("PASSWORD_RESET_MAX_PER_EMAIL", 3),
+            reset_ip_requests: var("PASSWORD_RESET_MAX_PER_IP", 10),
+            reset_window: var("PASSWORD_RESET_WINDOW_SECONDS", 60*60),
+        }
+    }
+
+    // Seconds to lock for after `failures` failed attempts, when they exceed `free_attempts`
+    pub fn lockout(&self, failures: u64, free_attempts: u64) -> Option<u64> {
+        if failures < free_attempts {
+            return None
+        }
+
+        let doublings = (failures - free_attempts).min(32) as u32;
+        Some(self.base_lockout.saturating_mul(1 << doublings).min(self.max_lockout))
+    }
+}
+
+static POLICY: LazyLock<Policy> = LazyLock::new(Policy::from_env);
+
+// Limits are read on start, a bad one stops the server before any sign in
+pub fn load_policy() {
+    LazyLock::force(&POLICY);
+}
+
+enum Scope {
+    Login,
+    Ip,
+}
+
+fn failures_key(scope: &Scope, value: &str) -> String {
+    match scope {
+        Scope::Login => format!("signin_failures:login:{}", value),
+        Scope::Ip => format!("signin_failures:ip:{}", value),
+    }
+}
+
+fn lock_key(scope: &Scope, value: &str) -> String {
+    match scope {
+        Scope::Login => format!("signin_lock:login:{}", value),
+        Scope::Ip => format!("signin_lock:ip:{}", value),
+    }
+}
+
+// Failed attempts of one login from one address
+pub struct SignInThrottle {
+    login: String,
+    ip: Option<String>,
+}
+
+impl SignInThrottle {
+    pub fn new(login: &Login, ip: Option<String>) -> Self {
+        SignInThrottle { login: login.to_string(), ip }
+    }
+
+    fn scopes(&self) -> Vec<(Scope, &str, u64)> {
+        let mut scopes = vec![(Scope::Login, self.login.as_str(), POLICY.login_attempts)];
+        if let Some(ip) = &self.ip {
+            scopes.push((Scope::Ip, ip.as_str(), POLICY.ip_attempts));
+        }
+        scopes
+    }
+
+    // Seconds left until sign in is allowed again
+    pub async fn locked_for(&self, cache: &mut redis::aio::MultiplexedConnection) -> auth::Result<Option<u64>> {
+        let mut locked_for: Option<u64> = None;
+
+        for (scope, value, _) in self.scopes() {
+            let ttl: i64 = cache.ttl(lock_key(&scope, value)).await?;
+            if ttl > 0 {
+                locked_for = locked_for.max(Some(ttl as u64));
+            }
+        }
+
+        Ok(locked_for)
+    }
+
+    // Counts the failure, returns the lockout when it triggers one
+    pub async fn register_failure(&self, cache: &mut redis::aio::MultiplexedConnection) -> auth::Result<Option<u64>> {
+        let mut lockout: Option<u64> = None;
+
+        for (scope, value, free_attempts) in self.scopes() {
+            let key = failures_key(&scope, value);
+
+            // Counters outlive the longest lockout, so backoff keeps growing
+            let (failures,): (u64,) = redis::pipe()
+                .incr(&key, 1)
+                .expire(&key, POLICY.max_lockout as i64).ignore()
+                .query_async(cache).await?;
+
+            if let Some(seconds) = POLICY.lockout(failures, free_attempts) {
+                cache.set_ex::<_, _, ()>(lock_key(&scope, value), "", seconds).await?;
+                lockout = lockout.max(Some(seconds));
+            }
+        }
+
+        Ok(lockout)
+    }
+
+    // Per address counter stays, otherwise signing into an own account
+    // would reset the limit for guessing others
+    pub async fn reset(&self, cache: &mut redis::aio::MultiplexedConnection) -> auth::Result<()> {
+        redis::pipe()
+            .del(failures_key(&Scope::Login, &self.login)).ignore()
+            .del(lock_key(&Scope::Login, &self.login)).ignore()
+            .query_async::<_, ()>(cache).await?;
+
+        Ok(())
+    }
+}
+
+// Password reset requests from one address and to the mailboxes it asks for
+pub struct ResetThrottle {
+    ip: Option<String>,
+}
+
+impl ResetThrottle {
+    pub fn new(ip: Option<String>) -> Self {
+        ResetThrottle { ip }
+    }
+
+    // Counts the request, returns seconds until the address may ask again
+    // when it's over the limit. Without an address there is nothing to count
+    pub async fn ip_limited(&self, cache: &mut redis::aio::MultiplexedConnection) -> auth::Result<Option<u64>> {
+        match &self.ip {
+            Some(ip) => count_request(cache, format!("reset_requests:ip:{}", ip), POLICY.reset_ip_requests).await,
+            None => Ok(None),
+        }
+    }
+
+    // Counts the request, returns whether a mail can go to the mailbox
+    pub async fn allows_mail(&self, email: &str, cache: &mut redis::aio::MultiplexedConnection) -> auth::Result<bool> {
+        let limited = count_request(
+            cache,
+            format!("reset_requests:email:{}", email),
+            POLICY.reset_email_requests
+        ).await?;
+
+        Ok(limited.is_none())
+    }
+}
+
+// Fixed window counter, the window starts with the first request
+async fn count_request(cache: &mut redis::aio::MultiplexedConnection, key: String, limit: u64) -> auth::Result<Option<u64>> {
+    let requests: u64 = cache.incr(&key, 1).await?;
+    let mut ttl: i64 = cache.ttl(&key).await?;
+
+    // Also covers a counter left without expiry by a failed call
+    if ttl < 0 {
+        cache.expire::<_, ()>(&key, POLICY.reset_window as i64).await?;
+        ttl = POLICY.reset_window as i64;
+    }
+
+    if requests <= limit {
+        return Ok(None)
+    }
+
+    Ok(Some(ttl.max(1) as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lockout_doubles_up_to_max() {
+        let policy = Policy {
+            login_attempts: 5,
+            ip_attempts: 20,
+            base_lockout: 30,
+            max_lockout: 600,
+            reset_email_requests: 3,
+            reset_ip_requests: 10,
+            reset_window: 3600,
+        };
+
+        assert_eq!(policy.lockout(4, 5), None);
+        assert_eq!(policy.lockout(5, 5), Some(30));
+        assert_eq!(policy.lockout(6, 5), Some(60));
+        assert_eq!(policy.lockout(9, 5), Some(480));
+        assert_eq!(policy.lockout(10, 5), Some(600));
+        assert_eq!(policy.lockout(500, 5), Some(600));
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+
+pub mod outbox;
+pub mod smtp;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// Blocking by design, handlers send through `web::block`
+pub trait Mailer: Send + Sync {
+    fn send(&self, message: &Message) -> Result<()>;
+}
+
+// MAILER selects implementation: `smtp`, `file` (default) or `memory`
+pub fn from_env() -> Arc<dyn Mailer> {
+    dotenv().ok();
+
+    match std::env::var("MAILER").as_deref() {
+        Ok("smtp") => Arc::new(smtp::SmtpMailer::from_env()),
+        Ok("memory") => Arc::new(outbox::MemoryOutbox::new()),
+        Ok("file") | Err(_) => {
+            let dir = std::env::var("MAIL_OUTBOX_DIR").unwrap_or("outbox".to_string());
+            Arc::new(outbox::FileOutbox::new(dir))
+        },
+        Ok(other) => panic!("Unknown MAILER: {}", other),
+    }
+}
+
+// Error
+pub type Result<T> = std::result::Result<T,Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidAddress(String),
+    Build(lettre::error::Error),
+    Smtp(lettre::transport::smtp::Error),
+    Io(std::io::Error),
+}
+
+impl From<lettre::error::Error> for Error {
+    fn from(value: lettre::error::Error) -> Self {
+        Error::Build(value)
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for Error {
+    fn from(value: lettre::transport::smtp::Error) -> Self {
+        Error::Smtp(value)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Error::Io(value)
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidAddress(address) => { write!(f, "Invalid address: {}", address) },
+            Error::Build(error) => { write!(f, "{}", error) },
+            Error::Smtp(error) => { write!(f, "{}", error) },
+            Error::Io(error) => { write!(f, "{}", error) },
+        }
+    }
+}
+
+impl std::error::Error for Error {}
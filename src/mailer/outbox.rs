@@ -0,0 +1,105 @@
+use std::{path::PathBuf, sync::Mutex};
+
+use super::{Mailer, Message, Result};
+
+// Writes every message as a JSON file, for local development
+pub struct FileOutbox {
+    dir: PathBuf,
+}
+
+impl FileOutbox {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        FileOutbox { dir: dir.into() }
+    }
+}
+
+impl Mailer for FileOutbox {
+    fn send(&self, message: &Message) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+
+        let path = self.dir.join(format!("{}.json", uuid::Uuid::new_v4()));
+        let content = serde_json::to_vec_pretty(message)
+            .expect("Message is always serializable");
+
+        std::fs::write(path, content)?;
+
+        Ok(())
+    }
+}
+
+// Keeps messages in memory, for tests
+#[derive(Default)]
+pub struct MemoryOutbox {
+    messages: Mutex<Vec<Message>>,
+}
+
+impl MemoryOutbox {
+    pub fn new() -> Self {
+        MemoryOutbox::default()
+    }
+
+    #[cfg(test)]
+    pub fn messages(&self) -> Vec<Message> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    #[cfg(test)]
+    pub fn last_to(&self, to: &str) -> Option<Message> {
+        self.messages
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|message| message.to == to)
+            .cloned()
+    }
+}
+
+impl Mailer for MemoryOutbox {
+    fn send(&self, message: &Message) -> Result<()> {
+        self.messages.lock().unwrap().push(message.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(to: &str) -> Message {
+        Message {
+            to: to.to_string(),
+            subject: "Subject".to_string(),
+            body: "Body".to_string(),
+        }
+    }
+
+    #[test]
+    fn memory_outbox_keeps_messages() {
+        let outbox = MemoryOutbox::new();
+
+        outbox.send(&message("a@shop.test")).unwrap();
+        outbox.send(&message("b@shop.test")).unwrap();
+
+        assert_eq!(outbox.messages().len(), 2);
+        assert_eq!(outbox.last_to("b@shop.test"), Some(message("b@shop.test")));
+        assert_eq!(outbox.last_to("c@shop.test"), None);
+    }
+
+    #[test]
+    fn file_outbox_writes_messages() {
+        let dir = std::env::temp_dir().join(format!("outbox-{}", uuid::Uuid::new_v4()));
+        let outbox = FileOutbox::new(&dir);
+
+        outbox.send(&message("a@shop.test")).unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+
+        let content = std::fs::read(files[0].as_ref().unwrap().path()).unwrap();
+        let written: Message = serde_json::from_slice(&content).unwrap();
+        assert_eq!(written, message("a@shop.test"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,55 @@
+use lettre::{message::Mailbox, transport::smtp::authentication::Credentials, SmtpTransport, Transport};
+
+use super::{Error, Mailer, Message, Result};
+
+pub struct SmtpMailer {
+    transport: SmtpTransport,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(transport: SmtpTransport, from: Mailbox) -> Self {
+        SmtpMailer { transport, from }
+    }
+
+    // STARTTLS relay configured by SMTP_HOST, SMTP_PORT, SMTP_USERNAME, SMTP_PASSWORD and MAIL_FROM
+    pub fn from_env() -> Self {
+        let host = std::env::var("SMTP_HOST")
+            .expect("SMTP_HOST env must be provided");
+        let port: u16 = std::env::var("SMTP_PORT")
+            .map(|port| port.parse().expect("SMTP_PORT must be a port number"))
+            .unwrap_or(587);
+        let from: Mailbox = std::env::var("MAIL_FROM")
+            .expect("MAIL_FROM env must be provided")
+            .parse()
+            .expect("MAIL_FROM must be a valid address");
+
+        let mut builder = SmtpTransport::starttls_relay(&host)
+            .expect("Error building SMTP transport")
+            .port(port);
+
+        if let (Ok(username), Ok(password)) = (std::env::var("SMTP_USERNAME"), std::env::var("SMTP_PASSWORD")) {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        SmtpMailer::new(builder.build(), from)
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, message: &Message) -> Result<()> {
+        let to: Mailbox = message.to
+            .parse()
+            .map_err(|_| Error::InvalidAddress(message.to.clone()))?;
+
+        let email = lettre::Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(&message.subject)
+            .body(message.body.clone())?;
+
+        self.transport.send(&email)?;
+
+        Ok(())
+    }
+}
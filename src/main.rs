@@ -1,33 +1,73 @@
 use std::sync::Arc;
 
 use actix_web::{web::Data, App, HttpServer};
-use app::controllers::services;
+use app::{
+    controllers::{admin, baskets, categories, orders, products, services, well_known},
+    models::product::search::{self, ProductEvent, SearchBackend}
+};
 use repository::db::GetPool;
 use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
 
 mod app;
 mod error;
+mod mailer;
 mod repository;
 
 #[derive(Clone)]
 pub struct AppState {
     db: Arc<Pool<Postgres>>,
-    cache: Arc<redis::Connection>
+    cache: redis::aio::MultiplexedConnection,
+    mailer: Arc<dyn mailer::Mailer>,
+    search: Arc<dyn SearchBackend>,
+    search_events: tokio::sync::mpsc::UnboundedSender<ProductEvent>
+}
+
+impl AppState {
+    // Multiplexed connection is cheap to clone, every clone shares the same socket
+    pub fn cache(&self) -> redis::aio::MultiplexedConnection {
+        self.cache.clone()
+    }
+
+    // Search catches up in the background, see `jobs::spawn_search_sync`
+    pub fn publish(&self, event: ProductEvent) {
+        if let Err(e) = self.search_events.send(event) {
+            eprintln!("Search sync has stopped, dropped {:?}", e.0);
+        }
+    }
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
 
+    let db = Arc::new(repository::db::Database::get_pool().await);
+    app::models::user::load_password_settings();
+    app::models::user::keys::load_key_ring();
+    app::models::user::auth::load_token_config();
+    app::models::user::throttle::load_policy();
+    app::models::user::oauth::load_identity_providers();
+    let search = search::from_env(&db).await;
+
     let app_state = AppState {
-        db: Arc::new(repository::db::Database::get_pool().await),
-        cache: Arc::new(repository::cache::create_connection().await)
+        search_events: app::jobs::spawn_search_sync(db.clone(), search.clone()),
+        search,
+        db,
+        cache: repository::cache::create_connection().await,
+        mailer: mailer::from_env()
     };
 
+    app::jobs::spawn_user_purge(app_state.db.clone());
+
     HttpServer::new(move || {
         App::new()
             .app_data(Data::new(app_state.clone()))
             .service(services())
+            .service(well_known())
+            .service(admin())
+            .service(products())
+            .service(categories())
+            .service(baskets())
+            .service(orders())
     })
     .bind(("127.0.0.1", 8080))?
     .run()
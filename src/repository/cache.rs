@@ -1,6 +1,6 @@
 use dotenv::dotenv;
 
-pub async fn create_connection() -> redis::Connection {
+pub async fn create_connection() -> redis::aio::MultiplexedConnection {
     dotenv().ok();
     
     let client = redis::Client::open(
@@ -8,5 +8,8 @@ pub async fn create_connection() -> redis::Connection {
             .expect("REDIS_URL env must be provided!")
     ).expect("Error opening redis client");
 
-    client.get_connection().expect("Error connection to redis client")
+    client
+        .get_multiplexed_tokio_connection()
+        .await
+        .expect("Error connection to redis client")
 }
@@ -2,7 +2,7 @@ use crate::PgPoolOptions;
 
 use actix_web::http::StatusCode;
 use dotenv::dotenv;
-use sqlx::{ error::{DatabaseError, ErrorKind}, Pool, Postgres};
+use sqlx::{ error::ErrorKind, Pool, Postgres};
 
 pub struct Database;
 
@@ -37,6 +37,16 @@ impl std::fmt::Display for SqlxError {
     }
 }
 
+impl SqlxError {
+    // Name of the unique constraint the query ran into
+    pub fn unique_constraint(&self) -> Option<&str> {
+        match &self.0 {
+            sqlx::Error::Database(error) if matches!(error.kind(), ErrorKind::UniqueViolation) => error.constraint(),
+            _ => None
+        }
+    }
+}
+
 // Into implementations
 
 impl From<sqlx::Error> for SqlxError {
@@ -56,6 +66,7 @@ impl From<SqlxError> for StatusCode {
 
         match sqlx::Error::from(value) {
             sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            sqlx::Error::Database(error) if matches!(error.kind(), ErrorKind::UniqueViolation) => StatusCode::CONFLICT,
             _ => StatusCode::INTERNAL_SERVER_ERROR
         }
 